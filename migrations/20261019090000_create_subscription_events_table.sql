@@ -0,0 +1,25 @@
+-- Append-only audit trail proving consent for every change to a subscription;
+-- no foreign key on `subscriber_id` so that the trail outlives the subscriber
+CREATE TABLE subscription_events(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  subscriber_id uuid NOT NULL,
+  email TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  occurred_at timestamptz NOT NULL,
+  source_ip TEXT NULL,
+  user_agent TEXT NULL,
+  form_source TEXT NULL,
+  consent_text_version TEXT NOT NULL
+);
+CREATE INDEX subscription_events_subscriber_id_idx ON subscription_events (subscriber_id);
+
+CREATE FUNCTION reject_subscription_events_change() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'subscription_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER subscription_events_append_only
+  BEFORE UPDATE OR DELETE ON subscription_events
+  FOR EACH ROW EXECUTE FUNCTION reject_subscription_events_change();
@@ -0,0 +1,2 @@
+-- Tokens issued before this migration are treated as if they had just been created
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,4 @@
+ALTER TABLE subscriptions ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+-- Keyset pagination of the admin listing orders by (subscribed_at, id)
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
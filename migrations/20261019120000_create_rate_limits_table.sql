@@ -0,0 +1,7 @@
+-- Request counters per key and fixed time window, shared by all instances of the app
+CREATE TABLE rate_limits(
+  key TEXT NOT NULL,
+  PRIMARY KEY (key),
+  window_start timestamptz NOT NULL,
+  hits INTEGER NOT NULL
+);
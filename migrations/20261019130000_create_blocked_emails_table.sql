@@ -0,0 +1,7 @@
+-- domains and single addresses admins don't accept subscriptions from
+CREATE TABLE blocked_emails(
+    value TEXT NOT NULL PRIMARY KEY, -- lowercased domain or address
+    kind TEXT NOT NULL CHECK (kind IN ('domain', 'address')),
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,32 @@
+-- canonical form of the email, used to tell whether two emails belong to the same subscriber;
+-- the app also converts internationalized domains to punycode and can fold provider aliases,
+-- for existing rows lowercasing is as close as we can get in SQL
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT;
+UPDATE subscriptions SET canonical_email = lower(trim(email));
+
+-- existing subscribers that only differ in case can't be told apart anymore; we don't know which
+-- of them to keep, so we report them and abort, rather than silently merging subscribers
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(canonical_email || ': ' || subscribers, E'\n' ORDER BY canonical_email)
+    INTO collisions
+    FROM (
+        SELECT canonical_email, string_agg(email || ' (' || id || ', ' || status || ')', ', '
+            ORDER BY subscribed_at) AS subscribers
+        FROM subscriptions
+        GROUP BY canonical_email
+        HAVING count(*) > 1
+    ) AS duplicates;
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'Subscribers with the same canonical email, merge or delete them before migrating:%',
+            E'\n' || collisions;
+    END IF;
+END $$;
+
+ALTER TABLE subscriptions ALTER COLUMN canonical_email SET NOT NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_canonical_email_key UNIQUE (canonical_email);
+-- the canonical email identifies subscribers now, different aliases may share a raw email once
+-- folding rules change
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
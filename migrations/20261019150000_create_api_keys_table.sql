@@ -0,0 +1,12 @@
+-- keys machine clients authenticate with; only a hash of the key is stored
+CREATE TABLE api_keys(
+    id UUID NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- first characters of the key, to tell keys apart without storing them
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
@@ -0,0 +1,34 @@
+-- Endpoints registered by admins to be notified about subscriber lifecycle events
+CREATE TABLE webhook_endpoints(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  url TEXT NOT NULL,
+  -- needed in plain text to sign the payloads we send
+  secret TEXT NOT NULL,
+  event_types TEXT[] NOT NULL,
+  created_at timestamptz NOT NULL,
+  -- endpoints are disabled rather than deleted, so that their delivery log is kept
+  disabled_at timestamptz NULL
+);
+
+-- Outbox of events to send, filled in the transaction recording the event, and log of their
+-- delivery
+CREATE TABLE webhook_deliveries(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  endpoint_id uuid NOT NULL REFERENCES webhook_endpoints (id),
+  event_id uuid NOT NULL,
+  event_type TEXT NOT NULL,
+  -- the exact body we sign and send, so that retries are byte for byte the same
+  payload TEXT NOT NULL,
+  status TEXT NOT NULL,
+  attempts INT NOT NULL,
+  next_attempt_at timestamptz NOT NULL,
+  last_attempt_at timestamptz NULL,
+  last_response_status INT NULL,
+  last_error TEXT NULL,
+  created_at timestamptz NOT NULL
+);
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at)
+  WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_endpoint_id_idx ON webhook_deliveries (endpoint_id, created_at);
@@ -0,0 +1,56 @@
+-- People administering the newsletter, logging in with a password
+CREATE TABLE admin_users(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  -- stored lowercased
+  email TEXT NOT NULL UNIQUE,
+  -- null until the user has accepted their invite
+  password_hash TEXT NULL,
+  role TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+
+-- Single-use tokens sent to admin users by email, e.g. invites
+CREATE TABLE admin_user_tokens(
+  token_hash TEXT NOT NULL,
+  PRIMARY KEY (token_hash),
+  user_id uuid NOT NULL REFERENCES admin_users (id) ON DELETE CASCADE,
+  purpose TEXT NOT NULL,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL,
+  used_at timestamptz NULL
+);
+CREATE INDEX admin_user_tokens_user_id_idx ON admin_user_tokens (user_id);
+
+CREATE TABLE admin_sessions(
+  token_hash TEXT NOT NULL,
+  PRIMARY KEY (token_hash),
+  user_id uuid NOT NULL REFERENCES admin_users (id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL
+);
+CREATE INDEX admin_sessions_user_id_idx ON admin_sessions (user_id);
+
+-- Append-only record of privileged actions of admin users and API keys
+CREATE TABLE audit_log(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  actor_type TEXT NOT NULL,
+  -- null for actions taken from the command line
+  actor_id uuid NULL,
+  action TEXT NOT NULL,
+  target TEXT NULL,
+  details TEXT NULL,
+  occurred_at timestamptz NOT NULL
+);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+
+CREATE FUNCTION reject_audit_log_change() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+  BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION reject_audit_log_change();
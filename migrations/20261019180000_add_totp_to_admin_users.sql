@@ -0,0 +1,21 @@
+-- Optional second factor of admin users; the secret is set when enrollment starts and only
+-- required at login once the user confirmed it with a code
+ALTER TABLE admin_users
+  ADD COLUMN totp_secret TEXT NULL,
+  ADD COLUMN totp_enabled_at timestamptz NULL,
+  -- time step of the last accepted code, so that codes can't be used twice
+  ADD COLUMN totp_last_step BIGINT NULL;
+
+-- Single-use codes to log in without the authenticator app
+CREATE TABLE admin_recovery_codes(
+  code_hash TEXT NOT NULL,
+  PRIMARY KEY (code_hash),
+  user_id uuid NOT NULL REFERENCES admin_users (id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  used_at timestamptz NULL
+);
+CREATE INDEX admin_recovery_codes_user_id_idx ON admin_recovery_codes (user_id);
+
+-- Failed attempts to use a token, e.g. wrong codes entered for a login challenge
+ALTER TABLE admin_user_tokens
+  ADD COLUMN failed_attempts INT NOT NULL DEFAULT 0;
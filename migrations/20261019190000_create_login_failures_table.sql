@@ -0,0 +1,17 @@
+-- Failed password logins per email and per client IP; a key is locked out for a while after too
+-- many failures within the window, longer with every consecutive lockout
+CREATE TABLE login_failures(
+  id uuid NOT NULL,
+  PRIMARY KEY (id),
+  -- `email` or `ip`
+  kind TEXT NOT NULL,
+  key TEXT NOT NULL,
+  UNIQUE (kind, key),
+  -- failures since the window started, reset when the key is locked out
+  failed_attempts INT NOT NULL,
+  window_started_at timestamptz NOT NULL,
+  last_failed_at timestamptz NOT NULL,
+  -- lockouts in a row, reset by a successful login, an admin, or after a day without failures
+  lockouts INT NOT NULL DEFAULT 0,
+  locked_until timestamptz NULL
+);
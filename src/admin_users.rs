@@ -0,0 +1,438 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::auth::{generate_token, hash_token, Role};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+
+const USER_TOKEN_LENGTH: usize = 40;
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct AdminUser {
+    pub id: Uuid,
+    pub email: String,
+    pub role: Role,
+    /// whether the user has yet to accept their invite and set a password
+    pub invite_pending: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+struct AdminUserRow {
+    id: Uuid,
+    email: String,
+    role: String,
+    invite_pending: Option<bool>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<AdminUserRow> for AdminUser {
+    type Error = sqlx::Error;
+
+    fn try_from(row: AdminUserRow) -> Result<Self, Self::Error> {
+        let role = Role::parse(&row.role)
+            .ok_or_else(|| sqlx::Error::Decode(format!("unknown role: {}", row.role).into()))?;
+        Ok(Self {
+            id: row.id,
+            email: row.email,
+            role,
+            invite_pending: row.invite_pending.unwrap_or(true),
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum AdminUserError {
+    AlreadyExists,
+    /// the change would leave nobody who can manage users
+    LastOwner,
+    Unexpected(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AdminUserError {
+    fn from(e: sqlx::Error) -> Self {
+        AdminUserError::Unexpected(e)
+    }
+}
+
+/// What a token sent to an admin user by email lets them do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    Invite,
+    /// second step of a login, after the password was checked
+    LoginChallenge,
+    MagicLink,
+    PasswordReset,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Invite => "invite",
+            TokenPurpose::LoginChallenge => "login_challenge",
+            TokenPurpose::MagicLink => "magic_link",
+            TokenPurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// Email addresses of admin users are compared case-insensitively
+pub fn normalize_admin_email(email: &str) -> Result<String, String> {
+    SubscriberEmail::parse(email.to_string()).map(|email| email.as_ref().to_lowercase())
+}
+
+#[tracing::instrument(name = "List admin users", skip(db_pool))]
+pub async fn list_admin_users(db_pool: &PgPool) -> Result<Vec<AdminUser>, sqlx::Error> {
+    sqlx::query_as!(
+        AdminUserRow,
+        r#"
+        SELECT id, email, role, password_hash IS NULL AS invite_pending, created_at
+        FROM admin_users
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list admin users: {:?}", e);
+        e
+    })?
+    .into_iter()
+    .map(AdminUser::try_from)
+    .collect()
+}
+
+/// Id and password hash of the user with the email, if any; the hash is missing until the
+/// invite is accepted
+pub async fn find_credentials(
+    db_pool: &PgPool,
+    email: &str,
+) -> Result<Option<(Uuid, Option<String>)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id, password_hash FROM admin_users WHERE email = $1"#,
+        email.to_lowercase(),
+    )
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to look up admin user: {:?}", e);
+        e
+    })?;
+    Ok(row.map(|row| (row.id, row.password_hash)))
+}
+
+pub async fn get_admin_user(
+    db: impl PgExecutor<'_>,
+    id: Uuid,
+) -> Result<Option<AdminUser>, sqlx::Error> {
+    sqlx::query_as!(
+        AdminUserRow,
+        r#"
+        SELECT id, email, role, password_hash IS NULL AS invite_pending, created_at
+        FROM admin_users
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_optional(db)
+    .await?
+    .map(AdminUser::try_from)
+    .transpose()
+}
+
+/// Create a user without password, together with a token to accept the invite and set one
+#[tracing::instrument(name = "Invite admin user", skip(transaction, expiry))]
+pub async fn invite_admin_user(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    role: Role,
+    expiry: TimeDelta,
+) -> Result<(AdminUser, String), AdminUserError> {
+    let row = sqlx::query_as!(
+        AdminUserRow,
+        r#"
+        INSERT INTO admin_users (id, email, role, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id, email, role, password_hash IS NULL AS invite_pending, created_at
+        "#,
+        Uuid::new_v4(),
+        email,
+        role.as_str(),
+        Utc::now(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(AdminUserError::AlreadyExists)?;
+    let user = AdminUser::try_from(row)?;
+    let token = create_user_token(transaction, user.id, TokenPurpose::Invite, expiry).await?;
+    Ok((user, token))
+}
+
+/// Change the role of a user, refusing to demote the last owner
+#[tracing::instrument(name = "Change admin user role", skip(transaction))]
+pub async fn change_role(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    role: Role,
+) -> Result<Option<AdminUser>, AdminUserError> {
+    // lock the owners, so that two owners can't concurrently demote each other
+    let owners = sqlx::query!(
+        r#"SELECT id FROM admin_users WHERE role = $1 FOR UPDATE"#,
+        Role::Owner.as_str(),
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    if role != Role::Owner && owners.len() == 1 && owners[0].id == id {
+        return Err(AdminUserError::LastOwner);
+    }
+    let row = sqlx::query_as!(
+        AdminUserRow,
+        r#"
+        UPDATE admin_users SET role = $2 WHERE id = $1
+        RETURNING id, email, role, password_hash IS NULL AS invite_pending, created_at
+        "#,
+        id,
+        role.as_str(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(AdminUser::try_from).transpose()?)
+}
+
+pub async fn set_password(
+    db: impl PgExecutor<'_>,
+    user_id: Uuid,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE admin_users SET password_hash = $2 WHERE id = $1"#,
+        user_id,
+        password_hash,
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Store a new single-use token for the user; returns the token to send them
+pub async fn create_user_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    purpose: TokenPurpose,
+    expiry: TimeDelta,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token(USER_TOKEN_LENGTH);
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_user_tokens (token_hash, user_id, purpose, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        hash_token(&token),
+        user_id,
+        purpose.as_str(),
+        now,
+        now + expiry,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(token)
+}
+
+/// Mark an unused, unexpired token as used; returns the user it was issued to
+#[tracing::instrument(name = "Use admin user token", skip(transaction, token))]
+pub async fn use_user_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let now = Utc::now();
+    let row = sqlx::query!(
+        r#"
+        UPDATE admin_user_tokens SET used_at = $3
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > $3
+        RETURNING user_id
+        "#,
+        hash_token(token),
+        purpose.as_str(),
+        now,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|row| row.user_id))
+}
+
+/// Invalidate all unused tokens of the user, e.g. once their password changed
+pub async fn invalidate_user_tokens(
+    db: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE admin_user_tokens SET used_at = $2 WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id,
+        Utc::now(),
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Count a failed attempt to use a token, invalidating it after `max_attempts`
+#[tracing::instrument(name = "Count failed token attempt", skip(db, token))]
+pub async fn fail_user_token(
+    db: impl PgExecutor<'_>,
+    token: &str,
+    purpose: TokenPurpose,
+    max_attempts: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE admin_user_tokens SET
+            failed_attempts = failed_attempts + 1,
+            used_at = CASE WHEN failed_attempts + 1 >= $3 THEN $4 ELSE used_at END
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+        hash_token(token),
+        purpose.as_str(),
+        max_attempts,
+        Utc::now(),
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+pub fn invite_link(base_url: &str, token: &str) -> String {
+    format!("{}/login/invite?token={}", base_url, token)
+}
+
+pub fn magic_link(base_url: &str, token: &str) -> String {
+    format!("{}/login/magic/confirm?token={}", base_url, token)
+}
+
+pub fn password_reset_link(base_url: &str, token: &str) -> String {
+    format!("{}/login/reset?token={}", base_url, token)
+}
+
+pub fn forgot_password_link(base_url: &str) -> String {
+    format!("{}/login/forgot", base_url)
+}
+
+#[tracing::instrument(name = "Send invite email", skip(email_client, link))]
+pub async fn send_invite_email(
+    email_client: &EmailClient,
+    email: &str,
+    role: Role,
+    link: &str,
+) -> Result<(), String> {
+    let email = SubscriberEmail::parse(email.to_string())?;
+    let html_body = format!(
+        "You have been invited to administer our newsletter as {}.<br />\
+        Click <a href=\"{}\">here</a> to choose a password.",
+        role.as_str(),
+        link
+    );
+    let text_body = format!(
+        "You have been invited to administer our newsletter as {}.\n\
+        Visit {} to choose a password.",
+        role.as_str(),
+        link
+    );
+    email_client
+        .send_email(email, "You're invited", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send invite email: {:?}", e);
+            e.to_string()
+        })
+}
+
+#[tracing::instrument(name = "Send magic link email", skip(email_client, link))]
+pub async fn send_magic_link_email(
+    email_client: &EmailClient,
+    email: &str,
+    link: &str,
+    expiry: TimeDelta,
+) -> Result<(), String> {
+    let email = SubscriberEmail::parse(email.to_string())?;
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to log in to the newsletter admin.<br />\
+        The link works once and expires in {} minutes. If you didn't ask for it, ignore this email.",
+        link,
+        expiry.num_minutes()
+    );
+    let text_body = format!(
+        "Visit {} to log in to the newsletter admin.\n\
+        The link works once and expires in {} minutes. If you didn't ask for it, ignore this email.",
+        link,
+        expiry.num_minutes()
+    );
+    email_client
+        .send_email(email, "Your login link", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send magic link email: {:?}", e);
+            e.to_string()
+        })
+}
+
+#[tracing::instrument(name = "Send password reset email", skip(email_client, link))]
+pub async fn send_password_reset_email(
+    email_client: &EmailClient,
+    email: &str,
+    link: &str,
+    expiry: TimeDelta,
+) -> Result<(), String> {
+    let email = SubscriberEmail::parse(email.to_string())?;
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to choose a new password for the newsletter admin.<br />\
+        The link works once and expires in {} minutes. If you didn't ask for it, ignore this email.",
+        link,
+        expiry.num_minutes()
+    );
+    let text_body = format!(
+        "Visit {} to choose a new password for the newsletter admin.\n\
+        The link works once and expires in {} minutes. If you didn't ask for it, ignore this email.",
+        link,
+        expiry.num_minutes()
+    );
+    email_client
+        .send_email(email, "Reset your password", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send password reset email: {:?}", e);
+            e.to_string()
+        })
+}
+
+/// Tell a user that their email is locked out after too many failed logins
+pub async fn send_lockout_email(
+    email_client: &EmailClient,
+    email: &str,
+    locked_until: DateTime<Utc>,
+    forgot_password_link: &str,
+) -> Result<(), String> {
+    let email = SubscriberEmail::parse(email.to_string())?;
+    let until = locked_until.format("%Y-%m-%d %H:%M UTC");
+    let html_body = format!(
+        "There were too many failed attempts to log in to your newsletter admin account, so \
+        logins are blocked until {}.<br />If it wasn't you, someone may be guessing your \
+        password; you can choose a new one <a href=\"{}\">here</a>.",
+        until, forgot_password_link
+    );
+    let text_body = format!(
+        "There were too many failed attempts to log in to your newsletter admin account, so \
+        logins are blocked until {}.\nIf it wasn't you, someone may be guessing your password; \
+        you can choose a new one at {}.",
+        until, forgot_password_link
+    );
+    email_client
+        .send_email(email, "Your account was locked", &html_body, &text_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send lockout email: {:?}", e);
+            e.to_string()
+        })
+}
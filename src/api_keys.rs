@@ -0,0 +1,198 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::auth::{generate_token, hash_token, Principal, Scope};
+
+/// Keys start with this, so that they are easy to recognize, e.g. by secret scanners
+const KEY_PREFIX: &str = "z2p_";
+const KEY_SECRET_LENGTH: usize = 40;
+/// Number of characters of the key we store and show to tell keys apart
+const DISPLAYED_PREFIX_LENGTH: usize = KEY_PREFIX.len() + 8;
+/// We only record when a key was last used to this precision, to avoid a write per request
+const LAST_USED_RESOLUTION: TimeDelta = TimeDelta::minutes(1);
+
+/// Scopes stored in the database, skipping any we don't know anymore
+fn parse_scopes(scopes: Vec<String>) -> Vec<Scope> {
+    scopes
+        .iter()
+        .filter_map(|s| {
+            let scope = Scope::parse(s);
+            if scope.is_none() {
+                tracing::warn!("Ignoring unknown API key scope: {}", s);
+            }
+            scope
+        })
+        .collect()
+}
+
+/// API key without its secret, as shown to admins
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+struct ApiKeyRow {
+    id: Uuid,
+    name: String,
+    key_prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            key_prefix: row.key_prefix,
+            scopes: parse_scopes(row.scopes),
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+fn generate_key() -> String {
+    format!("{}{}", KEY_PREFIX, generate_token(KEY_SECRET_LENGTH))
+}
+
+/// Create a key with the given scopes; returns the key, which can't be retrieved later
+#[tracing::instrument(name = "Create API key", skip(db))]
+pub async fn create_api_key(
+    db: impl PgExecutor<'_>,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<(ApiKey, String), sqlx::Error> {
+    let key = generate_key();
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.as_str().to_string())
+        .collect::<Vec<_>>();
+    let row = sqlx::query_as!(
+        ApiKeyRow,
+        r#"
+        INSERT INTO api_keys (id, name, key_prefix, key_hash, scopes, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, key_prefix, scopes, created_at, last_used_at, revoked_at
+        "#,
+        Uuid::new_v4(),
+        name,
+        &key[..DISPLAYED_PREFIX_LENGTH],
+        hash_token(&key),
+        &scopes,
+        Utc::now(),
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create API key: {:?}", e);
+        e
+    })?;
+    Ok((row.into(), key))
+}
+
+#[tracing::instrument(name = "List API keys", skip(db_pool))]
+pub async fn list_api_keys(db_pool: &PgPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        ApiKeyRow,
+        r#"
+        SELECT id, name, key_prefix, scopes, created_at, last_used_at, revoked_at
+        FROM api_keys
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list API keys: {:?}", e);
+        e
+    })?;
+    Ok(rows.into_iter().map(ApiKey::from).collect())
+}
+
+/// Revoke a key, returns whether there was an active key with the id
+#[tracing::instrument(name = "Revoke API key", skip(db))]
+pub async fn revoke_api_key(db: impl PgExecutor<'_>, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE api_keys SET revoked_at = $2 WHERE id = $1 AND revoked_at IS NULL"#,
+        id,
+        Utc::now(),
+    )
+    .execute(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to revoke API key: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up an active key, recording that it has been used
+pub async fn find_api_key(db_pool: &PgPool, key: &str) -> Result<Option<Principal>, sqlx::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        SELECT id, scopes, last_used_at FROM api_keys
+        WHERE key_hash = $1 AND revoked_at IS NULL
+        "#,
+        hash_token(key),
+    )
+    .fetch_optional(db_pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    if row
+        .last_used_at
+        .is_none_or(|last_used_at| last_used_at + LAST_USED_RESOLUTION < now)
+    {
+        sqlx::query!(
+            r#"UPDATE api_keys SET last_used_at = $2 WHERE id = $1"#,
+            row.id,
+            now,
+        )
+        .execute(db_pool)
+        .await?;
+    }
+    Ok(Some(Principal::ApiKey {
+        id: row.id,
+        scopes: parse_scopes(row.scopes),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_key, parse_scopes, KEY_PREFIX};
+    use crate::auth::{hash_token, Scope};
+
+    #[test]
+    fn keys_are_prefixed_and_unique() {
+        let (a, b) = (generate_key(), generate_key());
+        assert!(a.starts_with(KEY_PREFIX));
+        assert_ne!(a, b);
+        assert_ne!(hash_token(&a), hash_token(&b));
+    }
+
+    #[test]
+    fn scopes_round_trip_and_unknown_ones_are_skipped() {
+        let scopes = vec![
+            Scope::SubscribersRead.as_str().to_string(),
+            "newsletters:unknown".to_string(),
+            Scope::ApiKeysManage.as_str().to_string(),
+        ];
+        assert_eq!(
+            parse_scopes(scopes),
+            vec![Scope::SubscribersRead, Scope::ApiKeysManage]
+        );
+    }
+}
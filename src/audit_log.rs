@@ -0,0 +1,152 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::auth::Principal;
+
+/// Number of entries returned when no limit is given
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Privileged action recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    UserInvited,
+    UserRoleChanged,
+    UserTotpEnabled,
+    UserTotpDisabled,
+    UserRecoveryCodesReplaced,
+    LockoutCleared,
+    ApiKeyCreated,
+    ApiKeyRevoked,
+    WebhookRegistered,
+    WebhookDisabled,
+    SubscriberStatusChanged,
+    SubscriberDeleted,
+    SubscribersImported,
+    SubscribersExported,
+    BlocklistEntryAdded,
+    BlocklistEntryRemoved,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::UserInvited => "user.invited",
+            AuditAction::UserRoleChanged => "user.role_changed",
+            AuditAction::UserTotpEnabled => "user.totp_enabled",
+            AuditAction::UserTotpDisabled => "user.totp_disabled",
+            AuditAction::UserRecoveryCodesReplaced => "user.recovery_codes_replaced",
+            AuditAction::LockoutCleared => "lockout.cleared",
+            AuditAction::ApiKeyCreated => "api_key.created",
+            AuditAction::ApiKeyRevoked => "api_key.revoked",
+            AuditAction::WebhookRegistered => "webhook.registered",
+            AuditAction::WebhookDisabled => "webhook.disabled",
+            AuditAction::SubscriberStatusChanged => "subscriber.status_changed",
+            AuditAction::SubscriberDeleted => "subscriber.deleted",
+            AuditAction::SubscribersImported => "subscribers.imported",
+            AuditAction::SubscribersExported => "subscribers.exported",
+            AuditAction::BlocklistEntryAdded => "blocklist.entry_added",
+            AuditAction::BlocklistEntryRemoved => "blocklist.entry_removed",
+        }
+    }
+}
+
+/// Who took an action; actions from the command line have no principal
+#[derive(Debug, Clone, Copy)]
+pub enum Actor<'a> {
+    Principal(&'a Principal),
+    CommandLine,
+}
+
+impl Actor<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Actor::Principal(principal) => principal.kind(),
+            Actor::CommandLine => "command_line",
+        }
+    }
+
+    fn id(&self) -> Option<Uuid> {
+        match self {
+            Actor::Principal(principal) => Some(principal.id()),
+            Actor::CommandLine => None,
+        }
+    }
+}
+
+impl<'a> From<&'a Principal> for Actor<'a> {
+    fn from(principal: &'a Principal) -> Self {
+        Actor::Principal(principal)
+    }
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    /// `user`, `api_key` or `command_line`
+    pub actor_type: String,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    /// id of whatever the action was taken on
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Append an entry to the audit log; pass the transaction taking the action, so that the entry
+/// is only persisted together with it
+#[tracing::instrument(name = "Record audit log entry", skip(db, actor, target), fields(action = action.as_str()))]
+pub async fn record_audit_event<'a>(
+    db: impl PgExecutor<'_>,
+    actor: impl Into<Actor<'a>>,
+    action: AuditAction,
+    target: impl ToString,
+    details: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let actor = actor.into();
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (id, actor_type, actor_id, action, target, details, occurred_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        actor.kind(),
+        actor.id(),
+        action.as_str(),
+        target.to_string(),
+        details,
+        Utc::now(),
+    )
+    .execute(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to record audit log entry: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Latest entries, newest first
+#[tracing::instrument(name = "List audit log", skip(db_pool))]
+pub async fn list_audit_log(
+    db_pool: &PgPool,
+    limit: Option<i64>,
+) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT id, actor_type, actor_id, action, target, details, occurred_at
+        FROM audit_log
+        ORDER BY occurred_at DESC
+        LIMIT $1
+        "#,
+        limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list audit log: {:?}", e);
+        e
+    })
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::config::AuthConfig;
+
+const EMAIL: &str = "email";
+const IP: &str = "ip";
+
+/// Failed logins counted for an email or a client IP, as shown to admins
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct LoginFailures {
+    pub id: Uuid,
+    /// `email` or `ip`
+    pub kind: String,
+    /// the email or the IP address
+    pub key: String,
+    /// failures in the current window
+    pub failed_attempts: i32,
+    pub last_failed_at: DateTime<Utc>,
+    /// lockouts in a row
+    pub lockouts: i32,
+    /// logins are refused until then
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// Duration of the given lockout in a row, doubling from the first one up to the maximum
+fn lockout_duration(first: TimeDelta, max: TimeDelta, lockouts: i32) -> TimeDelta {
+    let doublings = lockouts.saturating_sub(1).clamp(0, 30) as u32;
+    first
+        .checked_mul(2_i32.pow(doublings))
+        .map_or(max, |duration| duration.min(max))
+}
+
+/// Time until which logins for the email or from the IP are refused, if either is locked out
+pub async fn locked_until(
+    db: impl PgExecutor<'_>,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT MAX(locked_until) FROM login_failures
+        WHERE ((kind = $1 AND key = $2) OR (kind = $3 AND key = $4)) AND locked_until > $5
+        "#,
+        EMAIL,
+        email,
+        IP,
+        ip.map(|ip| ip.to_string()),
+        Utc::now(),
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to look up lockouts: {:?}", e);
+        e
+    })
+}
+
+/// Count a failure for the key, locking it out once it reached the maximum within the window;
+/// returns the end of the lockout if this failure started one
+async fn count_failure(
+    transaction: &mut Transaction<'_, Postgres>,
+    kind: &str,
+    key: &str,
+    max_failures: u32,
+    config: &AuthConfig,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let now = Utc::now();
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO login_failures
+            (id, kind, key, failed_attempts, window_started_at, last_failed_at)
+        VALUES ($1, $2, $3, 1, $4, $4)
+        ON CONFLICT (kind, key) DO UPDATE SET
+            failed_attempts = CASE WHEN login_failures.window_started_at <= $5
+                THEN 1 ELSE login_failures.failed_attempts + 1 END,
+            window_started_at = CASE WHEN login_failures.window_started_at <= $5
+                THEN $4 ELSE login_failures.window_started_at END,
+            last_failed_at = $4
+        RETURNING id, failed_attempts, lockouts
+        "#,
+        Uuid::new_v4(),
+        kind,
+        key,
+        now,
+        now - config.failed_login_window(),
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    if max_failures == 0 || (row.failed_attempts as u32) < max_failures {
+        return Ok(None);
+    }
+    let lockouts = row.lockouts + 1;
+    let locked_until = now + lockout_duration(config.lockout(), config.max_lockout(), lockouts);
+    sqlx::query!(
+        r#"
+        UPDATE login_failures
+        SET failed_attempts = 0, window_started_at = $2, lockouts = $3, locked_until = $4
+        WHERE id = $1
+        "#,
+        row.id,
+        now,
+        lockouts,
+        locked_until,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    tracing::warn!("Locked out {} until {}", kind, locked_until);
+    Ok(Some(locked_until))
+}
+
+/// Count a failed login for the email and the IP; returns the end of the lockout if this failure
+/// locked out the email, so that its owner can be told
+#[tracing::instrument(name = "Record failed login", skip(db_pool, config))]
+pub async fn record_failed_login(
+    db_pool: &PgPool,
+    email: &str,
+    ip: Option<IpAddr>,
+    config: &AuthConfig,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let locked_until = count_failure(
+            &mut transaction,
+            EMAIL,
+            email,
+            config.max_failed_logins_per_email,
+            config,
+        )
+        .await?;
+        if let Some(ip) = ip {
+            count_failure(
+                &mut transaction,
+                IP,
+                &ip.to_string(),
+                config.max_failed_logins_per_ip,
+                config,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(locked_until)
+    }
+    .await;
+    result.map_err(|e| {
+        tracing::error!("Failed to record failed login: {:?}", e);
+        e
+    })
+}
+
+/// Forget the failures and lockouts of an email after its owner proved who they are; failures
+/// of IPs are kept, an attacker could otherwise reset them with an account of their own
+pub async fn clear_failed_logins(db: impl PgExecutor<'_>, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM login_failures WHERE kind = $1 AND key = $2"#,
+        EMAIL,
+        email,
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Current lockouts, or all keys with failed logins, most recent failure first
+#[tracing::instrument(name = "List login failures", skip(db_pool))]
+pub async fn list_login_failures(
+    db_pool: &PgPool,
+    locked_only: bool,
+) -> Result<Vec<LoginFailures>, sqlx::Error> {
+    sqlx::query_as!(
+        LoginFailures,
+        r#"
+        SELECT id, kind, key, failed_attempts, last_failed_at, lockouts, locked_until
+        FROM login_failures
+        WHERE NOT $1 OR locked_until > $2
+        ORDER BY last_failed_at DESC
+        "#,
+        locked_only,
+        Utc::now(),
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list login failures: {:?}", e);
+        e
+    })
+}
+
+/// Lift a lockout and forget its failures; returns the deleted entry, if it existed
+pub async fn delete_login_failures(
+    db: impl PgExecutor<'_>,
+    id: Uuid,
+) -> Result<Option<LoginFailures>, sqlx::Error> {
+    sqlx::query_as!(
+        LoginFailures,
+        r#"
+        DELETE FROM login_failures WHERE id = $1
+        RETURNING id, kind, key, failed_attempts, last_failed_at, lockouts, locked_until
+        "#,
+        id,
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Delete entries without a failure since the cutoff and no running lockout; the next lockout
+/// of their key starts over at the shortest duration
+pub async fn purge_login_failures(
+    db_pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM login_failures
+        WHERE last_failed_at < $1 AND (locked_until IS NULL OR locked_until <= $2)
+        "#,
+        cutoff,
+        Utc::now(),
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lockout_duration;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let first = TimeDelta::minutes(5);
+        let max = TimeDelta::minutes(60);
+        let durations = (1..=6)
+            .map(|lockouts| lockout_duration(first, max, lockouts).num_minutes())
+            .collect::<Vec<_>>();
+        assert_eq!(durations, vec![5, 10, 20, 40, 60, 60]);
+        assert_eq!(lockout_duration(first, max, i32::MAX), max);
+    }
+}
@@ -0,0 +1,360 @@
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api_keys::find_api_key;
+use crate::config::AuthConfig;
+use crate::csrf::has_csrf_header;
+
+mod lockout;
+mod password;
+mod sessions;
+mod totp;
+
+pub use lockout::*;
+pub use password::*;
+pub use sessions::*;
+pub use totp::*;
+
+/// Permission to use a group of admin routes, granted to API keys directly and to admin users
+/// through their [`Role`]
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    utoipa::ToSchema,
+)]
+pub enum Scope {
+    #[serde(rename = "subscribers:read")]
+    #[value(name = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    #[value(name = "subscribers:write")]
+    SubscribersWrite,
+    #[serde(rename = "blocklist:manage")]
+    #[value(name = "blocklist:manage")]
+    BlocklistManage,
+    #[serde(rename = "api_keys:manage")]
+    #[value(name = "api_keys:manage")]
+    ApiKeysManage,
+    #[serde(rename = "webhooks:manage")]
+    #[value(name = "webhooks:manage")]
+    WebhooksManage,
+    #[serde(rename = "users:manage")]
+    #[value(name = "users:manage")]
+    UsersManage,
+    #[serde(rename = "audit_log:read")]
+    #[value(name = "audit_log:read")]
+    AuditLogRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Scope::SubscribersRead,
+        Scope::SubscribersWrite,
+        Scope::BlocklistManage,
+        Scope::ApiKeysManage,
+        Scope::WebhooksManage,
+        Scope::UsersManage,
+        Scope::AuditLogRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+            Scope::BlocklistManage => "blocklist:manage",
+            Scope::ApiKeysManage => "api_keys:manage",
+            Scope::WebhooksManage => "webhooks:manage",
+            Scope::UsersManage => "users:manage",
+            Scope::AuditLogRead => "audit_log:read",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Scope> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == s)
+    }
+}
+
+/// Role of an admin user, each including the permissions of the ones before it
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// reads subscribers
+    Viewer,
+    /// manages subscribers and the blocklist
+    Editor,
+    /// like editors, will also publish newsletters once the app sends them
+    Publisher,
+    /// manages everything, including users, API keys and webhooks
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Publisher => "publisher",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        [Role::Viewer, Role::Editor, Role::Publisher, Role::Owner]
+            .into_iter()
+            .find(|role| role.as_str() == s)
+    }
+
+    pub fn scopes(&self) -> &'static [Scope] {
+        match self {
+            Role::Viewer => &[Scope::SubscribersRead],
+            Role::Editor | Role::Publisher => &[
+                Scope::SubscribersRead,
+                Scope::SubscribersWrite,
+                Scope::BlocklistManage,
+            ],
+            Role::Owner => &Scope::ALL,
+        }
+    }
+}
+
+/// Who sent an admin request, stored in the request extensions by [`authenticate`]; use it as
+/// extractor in handlers that need to know
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    ApiKey {
+        id: Uuid,
+        scopes: Vec<Scope>,
+    },
+    User {
+        id: Uuid,
+        role: Role,
+        /// the role requires two-factor authentication, which the user hasn't set up yet; until
+        /// they do, they have no scopes
+        needs_two_factor: bool,
+    },
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self {
+            Principal::ApiKey { scopes, .. } => scopes.contains(&scope),
+            Principal::User {
+                role,
+                needs_two_factor,
+                ..
+            } => !needs_two_factor && role.scopes().contains(&scope),
+        }
+    }
+
+    /// Whether an owner is acting in person; deciding who can administrate is left to them,
+    /// whatever scopes an API key was granted
+    pub fn is_owner(&self) -> bool {
+        matches!(
+            self,
+            Principal::User {
+                role: Role::Owner,
+                needs_two_factor: false,
+                ..
+            }
+        )
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Principal::ApiKey { .. } => "api_key",
+            Principal::User { .. } => "user",
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        match self {
+            Principal::ApiKey { id, .. } | Principal::User { id, .. } => *id,
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let principal = request.extensions().get::<Principal>().cloned();
+        ready(principal.ok_or_else(|| actix_web::error::ErrorUnauthorized("Not authenticated")))
+    }
+}
+
+/// Random token of letters and digits, for secrets we send out and only store hashed
+pub fn generate_token(length: usize) -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(length)
+        .collect()
+}
+
+/// Tokens are random and long, so a fast hash is enough to keep them safe at rest
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn bearer_token(request: &ServiceRequest) -> Option<&str> {
+    request
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+async fn find_principal(
+    db_pool: &PgPool,
+    config: &AuthConfig,
+    request: &ServiceRequest,
+) -> Result<Option<Principal>, sqlx::Error> {
+    if let Some(key) = bearer_token(request) {
+        return find_api_key(db_pool, key).await;
+    }
+    match request.cookie(SESSION_COOKIE) {
+        Some(cookie) => find_session_user(db_pool, cookie.value(), config).await,
+        None => Ok(None),
+    }
+}
+
+/// Middleware rejecting requests with 401, unless they carry a valid API key as
+/// `Authorization: Bearer <key>` header or the session cookie of an admin user; requests with a
+/// session cookie that change state must also send the token of the CSRF cookie in the
+/// [`CSRF_HEADER`], else they're rejected with 403, as browsers send the cookie along with
+/// requests forged by other sites
+///
+/// [`CSRF_HEADER`]: crate::csrf::CSRF_HEADER
+pub async fn authenticate(
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    match find_principal(&db_pool, &config, &request).await {
+        Ok(Some(Principal::User { .. })) if !has_csrf_header(&request) => {
+            tracing::warn!("Rejected session request without valid CSRF token");
+            let response = HttpResponse::Forbidden().body("Invalid CSRF token");
+            Ok(request.into_response(response).map_into_right_body())
+        }
+        Ok(Some(principal)) => {
+            request.extensions_mut().insert(principal);
+            Ok(next.call(request).await?.map_into_left_body())
+        }
+        Ok(None) => {
+            let response = HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .finish();
+            Ok(request.into_response(response).map_into_right_body())
+        }
+        Err(e) => {
+            tracing::error!("Failed to authenticate request: {:?}", e);
+            let response = HttpResponse::InternalServerError().finish();
+            Ok(request.into_response(response).map_into_right_body())
+        }
+    }
+}
+
+type ScopeMiddlewareFuture =
+    LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<BoxBody>>, actix_web::Error>>;
+
+/// Middleware rejecting requests whose [`Principal`] lacks `scope` with 403; must run after
+/// [`authenticate`]
+pub fn require_scope(
+    scope: Scope,
+) -> impl Fn(ServiceRequest, Next<BoxBody>) -> ScopeMiddlewareFuture {
+    move |request, next| {
+        Box::pin(async move {
+            let rejection = match request.extensions().get::<Principal>() {
+                Some(principal) if principal.has_scope(scope) => None,
+                Some(Principal::User {
+                    needs_two_factor: true,
+                    ..
+                }) => Some("Set up two-factor authentication first".to_string()),
+                _ => Some(format!("Missing scope: {}", scope.as_str())),
+            };
+            if let Some(rejection) = rejection {
+                let response = HttpResponse::Forbidden().body(rejection);
+                return Ok(request.into_response(response).map_into_right_body());
+            }
+            Ok(next.call(request).await?.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Principal, Role, Scope};
+    use uuid::Uuid;
+
+    #[test]
+    fn roles_include_the_scopes_of_lower_roles() {
+        let roles = [Role::Viewer, Role::Editor, Role::Publisher, Role::Owner];
+        for pair in roles.windows(2) {
+            for scope in pair[0].scopes() {
+                assert!(pair[1].scopes().contains(scope), "{:?}", pair);
+            }
+        }
+    }
+
+    #[test]
+    fn only_owners_manage_users() {
+        let user = |role| Principal::User {
+            id: Uuid::new_v4(),
+            role,
+            needs_two_factor: false,
+        };
+        assert!(user(Role::Owner).has_scope(Scope::UsersManage));
+        assert!(!user(Role::Publisher).has_scope(Scope::UsersManage));
+        assert!(user(Role::Viewer).has_scope(Scope::SubscribersRead));
+        assert!(!user(Role::Viewer).has_scope(Scope::SubscribersWrite));
+    }
+
+    #[test]
+    fn users_who_need_two_factor_have_no_scopes() {
+        let owner = Principal::User {
+            id: Uuid::new_v4(),
+            role: Role::Owner,
+            needs_two_factor: true,
+        };
+        for scope in Scope::ALL {
+            assert!(!owner.has_scope(scope));
+        }
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in Scope::ALL {
+            assert_eq!(Scope::parse(scope.as_str()), Some(scope));
+        }
+        assert_eq!(Scope::parse("newsletters:unknown"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, SecretString};
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+/// Argon2 is deliberately slow, so we don't hash arbitrarily long input
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Hash checked when there is no user, so that unknown emails take as long as wrong passwords
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| {
+    hash_password_blocking(&SecretString::from(super::generate_token(32)))
+        .expect("dummy password can be hashed")
+});
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Invalid(&'static str),
+    Unexpected(String),
+}
+
+pub fn validate_new_password(password: &SecretString) -> Result<(), PasswordError> {
+    let length = password.expose_secret().chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(PasswordError::Invalid(
+            "Passwords must be at least 12 characters long",
+        ));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(PasswordError::Invalid(
+            "Passwords must be at most 128 characters long",
+        ));
+    }
+    Ok(())
+}
+
+fn hash_password_blocking(password: &SecretString) -> Result<String, PasswordError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| PasswordError::Unexpected(e.to_string()))
+}
+
+/// Validate and hash a new password, as PHC string; hashing runs on the blocking thread pool
+pub async fn hash_password(password: SecretString) -> Result<String, PasswordError> {
+    validate_new_password(&password)?;
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(|| hash_password_blocking(&password)))
+        .await
+        .map_err(|e| PasswordError::Unexpected(e.to_string()))?
+}
+
+/// Whether the password matches the hash; without a hash, e.g. for unknown users, a dummy hash
+/// is checked instead and the password never matches
+pub async fn verify_password(
+    password: SecretString,
+    hash: Option<String>,
+) -> Result<bool, PasswordError> {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            let is_dummy = hash.is_none();
+            let hash = hash.unwrap_or_else(|| DUMMY_HASH.clone());
+            let hash =
+                PasswordHash::new(&hash).map_err(|e| PasswordError::Unexpected(e.to_string()))?;
+            let matches = Argon2::default()
+                .verify_password(password.expose_secret().as_bytes(), &hash)
+                .is_ok();
+            Ok(matches && !is_dummy)
+        })
+    })
+    .await
+    .map_err(|e| PasswordError::Unexpected(e.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password, validate_new_password, verify_password, PasswordError};
+    use claims::{assert_matches, assert_ok};
+    use secrecy::SecretString;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::from(s)
+    }
+
+    #[test]
+    fn short_and_overly_long_passwords_are_rejected() {
+        assert_matches!(
+            validate_new_password(&secret("too short")),
+            Err(PasswordError::Invalid(_))
+        );
+        assert_matches!(
+            validate_new_password(&secret(&"a".repeat(129))),
+            Err(PasswordError::Invalid(_))
+        );
+        assert_ok!(validate_new_password(&secret("correct horse battery")));
+    }
+
+    #[tokio::test]
+    async fn only_the_hashed_password_matches() {
+        let hash = hash_password(secret("correct horse battery"))
+            .await
+            .unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        let matches = |password| verify_password(secret(password), Some(hash.clone()));
+        assert!(matches("correct horse battery").await.unwrap());
+        assert!(!matches("correct horse battery!").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn nothing_matches_without_a_hash() {
+        assert!(!verify_password(secret("anything"), None).await.unwrap());
+    }
+}
@@ -0,0 +1,127 @@
+use actix_web::cookie::{time, Cookie, SameSite};
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use super::{generate_token, hash_token, Principal, Role};
+use crate::config::AuthConfig;
+
+pub const SESSION_COOKIE: &str = "session";
+const SESSION_TOKEN_LENGTH: usize = 40;
+
+/// Start a session for the user; returns the token to hand out in the session cookie
+#[tracing::instrument(name = "Create session", skip(db_pool, config))]
+pub async fn create_session(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    config: &AuthConfig,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token(SESSION_TOKEN_LENGTH);
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_sessions (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        hash_token(&token),
+        user_id,
+        now,
+        now + config.session_ttl(),
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create session: {:?}", e);
+        e
+    })?;
+    Ok(token)
+}
+
+/// User of an unexpired session
+pub async fn find_session_user(
+    db_pool: &PgPool,
+    token: &str,
+    config: &AuthConfig,
+) -> Result<Option<Principal>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT u.id, u.role, u.totp_enabled_at IS NOT NULL AS "totp_enabled!"
+        FROM admin_sessions s
+        JOIN admin_users u ON u.id = s.user_id
+        WHERE s.token_hash = $1 AND s.expires_at > $2
+        "#,
+        hash_token(token),
+        Utc::now(),
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    Ok(row.and_then(|row| {
+        let role = Role::parse(&row.role);
+        if role.is_none() {
+            tracing::warn!("Ignoring session of user with unknown role: {}", row.role);
+        }
+        role.map(|role| Principal::User {
+            id: row.id,
+            role,
+            needs_two_factor: config.totp_required(role) && !row.totp_enabled,
+        })
+    }))
+}
+
+#[tracing::instrument(name = "Delete session", skip_all)]
+pub async fn delete_session(db_pool: &PgPool, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM admin_sessions WHERE token_hash = $1"#,
+        hash_token(token),
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to delete session: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Log the user out everywhere, e.g. once their password changed
+#[tracing::instrument(name = "Delete sessions of user", skip(db))]
+pub async fn delete_user_sessions(
+    db: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(r#"DELETE FROM admin_sessions WHERE user_id = $1"#, user_id)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete sessions that have expired; returns the number of deleted sessions
+#[tracing::instrument(name = "Purge expired sessions", skip(db_pool))]
+pub async fn purge_expired_sessions(db_pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM admin_sessions WHERE expires_at <= $1"#,
+        Utc::now(),
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Cookie carrying the session token; not readable from scripts and only sent over HTTPS unless
+/// configured otherwise for local development
+pub fn session_cookie(token: String, config: &AuthConfig) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .secure(config.secure_cookies)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::hours(config.session_ttl_hours.into()))
+        .finish()
+}
+
+/// Cookie telling the browser to drop the session cookie
+pub fn expired_session_cookie(config: &AuthConfig) -> Cookie<'static> {
+    let mut cookie = session_cookie(String::new(), config);
+    cookie.make_removal();
+    cookie
+}
@@ -0,0 +1,422 @@
+use chrono::Utc;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rand::{thread_rng, Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use sqlx::{PgExecutor, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::hash_token;
+
+/// Digits, time step and algorithm are fixed to the defaults authenticator apps support
+pub const DIGITS: u32 = 6;
+pub const STEP_SECS: u64 = 30;
+/// 160 bits, as recommended by RFC 4226 for HMAC-SHA1
+const SECRET_LENGTH: usize = 20;
+/// codes of the previous and the next time step are accepted too, to allow for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// letters and digits that can't be confused with each other when typed from a printout
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnabled,
+    InvalidCode,
+    Unexpected(sqlx::Error),
+}
+
+impl From<sqlx::Error> for TwoFactorError {
+    fn from(e: sqlx::Error) -> Self {
+        TwoFactorError::Unexpected(e)
+    }
+}
+
+fn mac<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    <M as Mac>::new_from_slice(key)
+        .expect("HMAC takes keys of any length")
+        .chain_update(message)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+/// HMAC-based one-time password as of RFC 4226
+pub fn hotp(algorithm: Algorithm, secret: &[u8], counter: u64, digits: u32) -> String {
+    let message = counter.to_be_bytes();
+    let hash = match algorithm {
+        Algorithm::Sha1 => mac::<Hmac<Sha1>>(secret, &message),
+        Algorithm::Sha256 => mac::<Hmac<Sha256>>(secret, &message),
+        Algorithm::Sha512 => mac::<Hmac<Sha512>>(secret, &message),
+    };
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let bytes: [u8; 4] = hash[offset..offset + 4].try_into().unwrap();
+    let code = (u32::from_be_bytes(bytes) & 0x7fff_ffff) % 10u32.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Time-based one-time password as of RFC 6238
+pub fn totp(algorithm: Algorithm, secret: &[u8], unix_time: u64, digits: u32) -> String {
+    hotp(algorithm, secret, unix_time / STEP_SECS, digits)
+}
+
+/// Time step of the code if it is valid at `unix_time` and newer than `last_step`, so that a
+/// code can't be used twice
+pub fn verify_code(
+    secret: &[u8],
+    code: &str,
+    unix_time: u64,
+    last_step: Option<i64>,
+) -> Option<i64> {
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current = (unix_time / STEP_SECS) as i64;
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .filter(|step| *step >= 0 && last_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(Algorithm::Sha1, secret, *step as u64, DIGITS) == code)
+}
+
+/// New random secret, base32 encoded as authenticator apps expect it
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_LENGTH];
+    thread_rng().fill_bytes(&mut secret);
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret)
+}
+
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret)
+}
+
+/// `otpauth://` URI to scan as QR code with an authenticator app
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let mut uri = reqwest::Url::parse("otpauth://totp").expect("otpauth URI is valid");
+    uri.path_segments_mut()
+        .expect("otpauth URI has a path")
+        .push(&format!("{}:{}", issuer, account));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECS.to_string());
+    uri.to_string()
+}
+
+/// QR code of the provisioning URI as SVG image
+pub fn qr_code_svg(uri: &str) -> Result<String, qrcode::types::QrError> {
+    Ok(QrCode::new(uri)?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+/// Recovery codes are compared ignoring case, dashes and whitespace
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+fn unix_time() -> u64 {
+    Utc::now().timestamp().try_into().unwrap_or_default()
+}
+
+pub async fn totp_enabled(db: impl PgExecutor<'_>, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_enabled_at IS NOT NULL AS "enabled!" FROM admin_users WHERE id = $1"#,
+        user_id,
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row.is_some_and(|row| row.enabled))
+}
+
+/// Store a new secret for the user to confirm with a code; restarting replaces the secret
+#[tracing::instrument(name = "Start TOTP enrollment", skip(db))]
+pub async fn start_totp_enrollment(
+    db: impl PgExecutor<'_>,
+    user_id: Uuid,
+) -> Result<String, TwoFactorError> {
+    let secret = generate_secret();
+    let result = sqlx::query!(
+        r#"
+        UPDATE admin_users SET totp_secret = $2, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled_at IS NULL
+        "#,
+        user_id,
+        secret,
+    )
+    .execute(db)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+    Ok(secret)
+}
+
+/// Enable TOTP once the user proved their app generates valid codes; returns new recovery codes
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip(transaction, code))]
+pub async fn confirm_totp_enrollment(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_enabled_at IS NOT NULL AS "enabled!"
+        FROM admin_users WHERE id = $1 FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    if row.enabled {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+    let secret = row
+        .totp_secret
+        .and_then(|secret| decode_secret(&secret))
+        .ok_or(TwoFactorError::NotEnabled)?;
+    let step = verify_code(&secret, code, unix_time(), None).ok_or(TwoFactorError::InvalidCode)?;
+    sqlx::query!(
+        r#"UPDATE admin_users SET totp_enabled_at = $2, totp_last_step = $3 WHERE id = $1"#,
+        user_id,
+        Utc::now(),
+        step,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(replace_recovery_codes(transaction, user_id).await?)
+}
+
+/// Invalidate the recovery codes of the user and create new ones
+#[tracing::instrument(name = "Replace recovery codes", skip(transaction))]
+pub async fn replace_recovery_codes(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM admin_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_recovery_codes (code_hash, user_id, created_at)
+        SELECT code_hash, $2, $3 FROM UNNEST($1::TEXT[]) AS code_hash
+        "#,
+        &hashes,
+        user_id,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(codes)
+}
+
+/// Check a code from the authenticator app, or else a recovery code, marking it as used
+#[tracing::instrument(name = "Verify second factor", skip(transaction, code))]
+pub async fn verify_second_factor(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    // lock the user, so that concurrent logins can't both use the same code
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret, totp_last_step FROM admin_users
+        WHERE id = $1 AND totp_enabled_at IS NOT NULL
+        FOR UPDATE
+        "#,
+        user_id,
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let secret = row.totp_secret.and_then(|secret| decode_secret(&secret));
+    let code = code.trim();
+    if let Some(step) =
+        secret.and_then(|secret| verify_code(&secret, code, unix_time(), row.totp_last_step))
+    {
+        sqlx::query!(
+            r#"UPDATE admin_users SET totp_last_step = $2 WHERE id = $1"#,
+            user_id,
+            step,
+        )
+        .execute(&mut **transaction)
+        .await?;
+        return Ok(true);
+    }
+    let result = sqlx::query!(
+        r#"
+        UPDATE admin_recovery_codes SET used_at = $3
+        WHERE code_hash = $1 AND user_id = $2 AND used_at IS NULL
+        "#,
+        hash_recovery_code(code),
+        user_id,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    if result.rows_affected() > 0 {
+        tracing::info!("Recovery code used");
+    }
+    Ok(result.rows_affected() > 0)
+}
+
+/// Turn TOTP off and drop the recovery codes
+#[tracing::instrument(name = "Disable TOTP", skip(transaction))]
+pub async fn disable_totp(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), TwoFactorError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE admin_users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled_at IS NOT NULL
+        "#,
+        user_id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(TwoFactorError::NotEnabled);
+    }
+    sqlx::query!(
+        r#"DELETE FROM admin_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_secret, generate_recovery_code, generate_secret, hash_recovery_code, hotp,
+        provisioning_uri, totp, verify_code, Algorithm, STEP_SECS,
+    };
+
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn rfc_6238_test_vectors() {
+        // appendix B of RFC 6238: time, SHA1, SHA256, SHA512
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp(Algorithm::Sha1, SEED_SHA1, time, 8), sha1, "{}", time);
+            assert_eq!(
+                totp(Algorithm::Sha256, SEED_SHA256, time, 8),
+                sha256,
+                "{}",
+                time
+            );
+            assert_eq!(
+                totp(Algorithm::Sha512, SEED_SHA512, time, 8),
+                sha512,
+                "{}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn rfc_4226_test_vectors() {
+        // appendix D of RFC 4226
+        let expected = ["755224", "287082", "359152", "969429", "338314"];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(Algorithm::Sha1, SEED_SHA1, counter as u64, 6), code);
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_within_one_step_of_drift_and_only_once() {
+        let time = 1111111111;
+        let code = totp(Algorithm::Sha1, SEED_SHA1, time, 6);
+        let step = (time / STEP_SECS) as i64;
+        assert_eq!(verify_code(SEED_SHA1, &code, time, None), Some(step));
+        assert_eq!(
+            verify_code(SEED_SHA1, &code, time + STEP_SECS, None),
+            Some(step)
+        );
+        assert_eq!(
+            verify_code(SEED_SHA1, &code, time - STEP_SECS, None),
+            Some(step)
+        );
+        assert_eq!(
+            verify_code(SEED_SHA1, &code, time + 3 * STEP_SECS, None),
+            None
+        );
+        assert_eq!(verify_code(SEED_SHA1, &code, time, Some(step)), None);
+        assert_eq!(verify_code(SEED_SHA1, "12345", time, None), None);
+        assert_eq!(verify_code(SEED_SHA1, "abcdef", time, None), None);
+    }
+
+    #[test]
+    fn generated_secrets_decode_to_160_bits() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(decode_secret(&secret).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn provisioning_uri_follows_the_key_uri_format() {
+        assert_eq!(
+            provisioning_uri("Newsletter", "ursula@example.com", "JBSWY3DPEHPK3PXP"),
+            "otpauth://totp/Newsletter:ursula@example.com\
+            ?secret=JBSWY3DPEHPK3PXP&issuer=Newsletter&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_compared_ignoring_case_and_dashes() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        let hash = hash_recovery_code(&code);
+        assert_eq!(hash_recovery_code(&code.to_uppercase()), hash);
+        assert_eq!(hash_recovery_code(&code.replace('-', "")), hash);
+        assert_eq!(hash_recovery_code(&format!(" {} ", code)), hash);
+        assert_ne!(hash_recovery_code(&generate_recovery_code()), hash);
+    }
+}
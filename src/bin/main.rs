@@ -1,22 +1,203 @@
+use clap::{Parser, Subcommand};
 use std::io::Error;
-use zero2prod::config::read_config;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::configure_tracing;
+use std::path::PathBuf;
+use zero2prod::admin_users::{invite_admin_user, invite_link, normalize_admin_email};
+use zero2prod::api_keys::create_api_key;
+use zero2prod::audit_log::{record_audit_event, Actor, AuditAction};
+use zero2prod::auth::{Role, Scope};
+use zero2prod::cleanup_worker::run_cleanup_worker_until_stopped;
+use zero2prod::config::{
+    read_config_with_sources, read_sources, sources_to_json, Config, ConfigError,
+};
+use zero2prod::config_reload::{run_config_reloader_until_stopped, ConfigReloader};
+use zero2prod::startup::{
+    build_email_client, create_db_connection_pool, Application, ApplicationBaseUrl,
+};
+use zero2prod::subscriber_import::{import_subscribers, ImportMode, IMPORT_FORM_SOURCE};
+use zero2prod::subscription_events::ConsentContext;
+use zero2prod::telemetry::{configure_tracing, LogLevel};
+use zero2prod::webhooks::run_webhook_worker_until_stopped;
+
+#[derive(Parser)]
+#[command(name = "zero2prod", about = "Newsletter app")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the app together with its background workers (default)
+    Serve,
+    /// Import subscribers from a CSV file with an `email` and a `name` column
+    ImportSubscribers {
+        path: PathBuf,
+        #[arg(long, value_enum)]
+        mode: ImportMode,
+    },
+    /// Create an API key for the admin routes and print it, e.g. to bootstrap the first key
+    CreateApiKey {
+        #[arg(long)]
+        name: String,
+        #[arg(long = "scope", value_enum, required = true)]
+        scopes: Vec<Scope>,
+    },
+    /// Invite an admin user and print the invite link, e.g. to bootstrap the first owner
+    CreateAdminUser {
+        #[arg(long)]
+        email: String,
+        #[arg(long, value_enum)]
+        role: Role,
+    },
+    /// Inspect the config of the environment set in `ZERO2PROD_APP_ENV`
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the config merged from all files and environment variables as JSON
+    Print {
+        /// mask passwords, tokens and other secrets
+        #[arg(long)]
+        redacted: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+
     // configure telemetry
     let level = "info".to_string();
     let name = "zero2prod".to_string();
     let sink = std::io::stdout;
-    configure_tracing(name, level, sink);
+    let log_level = configure_tracing(name, level, sink);
     tracing::info!("Starting app ...");
 
+    let command = cli.command.unwrap_or(Command::Serve);
+    // the merged config is printed without validating it, to find out what is wrong with it
+    if let Command::Config { command } = command {
+        return match command {
+            ConfigCommand::Print { redacted } => print_config(redacted),
+        };
+    }
+
     // read app config
-    let config = read_config().expect("failed to read config");
+    let (config, sources) = read_config_with_sources()
+        .await
+        .unwrap_or_else(|e| exit_with_config_error(e));
+    log_level.set(&config.app.log_level).map_err(Error::other)?;
+
+    match command {
+        Command::Serve => serve(&config, &sources, log_level).await,
+        Command::ImportSubscribers { path, mode } => import(&config, path, mode).await,
+        Command::CreateApiKey { name, scopes } => create_key(&config, name, scopes).await,
+        Command::CreateAdminUser { email, role } => create_admin_user(&config, email, role).await,
+        Command::Config { .. } => unreachable!("handled before reading the config"),
+    }
+}
+
+fn exit_with_config_error(e: ConfigError) -> ! {
+    // all problems at once, readable without digging through the logs
+    tracing::error!("{}", e);
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
+
+fn print_config(redacted: bool) -> Result<(), Error> {
+    let json = read_sources()
+        .and_then(|sources| sources_to_json(&sources, redacted))
+        .unwrap_or_else(|e| exit_with_config_error(e));
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+async fn serve(
+    config: &Config,
+    sources: &::config::Config,
+    log_level: LogLevel,
+) -> Result<(), Error> {
+    // spawn app and background workers, stopping as soon as any of them stops
+    let app = Application::launch(config).await?;
+    let reloader = ConfigReloader::new(
+        app.email_client(),
+        app.rate_limiter(),
+        Some(log_level),
+        sources,
+    );
+    let worker = run_cleanup_worker_until_stopped(
+        create_db_connection_pool(&config.db),
+        config.subscriptions.clone(),
+    );
+    let webhook_worker = run_webhook_worker_until_stopped(
+        create_db_connection_pool(&config.db),
+        config.webhooks.clone(),
+    );
+    tokio::select! {
+        result = app.run_until_stopped() => result?,
+        result = worker => result?,
+        result = webhook_worker => result?,
+        result = run_config_reloader_until_stopped(reloader) => result?,
+    };
+    Ok(())
+}
+
+async fn import(config: &Config, path: PathBuf, mode: ImportMode) -> Result<(), Error> {
+    let file = std::fs::File::open(path)?;
+    let context = ConsentContext {
+        source_ip: None,
+        user_agent: None,
+        form_source: Some(IMPORT_FORM_SOURCE.into()),
+        consent_text_version: config.subscriptions.consent_text_version.clone(),
+    };
+    let report = import_subscribers(
+        file,
+        mode,
+        Actor::CommandLine,
+        &create_db_connection_pool(&config.db),
+        &build_email_client(&config.email_client),
+        &ApplicationBaseUrl::new(&config.app.base_url).0,
+        &context,
+        &config.subscriptions,
+    )
+    .await
+    .map_err(Error::other)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+async fn create_key(config: &Config, name: String, scopes: Vec<Scope>) -> Result<(), Error> {
+    let (_, key) = create_api_key(&create_db_connection_pool(&config.db), &name, &scopes)
+        .await
+        .map_err(Error::other)?;
+    println!("{}", key);
+    Ok(())
+}
 
-    // spawn app
-    let app = Application::launch(&config).await?;
-    app.run_until_stopped().await?;
+async fn create_admin_user(config: &Config, email: String, role: Role) -> Result<(), Error> {
+    let email = normalize_admin_email(&email).map_err(Error::other)?;
+    let db_pool = create_db_connection_pool(&config.db);
+    let mut transaction = db_pool.begin().await.map_err(Error::other)?;
+    let (user, token) =
+        invite_admin_user(&mut transaction, &email, role, config.auth.invite_expiry())
+            .await
+            .map_err(|e| Error::other(format!("{:?}", e)))?;
+    record_audit_event(
+        &mut *transaction,
+        Actor::CommandLine,
+        AuditAction::UserInvited,
+        user.id,
+        Some(&format!("{} as {}", user.email, user.role.as_str())),
+    )
+    .await
+    .map_err(Error::other)?;
+    transaction.commit().await.map_err(Error::other)?;
+    println!(
+        "{}",
+        invite_link(&ApplicationBaseUrl::new(&config.app.base_url).0, &token)
+    );
     Ok(())
 }
@@ -0,0 +1,392 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::Sha256;
+use std::net::IpAddr;
+
+use crate::config::{BotProtectionConfig, CaptchaConfig};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Verifies the token a CAPTCHA widget handed to the client
+pub trait CaptchaVerifier: Send + Sync {
+    fn verify<'a>(
+        &'a self,
+        token: &'a str,
+        remote_ip: Option<IpAddr>,
+    ) -> BoxFuture<'a, Result<bool, reqwest::Error>>;
+}
+
+#[derive(serde::Serialize)]
+struct VerifyRequest<'a> {
+    secret: &'a str,
+    response: &'a str,
+    remoteip: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+/// Verifier for services with a hCaptcha/Turnstile-style `siteverify` endpoint, which takes a
+/// form-encoded secret and token and answers with a JSON object with a `success` flag
+pub struct HttpCaptchaVerifier {
+    http_client: Client,
+    verify_url: reqwest::Url,
+    secret: SecretBox<String>,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(
+        verify_url: reqwest::Url,
+        secret: SecretBox<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            verify_url,
+            secret,
+        }
+    }
+}
+
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    fn verify<'a>(
+        &'a self,
+        token: &'a str,
+        remote_ip: Option<IpAddr>,
+    ) -> BoxFuture<'a, Result<bool, reqwest::Error>> {
+        Box::pin(async move {
+            let request = VerifyRequest {
+                secret: self.secret.expose_secret(),
+                response: token,
+                remoteip: remote_ip.map(|ip| ip.to_string()),
+            };
+            let response: VerifyResponse = self
+                .http_client
+                .post(self.verify_url.clone())
+                .form(&request)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(response.success)
+        })
+    }
+}
+
+/// Bot protection fields submitted with the subscription form
+#[derive(Debug, Default)]
+pub struct BotSignals<'a> {
+    /// field hidden from humans, so anything in it was filled in by a bot
+    pub honeypot: Option<&'a str>,
+    pub form_token: Option<&'a str>,
+    pub captcha_token: Option<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BotCheckError {
+    /// submission looks automated
+    Rejected(&'static str),
+    /// we could not reach the CAPTCHA service
+    Unavailable,
+}
+
+pub struct BotProtection {
+    form_secret: SecretBox<String>,
+    min_submit_time: TimeDelta,
+    max_form_age: TimeDelta,
+    require_form_token: bool,
+    captcha_verifier: Option<Box<dyn CaptchaVerifier>>,
+}
+
+impl BotProtection {
+    pub fn new(
+        form_secret: SecretBox<String>,
+        min_submit_time: TimeDelta,
+        max_form_age: TimeDelta,
+        require_form_token: bool,
+        captcha_verifier: Option<Box<dyn CaptchaVerifier>>,
+    ) -> Self {
+        Self {
+            form_secret,
+            min_submit_time,
+            max_form_age,
+            require_form_token,
+            captcha_verifier,
+        }
+    }
+
+    pub fn from_config(config: &BotProtectionConfig) -> Self {
+        let captcha_verifier = config.captcha.as_ref().map(|captcha: &CaptchaConfig| {
+            // SecretBox does not implement Clone, see `build_email_client`
+            let secret = SecretBox::new(Box::new(captcha.secret.expose_secret().clone()));
+            Box::new(HttpCaptchaVerifier::new(
+                captcha.verify_url.clone(),
+                secret,
+                captcha.timeout,
+            )) as Box<dyn CaptchaVerifier>
+        });
+        Self::new(
+            SecretBox::new(Box::new(config.form_secret.expose_secret().clone())),
+            TimeDelta::from_std(config.min_submit_time)
+                .expect("time is bounded by the config validation"),
+            TimeDelta::from_std(config.max_form_age)
+                .expect("age is bounded by the config validation"),
+            config.require_form_token,
+            captcha_verifier,
+        )
+    }
+
+    fn mac(&self, timestamp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.form_secret.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac
+    }
+
+    /// Token embedded in the form when it is rendered, `<unix timestamp>.<hex HMAC-SHA256>`
+    pub fn issue_form_token(&self, now: DateTime<Utc>) -> String {
+        let timestamp = now.timestamp();
+        let signature = hex::encode(self.mac(timestamp).finalize().into_bytes());
+        format!("{}.{}", timestamp, signature)
+    }
+
+    fn check_form_token(&self, token: &str, now: DateTime<Utc>) -> Result<(), BotCheckError> {
+        let invalid = || BotCheckError::Rejected("invalid form token");
+        let (timestamp, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let timestamp = timestamp.parse::<i64>().map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        // constant-time comparison, so that the signature can't be guessed byte by byte
+        self.mac(timestamp)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let rendered_at = DateTime::from_timestamp(timestamp, 0).ok_or_else(invalid)?;
+        let elapsed = now - rendered_at;
+        if elapsed < self.min_submit_time {
+            return Err(BotCheckError::Rejected("form submitted too quickly"));
+        }
+        if elapsed > self.max_form_age {
+            return Err(BotCheckError::Rejected("form token expired"));
+        }
+        Ok(())
+    }
+
+    /// Check a submission, cheapest checks first, so that the CAPTCHA service is only called
+    /// for submissions that passed everything else
+    pub async fn check(
+        &self,
+        signals: &BotSignals<'_>,
+        remote_ip: Option<IpAddr>,
+    ) -> Result<(), BotCheckError> {
+        if signals.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Err(BotCheckError::Rejected("honeypot field filled in"));
+        }
+        match signals.form_token {
+            Some(token) => self.check_form_token(token, Utc::now())?,
+            None if self.require_form_token => {
+                return Err(BotCheckError::Rejected("missing form token"))
+            }
+            None => {}
+        }
+        if let Some(verifier) = &self.captcha_verifier {
+            let token = signals
+                .captcha_token
+                .ok_or(BotCheckError::Rejected("missing CAPTCHA token"))?;
+            match verifier.verify(token, remote_ip).await {
+                Ok(true) => {}
+                Ok(false) => return Err(BotCheckError::Rejected("CAPTCHA verification failed")),
+                Err(e) => {
+                    tracing::error!("Failed to verify CAPTCHA: {:?}", e);
+                    return Err(BotCheckError::Unavailable);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotCheckError, BotProtection, BotSignals, CaptchaVerifier, HttpCaptchaVerifier};
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err, assert_ok};
+    use secrecy::SecretBox;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn make_bot_protection(
+        require_form_token: bool,
+        captcha_verifier: Option<Box<dyn CaptchaVerifier>>,
+    ) -> BotProtection {
+        BotProtection::new(
+            SecretBox::new(Box::new("secret".into())),
+            TimeDelta::seconds(3),
+            TimeDelta::hours(1),
+            require_form_token,
+            captcha_verifier,
+        )
+    }
+
+    fn make_verifier(base_url: String) -> HttpCaptchaVerifier {
+        HttpCaptchaVerifier::new(
+            format!("{}/siteverify", base_url).parse().unwrap(),
+            SecretBox::new(Box::new("captcha-secret".into())),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn filled_in_honeypot_is_rejected() {
+        let bot_protection = make_bot_protection(false, None);
+        let signals = BotSignals {
+            honeypot: Some("http://spam.com"),
+            ..Default::default()
+        };
+        assert_err!(bot_protection.check(&signals, None).await);
+    }
+
+    #[tokio::test]
+    async fn empty_honeypot_passes() {
+        let bot_protection = make_bot_protection(false, None);
+        let signals = BotSignals {
+            honeypot: Some(""),
+            ..Default::default()
+        };
+        assert_ok!(bot_protection.check(&signals, None).await);
+    }
+
+    #[tokio::test]
+    async fn missing_form_token_is_rejected_if_required() {
+        let bot_protection = make_bot_protection(true, None);
+        assert_err!(bot_protection.check(&BotSignals::default(), None).await);
+    }
+
+    #[test]
+    fn form_token_submitted_after_minimum_time_passes() {
+        let bot_protection = make_bot_protection(true, None);
+        let now = Utc::now();
+        let token = bot_protection.issue_form_token(now - TimeDelta::seconds(10));
+        assert_ok!(bot_protection.check_form_token(&token, now));
+    }
+
+    #[test]
+    fn form_token_submitted_too_quickly_is_rejected() {
+        let bot_protection = make_bot_protection(true, None);
+        let now = Utc::now();
+        let token = bot_protection.issue_form_token(now);
+        assert_eq!(
+            bot_protection.check_form_token(&token, now),
+            Err(BotCheckError::Rejected("form submitted too quickly"))
+        );
+    }
+
+    #[test]
+    fn expired_form_token_is_rejected() {
+        let bot_protection = make_bot_protection(true, None);
+        let now = Utc::now();
+        let token = bot_protection.issue_form_token(now - TimeDelta::hours(2));
+        assert_err!(bot_protection.check_form_token(&token, now));
+    }
+
+    #[test]
+    fn tampered_form_token_is_rejected() {
+        let bot_protection = make_bot_protection(true, None);
+        let now = Utc::now();
+        let token = bot_protection.issue_form_token(now - TimeDelta::seconds(10));
+        let (_, signature) = token.split_once('.').unwrap();
+        let tampered = format!(
+            "{}.{}",
+            (now - TimeDelta::minutes(5)).timestamp(),
+            signature
+        );
+        for token in [tampered.as_str(), "", "123", "123.xyz"] {
+            assert_err!(bot_protection.check_form_token(token, now));
+        }
+    }
+
+    #[tokio::test]
+    async fn captcha_verifier_passes_if_service_reports_success() {
+        let server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .and(method("POST"))
+            .and(body_string_contains("secret=captcha-secret"))
+            .and(body_string_contains("response=token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let verifier = make_verifier(server.uri());
+
+        let result = verifier.verify("token", None).await;
+
+        assert!(assert_ok!(result));
+    }
+
+    #[tokio::test]
+    async fn captcha_verifier_fails_if_service_reports_failure() {
+        let server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&server)
+            .await;
+        let bot_protection =
+            make_bot_protection(false, Some(Box::new(make_verifier(server.uri()))));
+        let signals = BotSignals {
+            captcha_token: Some("token"),
+            ..Default::default()
+        };
+
+        let result = bot_protection.check(&signals, None).await;
+
+        assert_eq!(
+            result,
+            Err(BotCheckError::Rejected("CAPTCHA verification failed"))
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_captcha_token_is_rejected_without_calling_service() {
+        let server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let bot_protection =
+            make_bot_protection(false, Some(Box::new(make_verifier(server.uri()))));
+
+        let result = bot_protection.check(&BotSignals::default(), None).await;
+
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn captcha_service_errors_are_reported_as_unavailable() {
+        let server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let bot_protection =
+            make_bot_protection(false, Some(Box::new(make_verifier(server.uri()))));
+        let signals = BotSignals {
+            captcha_token: Some("token"),
+            ..Default::default()
+        };
+
+        let result = bot_protection.check(&signals, None).await;
+
+        assert_eq!(result, Err(BotCheckError::Unavailable));
+    }
+}
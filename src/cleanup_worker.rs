@@ -0,0 +1,83 @@
+use chrono::{TimeDelta, Utc};
+use sqlx::PgPool;
+
+use crate::auth::{purge_expired_sessions, purge_login_failures};
+use crate::config::SubscriptionsConfig;
+use crate::rate_limit::purge_rate_limits;
+
+/// Rate limit counters older than this are deleted, longer than any sensible window
+const RATE_LIMIT_RETENTION: TimeDelta = TimeDelta::days(1);
+/// Failed logins are forgotten after a day without failures, unless their key is locked out
+const LOGIN_FAILURE_RETENTION: TimeDelta = TimeDelta::days(1);
+
+/// Periodically purge subscribers who never confirmed their subscription, stale rate limits,
+/// expired sessions and old failed logins
+pub async fn run_cleanup_worker_until_stopped(
+    db_pool: PgPool,
+    config: SubscriptionsConfig,
+) -> Result<(), std::io::Error> {
+    tracing::info!("Launching cleanup worker ...");
+    loop {
+        match purge_pending_subscriptions(&db_pool, config.pending_retention()).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Purged {} pending subscriptions", n),
+            // a failed run is retried on the next tick, we don't want to bring down the app
+            Err(e) => tracing::error!("Failed to purge pending subscriptions: {:?}", e),
+        }
+        // counters of the Postgres rate limit store are only needed for their current window
+        if let Err(e) = purge_rate_limits(&db_pool, Utc::now() - RATE_LIMIT_RETENTION).await {
+            tracing::error!("Failed to purge rate limits: {:?}", e);
+        }
+        if let Err(e) = purge_expired_sessions(&db_pool).await {
+            tracing::error!("Failed to purge expired sessions: {:?}", e);
+        }
+        if let Err(e) = purge_login_failures(&db_pool, Utc::now() - LOGIN_FAILURE_RETENTION).await {
+            tracing::error!("Failed to purge failed logins: {:?}", e);
+        }
+        tokio::time::sleep(config.cleanup_interval).await;
+    }
+}
+
+/// Delete pending subscribers, together with their tokens, who subscribed and were last sent a
+/// confirmation link before the retention period; returns the number of deleted subscribers
+#[tracing::instrument(name = "Purge pending subscriptions", skip(db_pool))]
+pub async fn purge_pending_subscriptions(
+    db_pool: &PgPool,
+    retention: TimeDelta,
+) -> Result<u64, sqlx::Error> {
+    let cutoff = Utc::now() - retention;
+    let mut transaction = db_pool.begin().await?;
+    let stale_ids = sqlx::query!(
+        r#"
+        SELECT s.id FROM subscriptions s
+        WHERE s.status = 'pending_confirmation'
+        AND s.subscribed_at < $1
+        AND NOT EXISTS (
+            SELECT 1 FROM subscription_tokens t
+            WHERE t.subscriber_id = s.id AND t.created_at >= $1
+        )
+        FOR UPDATE
+        "#,
+        cutoff,
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect::<Vec<_>>();
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+        &stale_ids,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let result = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+        &stale_ids,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(result.rows_affected())
+}
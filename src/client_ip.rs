@@ -0,0 +1,115 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest};
+use std::net::IpAddr;
+
+/// Proxies in front of the app whose `X-Forwarded-For` header we trust
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+/// IP address of the client that sent the request, resolved by [`resolve_client_ip`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    /// Resolved client IP of a request, falling back to the peer address if the middleware did
+    /// not run for this request
+    pub fn of(request: &HttpRequest) -> Option<IpAddr> {
+        request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ip| ip.0)
+            .or_else(|| request.peer_addr().map(|addr| addr.ip()))
+    }
+}
+
+/// Walk the `X-Forwarded-For` chain from the closest hop outwards, returning the first address
+/// that is not one of our trusted proxies; anything further out could have been forged
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let forwarded = headers
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|ip| ip.trim().parse::<IpAddr>())
+        .collect::<Vec<_>>();
+    let mut client = peer;
+    for ip in forwarded.into_iter().rev() {
+        match ip {
+            Ok(ip) if trusted_proxies.contains(&ip) => client = ip,
+            Ok(ip) => return ip,
+            // stop at malformed entries, the closest trusted hop is the best we know
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Middleware storing the [`ClientIp`] of every request in its extensions
+pub async fn resolve_client_ip(
+    trusted_proxies: web::Data<TrustedProxies>,
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if let Some(peer) = request.peer_addr() {
+        let ip = client_ip(peer.ip(), request.headers(), &trusted_proxies.0);
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.call(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::client_ip;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use std::net::IpAddr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn header_is_ignored_for_untrusted_peer() {
+        let headers = forwarded_for("1.1.1.1");
+        assert_eq!(client_ip(ip("2.2.2.2"), &headers, &[]), ip("2.2.2.2"));
+    }
+
+    #[test]
+    fn first_untrusted_hop_is_the_client() {
+        let headers = forwarded_for("6.6.6.6, 1.1.1.1, 10.0.0.2");
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn trusted_peer_without_header_is_the_client() {
+        let trusted = [ip("10.0.0.1")];
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn malformed_entries_stop_the_walk() {
+        let headers = forwarded_for("1.1.1.1, not-an-ip");
+        let trusted = [ip("10.0.0.1")];
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("10.0.0.1")
+        );
+    }
+}
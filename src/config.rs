@@ -3,39 +3,602 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgSslMode;
 use sqlx::ConnectOptions;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::domain::SubscriberEmail;
+use crate::auth::Role;
+use crate::cors::CorsPolicy;
+use crate::domain::{EmailNormalization, NamePolicy, SubscriberEmail};
+use crate::secrets::{resolve_secrets, SECRET_KEYS};
+use crate::security_headers::SecurityHeaders;
 
-#[derive(serde::Deserialize)]
 pub struct Config {
     pub db: DatabaseConfig,
     pub app: AppConfig,
     pub email_client: EmailClientConfig,
+    pub subscriptions: SubscriptionsConfig,
+    pub bot_protection: BotProtectionConfig,
+    pub email_policy: EmailPolicyConfig,
+    pub webhooks: WebhooksConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct AuthConfig {
+    /// how long admin users stay logged in
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_ttl_hours: u32,
+    /// only send the session cookie over HTTPS; turn off for local development over HTTP
+    pub secure_cookies: bool,
+    /// how long an invite link for a new admin user stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub invite_expiry_hours: u32,
+    /// shown as account name in authenticator apps
+    pub totp_issuer: String,
+    pub totp_required_roles: Vec<Role>,
+    /// how long a link to log in without password stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub magic_link_expiry_minutes: u32,
+    /// roles allowed to log in without password
+    pub magic_link_roles: Vec<Role>,
+    /// how long a link to reset a forgotten password stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_expiry_minutes: u32,
+    /// failed logins for one email within the window before it is locked out, 0 turns it off
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failed_logins_per_email: u32,
+    /// failed logins from one IP within the window before it is locked out, 0 turns it off
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failed_logins_per_ip: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failed_login_window_minutes: u32,
+    /// duration of the first lockout, doubling with every further lockout in a row
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_minutes: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lockout_minutes: u32,
+}
+
+impl AuthConfig {
+    fn validate(&self, problems: &mut Problems) {
+        problems.bounded("auth.session_ttl_hours", self.session_ttl_hours, 1..=720);
+        problems.bounded(
+            "auth.invite_expiry_hours",
+            self.invite_expiry_hours,
+            1..=720,
+        );
+        // the provisioning URI separates issuer and account name with a colon
+        if self.totp_issuer.trim().is_empty() || self.totp_issuer.contains(':') {
+            problems.add("auth.totp_issuer", "must not be empty or contain `:`");
+        }
+        problems.bounded(
+            "auth.magic_link_expiry_minutes",
+            self.magic_link_expiry_minutes,
+            1..=1440,
+        );
+        problems.bounded(
+            "auth.password_reset_expiry_minutes",
+            self.password_reset_expiry_minutes,
+            1..=1440,
+        );
+        problems.bounded(
+            "auth.failed_login_window_minutes",
+            self.failed_login_window_minutes,
+            1..=1440,
+        );
+        problems.bounded("auth.lockout_minutes", self.lockout_minutes, 1..=1440);
+        problems.bounded(
+            "auth.max_lockout_minutes",
+            self.max_lockout_minutes,
+            self.lockout_minutes.max(1)..=10_080,
+        );
+    }
+
+    pub fn session_ttl(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::hours(self.session_ttl_hours.into())
+    }
+
+    pub fn invite_expiry(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::hours(self.invite_expiry_hours.into())
+    }
+
+    pub fn magic_link_expiry(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::minutes(self.magic_link_expiry_minutes.into())
+    }
+
+    pub fn password_reset_expiry(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::minutes(self.password_reset_expiry_minutes.into())
+    }
+
+    pub fn failed_login_window(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::minutes(self.failed_login_window_minutes.into())
+    }
+
+    pub fn lockout(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::minutes(self.lockout_minutes.into())
+    }
+
+    pub fn max_lockout(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::minutes(self.max_lockout_minutes.into())
+    }
+
+    pub fn magic_link_allowed(&self, role: Role) -> bool {
+        self.magic_link_roles.contains(&role)
+    }
+
+    pub fn totp_required(&self, role: Role) -> bool {
+        self.totp_required_roles.contains(&role)
+    }
+}
+
+/// Delivery of webhooks, parsed from [`RawWebhooksConfig`] when the config is read
+#[derive(Clone)]
+pub struct WebhooksConfig {
+    pub timeout: Duration,
+    /// deliveries are marked as failed after this many attempts, to be replayed by an admin
+    pub max_attempts: u32,
+    /// delay before the first retry, doubling with every further attempt
+    pub retry_base_delay: Duration,
+    /// how often the background worker looks for due deliveries
+    pub poll_interval: Duration,
+    /// maximum number of deliveries sent at once
+    pub batch_size: u32,
+}
+
+#[derive(serde::Deserialize)]
+struct RawWebhooksConfig {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    timeout_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    retry_base_delay_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    poll_interval_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    batch_size: u32,
+}
+
+impl WebhooksConfig {
+    fn parse(raw: RawWebhooksConfig, problems: &mut Problems) -> Self {
+        problems.bounded("webhooks.max_attempts", raw.max_attempts, 1..=100);
+        problems.bounded("webhooks.batch_size", raw.batch_size, 1..=1000);
+        Self {
+            timeout: problems.millis("webhooks.timeout_ms", raw.timeout_ms, 1..=60_000),
+            max_attempts: raw.max_attempts,
+            retry_base_delay: problems.secs(
+                "webhooks.retry_base_delay_secs",
+                raw.retry_base_delay_secs,
+                1..=86_400,
+            ),
+            poll_interval: problems.millis(
+                "webhooks.poll_interval_ms",
+                raw.poll_interval_ms,
+                10..=60_000,
+            ),
+            batch_size: raw.batch_size,
+        }
+    }
+}
+
+/// Rules on the addresses we accept, parsed from [`RawEmailPolicyConfig`]
+pub struct EmailPolicyConfig {
+    /// reject addresses at the throwaway providers bundled with the app
+    pub block_disposable_domains: bool,
+    /// reject addresses whose domain can't receive mail, costs a DNS lookup per subscription
+    pub check_mx: bool,
+    pub mx_timeout: Duration,
 }
 
 #[derive(serde::Deserialize)]
+struct RawEmailPolicyConfig {
+    block_disposable_domains: bool,
+    check_mx: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    mx_timeout_ms: u64,
+}
+
+impl EmailPolicyConfig {
+    fn parse(raw: RawEmailPolicyConfig, problems: &mut Problems) -> Self {
+        Self {
+            block_disposable_domains: raw.block_disposable_domains,
+            check_mx: raw.check_mx,
+            mx_timeout: problems.millis(
+                "email_policy.mx_timeout_ms",
+                raw.mx_timeout_ms,
+                1..=60_000,
+            ),
+        }
+    }
+}
+
+/// Checks of subscription forms against bots, parsed from [`RawBotProtectionConfig`]
+pub struct BotProtectionConfig {
+    /// key used to sign the timestamp embedded in the subscription form
+    pub form_secret: SecretBox<String>,
+    /// submissions faster than this after rendering the form are from bots
+    pub min_submit_time: Duration,
+    pub max_form_age: Duration,
+    /// reject submissions without a signed timestamp, i.e. not posted from our own form
+    pub require_form_token: bool,
+    /// CAPTCHA verification is only enabled when configured
+    pub captcha: Option<CaptchaConfig>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawBotProtectionConfig {
+    form_secret: SecretBox<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    min_submit_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    max_form_age_secs: u64,
+    require_form_token: bool,
+    captcha: Option<RawCaptchaConfig>,
+}
+
+impl BotProtectionConfig {
+    fn parse(raw: RawBotProtectionConfig, problems: &mut Problems) -> Option<Self> {
+        let max_form_age = problems.secs(
+            "bot_protection.max_form_age_secs",
+            raw.max_form_age_secs,
+            1..=604_800,
+        );
+        // otherwise no form could ever be submitted in time
+        let min_submit_time = problems.secs(
+            "bot_protection.min_submit_secs",
+            raw.min_submit_secs,
+            0..=raw.max_form_age_secs.saturating_sub(1),
+        );
+        let captcha = match raw.captcha {
+            Some(captcha) => Some(CaptchaConfig::parse(captcha, problems)?),
+            None => None,
+        };
+        Some(Self {
+            form_secret: raw.form_secret,
+            min_submit_time,
+            max_form_age,
+            require_form_token: raw.require_form_token,
+            captcha,
+        })
+    }
+}
+
+pub struct CaptchaConfig {
+    /// hCaptcha/Turnstile-style verification endpoint
+    pub verify_url: reqwest::Url,
+    pub secret: SecretBox<String>,
+    pub timeout: Duration,
+}
+
+#[derive(serde::Deserialize)]
+struct RawCaptchaConfig {
+    verify_url: String,
+    secret: SecretBox<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    timeout_ms: u64,
+}
+
+impl CaptchaConfig {
+    fn parse(raw: RawCaptchaConfig, problems: &mut Problems) -> Option<Self> {
+        let verify_url = problems.http_url("bot_protection.captcha.verify_url", &raw.verify_url);
+        let timeout = problems.millis(
+            "bot_protection.captcha.timeout_ms",
+            raw.timeout_ms,
+            1..=60_000,
+        );
+        Some(Self {
+            verify_url: verify_url?,
+            secret: raw.secret,
+            timeout,
+        })
+    }
+}
+
+/// Subscription flow settings, parsed from [`RawSubscriptionsConfig`] when the config is read
+#[derive(Clone)]
+pub struct SubscriptionsConfig {
+    /// version of the consent text shown on the subscription form, recorded with every event
+    pub consent_text_version: String,
+    /// how long a confirmation link stays valid after it has been sent
+    pub token_expiry_hours: u32,
+    /// how long we keep subscribers who never confirmed before purging them
+    pub pending_retention_hours: u32,
+    /// how often the background job looks for pending subscribers to purge
+    pub cleanup_interval: Duration,
+    /// treat aliases like Gmail's `u.rsula+news@` as the same subscriber; only applies to
+    /// subscribers added after changing it
+    pub fold_provider_aliases: bool,
+    /// page browsers are redirected to after posting the subscription form; without it they
+    /// get an empty 200 response
+    pub thank_you_url: Option<reqwest::Url>,
+    pub name_policy: NamePolicy,
+    /// stricter or looser name rules for particular forms, keyed by the form source
+    pub name_policies_by_source: std::collections::HashMap<String, NamePolicy>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawSubscriptionsConfig {
+    consent_text_version: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    token_expiry_hours: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pending_retention_hours: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    cleanup_interval_secs: u64,
+    fold_provider_aliases: bool,
+    thank_you_url: Option<String>,
+    name_policy: NamePolicy,
+    #[serde(default)]
+    name_policies_by_source: std::collections::HashMap<String, NamePolicy>,
+}
+
+impl SubscriptionsConfig {
+    fn parse(raw: RawSubscriptionsConfig, problems: &mut Problems) -> Option<Self> {
+        problems.bounded(
+            "subscriptions.token_expiry_hours",
+            raw.token_expiry_hours,
+            1..=720,
+        );
+        // confirmation links must not outlive the subscribers they confirm
+        problems.bounded(
+            "subscriptions.pending_retention_hours",
+            raw.pending_retention_hours,
+            raw.token_expiry_hours..=8760,
+        );
+        let cleanup_interval = problems.secs(
+            "subscriptions.cleanup_interval_secs",
+            raw.cleanup_interval_secs,
+            1..=86_400,
+        );
+        let thank_you_url = match raw.thank_you_url {
+            Some(url) => Some(problems.http_url("subscriptions.thank_you_url", &url)?),
+            None => None,
+        };
+        Some(Self {
+            consent_text_version: raw.consent_text_version,
+            token_expiry_hours: raw.token_expiry_hours,
+            pending_retention_hours: raw.pending_retention_hours,
+            cleanup_interval,
+            fold_provider_aliases: raw.fold_provider_aliases,
+            thank_you_url,
+            name_policy: raw.name_policy,
+            name_policies_by_source: raw.name_policies_by_source,
+        })
+    }
+
+    pub fn token_expiry(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::hours(self.token_expiry_hours.into())
+    }
+
+    pub fn pending_retention(&self) -> chrono::TimeDelta {
+        chrono::TimeDelta::hours(self.pending_retention_hours.into())
+    }
+
+    /// Name rules of the form the subscriber used, falling back to the default rules
+    pub fn name_policy_for(&self, source: Option<&str>) -> &NamePolicy {
+        source
+            .and_then(|source| self.name_policies_by_source.get(source))
+            .unwrap_or(&self.name_policy)
+    }
+
+    pub fn email_normalization(&self) -> EmailNormalization {
+        EmailNormalization {
+            fold_provider_aliases: self.fold_provider_aliases,
+        }
+    }
+}
+
+/// HashiCorp Vault, or a server with the same API, to fetch secrets referenced with `*_vault`
+/// keys from; read from `secrets.vault` only when there are such references
+pub struct VaultConfig {
+    /// e.g. `https://vault.example.com:8200`
+    pub address: reqwest::Url,
+    pub token: SecretBox<String>,
+    /// mount of the KV version 2 secrets engine, e.g. `secret`
+    pub mount: String,
+    pub timeout: Duration,
+}
+
+#[derive(serde::Deserialize)]
+struct RawVaultConfig {
+    address: String,
+    token: SecretBox<String>,
+    mount: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    timeout_ms: u64,
+}
+
+impl VaultConfig {
+    /// Read `secrets.vault` from the config sources; unlike the other sections it is read before
+    /// the rest of the config, to resolve the secrets in it
+    pub fn from_sources(config: &config::Config) -> Result<Self, ConfigError> {
+        let mut problems = Problems::default();
+        let vault = problems
+            .section::<RawVaultConfig>(config, "secrets.vault")
+            .and_then(|raw| Self::parse(raw, &mut problems));
+        match vault {
+            Some(vault) if problems.0.is_empty() => Ok(vault),
+            _ => Err(ConfigError {
+                problems: problems.0,
+            }),
+        }
+    }
+
+    fn parse(raw: RawVaultConfig, problems: &mut Problems) -> Option<Self> {
+        let address = problems.http_url("secrets.vault.address", &raw.address);
+        let timeout = problems.millis("secrets.vault.timeout_ms", raw.timeout_ms, 1..=60_000);
+        Some(Self {
+            address: address?,
+            token: raw.token,
+            mount: raw.mount,
+            timeout,
+        })
+    }
+}
+
+/// Email delivery settings, parsed from [`RawEmailClientConfig`] when the config is read
 pub struct EmailClientConfig {
-    pub base_url: String,
-    pub sender_email: String,
+    /// endpoints are resolved relative to it, so a path must end with `/`
+    pub base_url: reqwest::Url,
+    pub sender_email: SubscriberEmail,
     pub auth_token: SecretBox<String>,
-    pub timeout_ms: u64,
+    pub timeout: Duration,
+}
+
+/// Email delivery settings as written in the config files
+#[derive(serde::Deserialize)]
+struct RawEmailClientConfig {
+    base_url: String,
+    sender_email: String,
+    auth_token: SecretBox<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    timeout_ms: u64,
 }
 
 impl EmailClientConfig {
-    pub fn parse_sender_email(&self) -> Result<SubscriberEmail, String> {
-        SubscriberEmail::parse(self.sender_email.clone())
+    fn parse(raw: RawEmailClientConfig, problems: &mut Problems) -> Option<Self> {
+        let base_url = problems.http_url("email_client.base_url", &raw.base_url);
+        let sender_email = SubscriberEmail::parse(raw.sender_email)
+            .map_err(|e| problems.add("email_client.sender_email", e))
+            .ok();
+        let timeout = problems.millis("email_client.timeout_ms", raw.timeout_ms, 1..=60_000);
+        Some(Self {
+            base_url: base_url?,
+            sender_email: sender_email?,
+            auth_token: raw.auth_token,
+            timeout,
+        })
     }
+}
 
-    pub fn parse_timeout(&self) -> std::time::Duration {
-        std::time::Duration::from_millis(self.timeout_ms)
+/// Server settings, parsed from [`RawAppConfig`] when the config is read
+pub struct AppConfig {
+    pub host: String,
+    pub port: u16,
+    /// public URL of the app, used to build links sent out in emails
+    pub base_url: reqwest::Url,
+    /// level or filter like `info,sqlx=warn`; `RUST_LOG` takes precedence
+    pub log_level: String,
+    pub rate_limit: RateLimitConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub cors: CorsConfig,
+}
+
+#[derive(serde::Deserialize)]
+struct RawAppConfig {
+    host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    port: u16,
+    base_url: String,
+    log_level: String,
+    rate_limit: RawRateLimitConfig,
+    security_headers: SecurityHeadersConfig,
+    cors: CorsConfig,
+}
+
+impl AppConfig {
+    fn parse(raw: RawAppConfig, problems: &mut Problems) -> Option<Self> {
+        // links are built by appending paths to it
+        if raw.base_url.ends_with('/') {
+            problems.add("app.base_url", "must not end with `/`");
+        }
+        let base_url = problems.http_url("app.base_url", &raw.base_url);
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&raw.log_level) {
+            problems.add("app.log_level", e);
+        }
+        let rate_limit = RateLimitConfig::parse(raw.rate_limit, problems);
+        if let Err(e) = SecurityHeaders::from_config(&raw.security_headers) {
+            problems.add("app.security_headers", e);
+        }
+        if let Err(e) = CorsPolicy::from_config(&raw.cors) {
+            problems.add("app.cors", e);
+        }
+        Some(Self {
+            host: raw.host,
+            port: raw.port,
+            base_url: base_url?,
+            log_level: raw.log_level,
+            rate_limit,
+            security_headers: raw.security_headers,
+            cors: raw.cors,
+        })
     }
 }
 
+/// Cross-origin access to the public routes, for scripts on our other sites
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct CorsConfig {
+    /// origins like `https://example.com`, or `*` for any origin
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// request headers scripts may send besides the ones always allowed
+    pub allowed_headers: Vec<String>,
+    /// let scripts send cookies; the public routes don't use any
+    pub allow_credentials: bool,
+    /// how long browsers may cache the answer to a preflight request
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_age_secs: usize,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SecurityHeadersConfig {
+    /// `Content-Security-Policy` of all responses, `frame-ancestors` is added to it
+    pub content_security_policy: String,
+    /// sources allowed to embed our pages in frames
+    pub frame_ancestors: String,
+    pub referrer_policy: String,
+    /// `max-age` of the `Strict-Transport-Security` header, which is only sent when set; only set
+    /// it where the app is served over HTTPS, browsers remember it
+    #[serde(default)]
+    pub hsts_max_age_secs: Option<u64>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreKind {
+    /// counters are kept per instance, enough for running a single instance
+    Memory,
+    /// counters are shared by all instances through the database
+    Postgres,
+}
+
+/// Limits of requests per client, parsed from [`RawRateLimitConfig`] when the config is read
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    pub store: RateLimitStoreKind,
+    pub window: Duration,
+    pub max_requests_per_ip: u32,
+    pub max_requests_per_email: u32,
+    /// proxies in front of the app whose `X-Forwarded-For` header we trust
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
 #[derive(serde::Deserialize)]
-pub struct AppConfig {
-    pub host: String,
+struct RawRateLimitConfig {
+    store: RateLimitStoreKind,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub port: u16,
+    window_secs: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    max_requests_per_ip: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    max_requests_per_email: u32,
+    #[serde(default)]
+    trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+impl RateLimitConfig {
+    fn parse(raw: RawRateLimitConfig, problems: &mut Problems) -> Self {
+        Self {
+            store: raw.store,
+            window: problems.secs("app.rate_limit.window_secs", raw.window_secs, 1..=86_400),
+            max_requests_per_ip: raw.max_requests_per_ip,
+            max_requests_per_email: raw.max_requests_per_email,
+            trusted_proxies: raw.trusted_proxies,
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -70,17 +633,14 @@ impl DatabaseConfig {
     }
 }
 
-pub enum Env {
-    Local,
-    Prod,
-}
+/// Deployment environment, selecting the config files layered over the base ones, e.g. `local`,
+/// `prod`, `staging` or `preview-42`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Env(String);
 
 impl Env {
     pub fn as_str(&self) -> &str {
-        match self {
-            Env::Local => "local",
-            Env::Prod => "prod",
-        }
+        &self.0
     }
 }
 
@@ -88,37 +648,469 @@ impl TryFrom<String> for Env {
     type Error = String;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.as_str() {
-            "local" => Ok(Env::Local),
-            "prod" => Ok(Env::Prod),
-            _ => Err(format!("{} is not a valid environment", value)),
+        // names end up in file paths, and `base` is always loaded
+        let is_valid = value.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && value
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+            && value != "base";
+        if !is_valid {
+            return Err(format!(
+                "{} is not a valid environment, expected lowercase letters, digits, `-` and `_`",
+                value
+            ));
         }
+        Ok(Self(value))
     }
 }
 
-pub fn read_config() -> Result<Config, config::ConfigError> {
-    tracing::info!("Reading config ...");
-    let directory = std::env::current_dir().expect("Failed to determine current directory");
-    let config_directory = directory.join("config");
+/// Everything wrong with the config, collected so that it can be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl ConfigError {
+    fn single(key: &str, problem: impl Display) -> Self {
+        Self {
+            problems: vec![format!("{}: {}", key, problem)],
+        }
+    }
+
+    /// Problems found, each prefixed with the key of its setting
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid config, found {} problem(s):",
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Problems found while reading the config so far
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn add(&mut self, key: &str, problem: impl Display) {
+        self.0.push(format!("{}: {}", key, problem));
+    }
+
+    /// Deserialize one section, so that a broken section doesn't hide problems in the others
+    fn section<T: serde::de::DeserializeOwned>(
+        &mut self,
+        config: &config::Config,
+        key: &str,
+    ) -> Option<T> {
+        config.get(key).map_err(|e| self.add(key, e)).ok()
+    }
+
+    fn http_url(&mut self, key: &str, value: &str) -> Option<reqwest::Url> {
+        match reqwest::Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+            Ok(_) => {
+                self.add(key, format!("{} is not an http(s) URL", value));
+                None
+            }
+            Err(e) => {
+                self.add(key, format!("{} is not a valid URL: {}", value, e));
+                None
+            }
+        }
+    }
+
+    /// Duration of a setting in milliseconds, checked to be within the bounds
+    fn millis(&mut self, key: &str, value: u64, bounds: RangeInclusive<u64>) -> Duration {
+        self.bounded(key, value, bounds);
+        Duration::from_millis(value)
+    }
+
+    /// Duration of a setting in seconds, checked to be within the bounds
+    fn secs(&mut self, key: &str, value: u64, bounds: RangeInclusive<u64>) -> Duration {
+        self.bounded(key, value, bounds);
+        Duration::from_secs(value)
+    }
+
+    fn bounded<T: PartialOrd + Display>(&mut self, key: &str, value: T, bounds: RangeInclusive<T>) {
+        if !bounds.contains(&value) {
+            let problem = format!(
+                "{} is out of range, expected {} to {}",
+                value,
+                bounds.start(),
+                bounds.end()
+            );
+            self.add(key, problem);
+        }
+    }
+}
+
+impl Config {
+    /// Deserialize and validate the merged config sources, reporting all problems at once
+    pub fn from_sources(config: &config::Config) -> Result<Self, ConfigError> {
+        let mut problems = Problems::default();
+        let db = problems.section::<DatabaseConfig>(config, "db");
+        let app = problems
+            .section::<RawAppConfig>(config, "app")
+            .and_then(|raw| AppConfig::parse(raw, &mut problems));
+        let email_client = problems
+            .section::<RawEmailClientConfig>(config, "email_client")
+            .and_then(|raw| EmailClientConfig::parse(raw, &mut problems));
+        let subscriptions = problems
+            .section::<RawSubscriptionsConfig>(config, "subscriptions")
+            .and_then(|raw| SubscriptionsConfig::parse(raw, &mut problems));
+        let bot_protection = problems
+            .section::<RawBotProtectionConfig>(config, "bot_protection")
+            .and_then(|raw| BotProtectionConfig::parse(raw, &mut problems));
+        let email_policy = problems
+            .section::<RawEmailPolicyConfig>(config, "email_policy")
+            .map(|raw| EmailPolicyConfig::parse(raw, &mut problems));
+        let webhooks = problems
+            .section::<RawWebhooksConfig>(config, "webhooks")
+            .map(|raw| WebhooksConfig::parse(raw, &mut problems));
+        let auth = problems.section::<AuthConfig>(config, "auth");
+        auth.iter().for_each(|auth| auth.validate(&mut problems));
+
+        match (
+            db,
+            app,
+            email_client,
+            subscriptions,
+            bot_protection,
+            email_policy,
+            webhooks,
+            auth,
+        ) {
+            (
+                Some(db),
+                Some(app),
+                Some(email_client),
+                Some(subscriptions),
+                Some(bot_protection),
+                Some(email_policy),
+                Some(webhooks),
+                Some(auth),
+            ) if problems.0.is_empty() => Ok(Config {
+                db,
+                app,
+                email_client,
+                subscriptions,
+                bot_protection,
+                email_policy,
+                webhooks,
+                auth,
+            }),
+            _ => Err(ConfigError {
+                problems: problems.0,
+            }),
+        }
+    }
+}
+
+/// Environment variable selecting the [`Env`], `local` by default
+const ENV_VAR: &str = "ZERO2PROD_APP_ENV";
+/// Environment variable overriding the directory with the config files, `config` in the current
+/// directory by default
+const CONFIG_DIR_VAR: &str = "ZERO2PROD_APP_CONFIG_DIR";
+/// Formats of the config files, layered in this order when a layer has files in several formats
+const FILE_EXTENSIONS: [&str; 3] = ["yaml", "toml", "json"];
+
+fn config_directory() -> Result<PathBuf, ConfigError> {
+    match std::env::var_os(CONFIG_DIR_VAR) {
+        Some(directory) => Ok(PathBuf::from(directory)),
+        None => std::env::current_dir()
+            .map(|directory| directory.join("config"))
+            .map_err(|e| ConfigError::single("current directory", e)),
+    }
+}
 
-    let env: Env = std::env::var("ZERO2PROD_APP_ENV")
+fn read_env() -> Result<Env, ConfigError> {
+    std::env::var(ENV_VAR)
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse: ZERO2PROD_APP_ENV");
+        .map_err(|e| ConfigError::single(ENV_VAR, e))
+}
 
-    let base_file = config_directory.join("base.yaml");
-    let env_file = config_directory.join(format!("{}.yaml", env.as_str()));
+/// Paths a layer can have files at, whether they exist or not
+fn layer_paths(directory: &Path, layer: &str) -> Vec<PathBuf> {
+    FILE_EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{}.{}", layer, extension)))
+        .collect()
+}
 
-    let base_env_source = config::File::from(base_file);
-    let env_source = config::File::from(env_file);
-    let env_var_source = config::Environment::with_prefix("ZERO2PROD_APP")
+/// Files of a layer, e.g. `base.yaml` and `base.toml`; each is optional, but a layer needs one
+fn layer_files(directory: &Path, layer: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    let files = layer_paths(directory, layer)
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        let problem = format!(
+            "found no {}.{{{}}} in {}",
+            layer,
+            FILE_EXTENSIONS.join(","),
+            directory.display()
+        );
+        return Err(ConfigError::single("config files", problem));
+    }
+    Ok(files)
+}
+
+/// Layer the files of the base and the environment with the environment variables on top
+fn merge_sources(
+    directory: &Path,
+    env: &Env,
+    env_vars: config::Environment,
+) -> Result<config::Config, ConfigError> {
+    let mut builder = config::Config::builder();
+    for layer in ["base", env.as_str()] {
+        for file in layer_files(directory, layer)? {
+            builder = builder.add_source(config::File::from(file));
+        }
+    }
+    builder
+        .add_source(env_vars)
+        .build()
+        .map_err(|e| ConfigError::single("config files", e))
+}
+
+/// Merged config sources of the environment, not validated yet
+pub fn read_sources() -> Result<config::Config, ConfigError> {
+    let env = read_env()?;
+    let env_vars = config::Environment::with_prefix("ZERO2PROD_APP")
         .prefix_separator("_")
         .separator("__");
+    merge_sources(&config_directory()?, &env, env_vars)
+}
+
+/// Paths of all files the config of the environment can be read from, including the ones that
+/// don't exist (yet), e.g. to watch them for changes
+pub fn config_paths() -> Result<Vec<PathBuf>, ConfigError> {
+    let env = read_env()?;
+    let directory = config_directory()?;
+    let mut paths = layer_paths(&directory, "base");
+    paths.extend(layer_paths(&directory, env.as_str()));
+    Ok(paths)
+}
+
+/// Validated config together with the merged sources it was read from, secrets resolved
+pub async fn read_config_with_sources() -> Result<(Config, config::Config), ConfigError> {
+    tracing::info!("Reading config ...");
+    let sources = resolve_secrets(read_sources()?)
+        .await
+        .map_err(|problems| ConfigError { problems })?;
+    let config = Config::from_sources(&sources)?;
+    Ok((config, sources))
+}
+
+pub async fn read_config() -> Result<Config, ConfigError> {
+    read_config_with_sources().await.map(|(config, _)| config)
+}
+
+/// Merged config sources as JSON, to see what an environment ends up with
+pub fn sources_to_json(
+    sources: &config::Config,
+    redacted: bool,
+) -> Result<serde_json::Value, ConfigError> {
+    let mut json = sources
+        .clone()
+        .try_deserialize::<serde_json::Value>()
+        .map_err(|e| ConfigError::single("config", e))?;
+    if let serde_json::Value::Object(sections) = &mut json {
+        // picked up with the other environment variables, but they only select the sources
+        sections.remove("env");
+        sections.remove("config_dir");
+    }
+    if redacted {
+        redact(&mut json);
+    }
+    Ok(json)
+}
+
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) && !value.is_null() {
+                    *value = "[redacted]".into();
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
 
-    let config = config::Config::builder()
-        .add_source(base_env_source)
-        .add_source(env_source)
-        .add_source(env_var_source)
-        .build()?;
-    config.try_deserialize::<Config>()
+#[cfg(test)]
+mod tests {
+    use super::{merge_sources, sources_to_json, Config, ConfigError, Env};
+    use claims::{assert_err, assert_ok};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    /// Directory with the given config files, removed when dropped
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn with_files(files: &[(&str, &str)]) -> Self {
+            let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+            std::fs::create_dir(&directory).unwrap();
+            for (name, content) in files {
+                std::fs::write(directory.join(name), content).unwrap();
+            }
+            Self(directory)
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn env_vars(vars: &[(&str, &str)]) -> config::Environment {
+        let vars = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        config::Environment::with_prefix("ZERO2PROD_APP")
+            .prefix_separator("_")
+            .separator("__")
+            .source(Some(vars))
+    }
+
+    fn from_files(overrides: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let mut builder = config::Config::builder()
+            .add_source(config::File::with_name("config/base.yaml"))
+            .add_source(config::File::with_name("config/local.yaml"));
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        Config::from_sources(&builder.build().unwrap())
+    }
+
+    #[test]
+    fn local_config_is_valid() {
+        let config = assert_ok!(from_files(&[]));
+        assert_eq!(config.email_client.timeout.as_millis(), 10_000);
+        assert_eq!(config.app.base_url.as_str(), "http://127.0.0.1:8000/");
+        assert_eq!(config.app.rate_limit.window, Duration::from_secs(3600));
+        assert_eq!(config.webhooks.poll_interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn all_problems_are_reported_together() {
+        let error = from_files(&[
+            ("email_client.sender_email", "not an email"),
+            ("email_client.base_url", "localhost"),
+            ("webhooks.timeout_ms", "0"),
+            ("auth.session_ttl_hours", "many"),
+            ("app.base_url", "http://127.0.0.1:8000/"),
+        ])
+        .map(|_| ())
+        .unwrap_err();
+        let keys = error
+            .problems()
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "app.base_url",
+                "email_client.base_url",
+                "email_client.sender_email",
+                "webhooks.timeout_ms",
+                "auth",
+            ]
+        );
+        assert!(error
+            .to_string()
+            .starts_with("Invalid config, found 5 problem(s):"));
+    }
+
+    #[test]
+    fn environments_can_have_any_name_safe_in_a_path() {
+        for name in ["local", "prod", "staging", "test", "preview-42", "eu_west"] {
+            assert_ok!(Env::try_from(name.to_string()));
+        }
+        for name in ["", "Prod", "../prod", "-x", "preview.42", "base"] {
+            assert_err!(Env::try_from(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn files_of_all_formats_are_layered_under_the_environment_variables() {
+        let directory = ConfigDir::with_files(&[
+            (
+                "base.yaml",
+                "app:\n  a: yaml\n  b: yaml\n  c: yaml\n  d: yaml\n",
+            ),
+            ("base.toml", "[app]\nb = \"toml\"\n"),
+            ("staging.json", r#"{"app": {"c": "json"}}"#),
+        ]);
+        let env = Env::try_from("staging".to_string()).unwrap();
+        let vars = env_vars(&[("ZERO2PROD_APP_APP__D", "var")]);
+
+        let sources = assert_ok!(merge_sources(&directory.0, &env, vars));
+
+        let values =
+            ["a", "b", "c", "d"].map(|key| sources.get_string(&format!("app.{}", key)).unwrap());
+        assert_eq!(values, ["yaml", "toml", "json", "var"]);
+    }
+
+    #[test]
+    fn every_layer_needs_a_file() {
+        let directory = ConfigDir::with_files(&[("base.yaml", "app: {}\n")]);
+        let env = Env::try_from("staging".to_string()).unwrap();
+        let error = merge_sources(&directory.0, &env, env_vars(&[]))
+            .map(|_| ())
+            .unwrap_err();
+        assert!(error.problems()[0].contains("found no staging.{yaml,toml,json}"));
+    }
+
+    #[test]
+    fn printed_config_masks_secrets_when_redacted() {
+        let directory = ConfigDir::with_files(&[
+            (
+                "base.yaml",
+                "db:\n  password: hunter2\n  name: newsletter\n",
+            ),
+            (
+                "test.yaml",
+                "bot_protection:\n  captcha:\n    secret: s3cret\n",
+            ),
+        ]);
+        let env = Env::try_from("test".to_string()).unwrap();
+        let vars = env_vars(&[("ZERO2PROD_APP_ENV", "test")]);
+        let sources = merge_sources(&directory.0, &env, vars).unwrap();
+
+        let redacted = sources_to_json(&sources, true).unwrap();
+        let plain = sources_to_json(&sources, false).unwrap();
+
+        assert_eq!(redacted["db"]["password"], "[redacted]");
+        assert_eq!(redacted["db"]["name"], "newsletter");
+        assert_eq!(
+            redacted["bot_protection"]["captcha"]["secret"],
+            "[redacted]"
+        );
+        assert!(redacted.get("env").is_none());
+        assert_eq!(plain["db"]["password"], "hunter2");
+    }
 }
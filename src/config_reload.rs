@@ -0,0 +1,221 @@
+use actix_web::web;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{config_paths, read_config_with_sources, sources_to_json, Config};
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+use crate::telemetry::LogLevel;
+
+/// Settings applied to the running app on reload; changes of all others, e.g. the bind address
+/// or the database, are only logged and need a restart
+const RELOADABLE_KEYS: [&str; 5] = [
+    "app.log_level",
+    "app.rate_limit.max_requests_per_email",
+    "app.rate_limit.max_requests_per_ip",
+    "app.rate_limit.window_secs",
+    "email_client.timeout_ms",
+];
+/// How often the config files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Applies changes of the config to the running app
+pub struct ConfigReloader {
+    email_client: web::Data<EmailClient>,
+    rate_limiter: web::Data<RateLimiter>,
+    /// `None` where tracing is set up elsewhere, e.g. in tests
+    log_level: Option<LogLevel>,
+    /// settings of the config applied last, by key
+    settings: BTreeMap<String, Value>,
+}
+
+/// Leaf values of the JSON by their dotted key, e.g. `app.port`
+fn flatten(value: Value, prefix: &str, settings: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let key = match prefix {
+                    "" => name,
+                    prefix => format!("{}.{}", prefix, name),
+                };
+                flatten(value, &key, settings);
+            }
+        }
+        value => {
+            settings.insert(prefix.to_string(), value);
+        }
+    }
+}
+
+fn settings_of(sources: &config::Config) -> BTreeMap<String, Value> {
+    let mut settings = BTreeMap::new();
+    // the sources are the ones the config was just validated from
+    if let Ok(json) = sources_to_json(sources, false) {
+        flatten(json, "", &mut settings);
+    }
+    settings
+}
+
+impl ConfigReloader {
+    /// Reloader for the parts of an app launched with the config read from the sources
+    pub fn new(
+        email_client: web::Data<EmailClient>,
+        rate_limiter: web::Data<RateLimiter>,
+        log_level: Option<LogLevel>,
+        sources: &config::Config,
+    ) -> Self {
+        Self {
+            email_client,
+            rate_limiter,
+            log_level,
+            settings: settings_of(sources),
+        }
+    }
+
+    /// Re-read the config, and apply it if it is valid
+    pub async fn reload(&mut self) {
+        tracing::info!("Reloading config ...");
+        match read_config_with_sources().await {
+            Ok((config, sources)) => self.apply(&config, &sources),
+            Err(e) => tracing::error!("Kept the current config: {}", e),
+        }
+    }
+
+    /// Apply the reloadable settings of a validated config, and log what changed
+    pub fn apply(&mut self, config: &Config, sources: &config::Config) {
+        let settings = settings_of(sources);
+        let mut keys = self
+            .settings
+            .keys()
+            .chain(settings.keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        let changed = keys
+            .into_iter()
+            .filter(|key| self.settings.get(*key) != settings.get(*key))
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            tracing::info!("Config is unchanged");
+            return;
+        }
+
+        self.email_client.set_timeout(config.email_client.timeout);
+        self.rate_limiter.set_limits(&config.app.rate_limit);
+        if let Some(log_level) = &self.log_level {
+            if let Err(e) = log_level.set(&config.app.log_level) {
+                tracing::error!("Failed to change log level: {}", e);
+            }
+        }
+        let show = |value: Option<&Value>| value.map_or("unset".to_string(), Value::to_string);
+        for key in changed {
+            if RELOADABLE_KEYS.contains(&key.as_str()) {
+                let (old, new) = (self.settings.get(key), settings.get(key));
+                tracing::info!("Changed {} from {} to {}", key, show(old), show(new));
+            } else {
+                // values aren't logged, they could be secrets
+                tracing::warn!("Changed {}, which only applies after a restart", key);
+            }
+        }
+        self.settings = settings;
+    }
+}
+
+/// Modification time and size of each file, `None` for missing ones
+fn files_state(paths: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    paths
+        .iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+/// Reload the config whenever one of its files changes or the process receives SIGHUP
+pub async fn run_config_reloader_until_stopped(
+    mut reloader: ConfigReloader,
+) -> Result<(), std::io::Error> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    // the environment and the directory can't change while running, neither can the paths
+    let paths = config_paths().map_err(std::io::Error::other)?;
+    let mut state = files_state(&paths);
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => tracing::info!("Received SIGHUP"),
+            _ = tokio::time::sleep(WATCH_INTERVAL) => {
+                if files_state(&paths) == state {
+                    continue;
+                }
+                tracing::info!("Config files changed");
+            }
+        }
+        state = files_state(&paths);
+        reloader.reload().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flatten, ConfigReloader};
+    use crate::config::Config;
+    use crate::rate_limit::{InMemoryRateLimitStore, RateLimiter};
+    use crate::startup::build_email_client;
+    use actix_web::web;
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn local_sources(overrides: &[(&str, &str)]) -> config::Config {
+        let mut builder = config::Config::builder()
+            .add_source(config::File::with_name("config/base.yaml"))
+            .add_source(config::File::with_name("config/local.yaml"));
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn reloadable_settings_are_applied() {
+        let sources = local_sources(&[]);
+        let config = Config::from_sources(&sources).unwrap();
+        let store = Arc::new(InMemoryRateLimitStore::default());
+        let rate_limiter = web::Data::new(RateLimiter::new(store, &config.app.rate_limit));
+        let email_client = web::Data::new(build_email_client(&config.email_client));
+        let mut reloader = ConfigReloader::new(email_client, rate_limiter.clone(), None, &sources);
+
+        let sources = local_sources(&[
+            ("app.rate_limit.max_requests_per_ip", "1"),
+            ("app.port", "9000"),
+        ]);
+        let config = Config::from_sources(&sources).unwrap();
+        reloader.apply(&config, &sources);
+
+        let ip = "1.1.1.1".parse().unwrap();
+        assert_ok!(rate_limiter.check_ip(ip).await);
+        assert_err!(rate_limiter.check_ip(ip).await);
+        assert_eq!(reloader.settings["app.port"], json!("9000"));
+    }
+
+    #[test]
+    fn settings_are_flattened_to_dotted_keys() {
+        let mut settings = BTreeMap::new();
+        flatten(
+            json!({ "app": { "port": 8000, "cors": { "allowed_methods": ["GET"] } } }),
+            "",
+            &mut settings,
+        );
+        assert_eq!(
+            settings.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("app.cors.allowed_methods".to_string(), json!(["GET"])),
+                ("app.port".to_string(), json!(8000)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use actix_cors::Cors;
+use actix_web::http::header::HeaderName;
+use actix_web::http::Method;
+
+use crate::config::CorsConfig;
+
+/// Cross-origin policy of the public routes, checked once at startup and turned into a
+/// middleware for every route and worker; admin routes don't get one and stay same-origin only
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    /// `None` allows any origin
+    allowed_origins: Option<Vec<String>>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<HeaderName>,
+    allow_credentials: bool,
+    max_age_secs: usize,
+}
+
+/// Origins are compared to the `Origin` header as they are, so they must look exactly like one
+fn parse_origin(origin: &str) -> Result<String, String> {
+    let url =
+        reqwest::Url::parse(origin).map_err(|e| format!("Invalid origin {}: {}", origin, e))?;
+    let is_origin = matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some()
+        && url.path() == "/"
+        && url.query().is_none()
+        && !origin.ends_with('/');
+    if !is_origin {
+        return Err(format!(
+            "Invalid origin {}, expected scheme, host and optional port like https://example.com",
+            origin
+        ));
+    }
+    Ok(origin.to_string())
+}
+
+impl CorsPolicy {
+    pub fn from_config(config: &CorsConfig) -> Result<Self, String> {
+        let allowed_origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+            // browsers refuse credentials for responses allowing any origin
+            if config.allow_credentials {
+                return Err("Credentials can't be allowed for any origin".into());
+            }
+            None
+        } else {
+            let origins = config
+                .allowed_origins
+                .iter()
+                .map(|origin| parse_origin(origin));
+            Some(origins.collect::<Result<_, _>>()?)
+        };
+        let allowed_methods = config
+            .allowed_methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("Invalid method {}", method))
+            })
+            .collect::<Result<_, _>>()?;
+        let allowed_headers = config
+            .allowed_headers
+            .iter()
+            .map(|header| {
+                HeaderName::try_from(header.as_str())
+                    .map_err(|_| format!("Invalid header {}", header))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            allowed_origins,
+            allowed_methods,
+            allowed_headers,
+            allow_credentials: config.allow_credentials,
+            max_age_secs: config.max_age_secs,
+        })
+    }
+
+    /// Middleware answering preflight requests and adding CORS headers for allowed origins;
+    /// requests from other origins are still handled, for plain HTML forms on other sites, but
+    /// browsers don't let scripts read the response
+    pub fn middleware(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.allowed_methods.clone())
+            .allowed_headers(self.allowed_headers.clone())
+            .max_age(self.max_age_secs)
+            .block_on_origin_mismatch(false);
+        match &self.allowed_origins {
+            Some(origins) => {
+                for origin in origins {
+                    cors = cors.allowed_origin(origin);
+                }
+            }
+            None => cors = cors.allow_any_origin().send_wildcard(),
+        }
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CorsPolicy;
+    use crate::config::CorsConfig;
+    use claims::{assert_err, assert_ok};
+
+    fn config(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_methods: vec!["GET".into(), "post".into()],
+            allowed_headers: vec!["Content-Type".into()],
+            allow_credentials: false,
+            max_age_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn origins_must_be_scheme_host_and_port() {
+        assert_ok!(CorsPolicy::from_config(&config(&[
+            "https://example.com",
+            "http://localhost:3000"
+        ])));
+        for origin in [
+            "example.com",
+            "https://example.com/",
+            "https://example.com/signup",
+            "ftp://example.com",
+        ] {
+            assert_err!(CorsPolicy::from_config(&config(&[origin])));
+        }
+    }
+
+    #[test]
+    fn any_origin_is_allowed_without_credentials_only() {
+        assert_ok!(CorsPolicy::from_config(&config(&["*"])));
+        let config = CorsConfig {
+            allow_credentials: true,
+            ..config(&["*"])
+        };
+        assert_err!(CorsPolicy::from_config(&config));
+    }
+
+    #[test]
+    fn invalid_methods_and_headers_are_rejected() {
+        let mut with_method = config(&[]);
+        with_method.allowed_methods.push("NOT A METHOD".into());
+        let mut with_header = config(&[]);
+        with_header.allowed_headers.push("Not a header".into());
+        assert_err!(CorsPolicy::from_config(&with_method));
+        assert_err!(CorsPolicy::from_config(&with_header));
+    }
+}
@@ -0,0 +1,181 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::future::{ready, Ready};
+
+use crate::auth::{generate_token, hash_token};
+use crate::config::AuthConfig;
+
+/// Cookie carrying the token; readable from scripts, which send it back in [`CSRF_HEADER`]
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Hidden field of our forms carrying the token
+pub const CSRF_FIELD: &str = "csrf_token";
+/// Header carrying the token, for requests sent from scripts
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+const TOKEN_LENGTH: usize = 32;
+
+/// Token of the browser that sent the request, stored by [`csrf_protection`]; use it as extractor
+/// in handlers that render forms
+#[derive(Debug, Clone)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = request.extensions().get::<CsrfToken>().cloned();
+        ready(token.ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("CSRF protection isn't set up for route")
+        }))
+    }
+}
+
+/// Tokens come back from the client and end up in our HTML, so only accept ones we could have
+/// generated
+fn is_well_formed(token: &str) -> bool {
+    token.len() == TOKEN_LENGTH && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Compare hashes rather than the tokens, so that the time taken doesn't reveal the cookie
+fn tokens_match(cookie: &str, submitted: &str) -> bool {
+    hash_token(cookie) == hash_token(submitted)
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn cookie_token(request: &ServiceRequest) -> Option<String> {
+    request
+        .cookie(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| is_well_formed(token))
+}
+
+fn header_token(request: &ServiceRequest) -> Option<String> {
+    request
+        .headers()
+        .get(CSRF_HEADER)?
+        .to_str()
+        .ok()
+        .map(str::to_string)
+}
+
+/// Cookie carrying the token for the rest of the browser session
+pub fn csrf_cookie(token: String, config: &AuthConfig) -> Cookie<'static> {
+    Cookie::build(CSRF_COOKIE, token)
+        .path("/")
+        .secure(config.secure_cookies)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/// Whether a request is safe from cross-site forgery: it doesn't change state, or it sends the
+/// token of its cookie in the [`CSRF_HEADER`], which other sites can't read to copy
+pub fn has_csrf_header(request: &ServiceRequest) -> bool {
+    if is_safe(request.method()) {
+        return true;
+    }
+    match (cookie_token(request), header_token(request)) {
+        (Some(cookie), Some(header)) => tokens_match(&cookie, &header),
+        _ => false,
+    }
+}
+
+/// Token in the [`CSRF_FIELD`] of a form body; the body is put back for the handler
+async fn form_token(request: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok(None);
+    }
+    let body = request.extract::<web::Bytes>().await?;
+    // the handler reads the body we've just consumed from a stream of that single chunk
+    let stream = futures_util::stream::once(ready(Ok(body.clone())));
+    request.set_payload(Payload::Stream {
+        payload: Box::pin(stream),
+    });
+    let fields = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body).unwrap_or_default();
+    Ok(fields
+        .into_iter()
+        .find(|(name, _)| name == CSRF_FIELD)
+        .map(|(_, token)| token))
+}
+
+/// Middleware for the routes of our HTML forms, using double-submit tokens: safe requests get a
+/// token in a cookie and as [`CsrfToken`] to put into the form, and other requests are rejected
+/// with 403 unless they send the token of their cookie in the form or the [`CSRF_HEADER`]
+pub async fn csrf_protection(
+    config: web::Data<AuthConfig>,
+    mut request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let cookie = cookie_token(&request);
+    if !is_safe(request.method()) {
+        let submitted = match header_token(&request) {
+            Some(token) => Some(token),
+            None => form_token(&mut request).await?,
+        };
+        let valid = match (&cookie, &submitted) {
+            (Some(cookie), Some(submitted)) => tokens_match(cookie, submitted),
+            _ => false,
+        };
+        if !valid {
+            tracing::warn!("Rejected request without valid CSRF token");
+            let response =
+                HttpResponse::Forbidden().body("Invalid CSRF token, reload the page and try again");
+            return Ok(request.into_response(response).map_into_right_body());
+        }
+        return Ok(next.call(request).await?.map_into_left_body());
+    }
+    let (token, is_new) = match cookie {
+        Some(token) => (token, false),
+        None => (generate_token(TOKEN_LENGTH), true),
+    };
+    request.extensions_mut().insert(CsrfToken(token.clone()));
+    let mut response = next.call(request).await?;
+    if is_new {
+        response
+            .response_mut()
+            .add_cookie(&csrf_cookie(token, &config))?;
+    }
+    Ok(response.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_well_formed, tokens_match, TOKEN_LENGTH};
+    use crate::auth::generate_token;
+
+    #[test]
+    fn only_generated_tokens_are_well_formed() {
+        assert!(is_well_formed(&generate_token(TOKEN_LENGTH)));
+        assert!(!is_well_formed(""));
+        assert!(!is_well_formed(&generate_token(TOKEN_LENGTH - 1)));
+        assert!(!is_well_formed(&format!(
+            "\"><script>{}",
+            generate_token(TOKEN_LENGTH - 10)
+        )));
+    }
+
+    #[test]
+    fn tokens_must_be_equal() {
+        let token = generate_token(TOKEN_LENGTH);
+        assert!(tokens_match(&token, &token.clone()));
+        assert!(!tokens_match(&token, &generate_token(TOKEN_LENGTH)));
+    }
+}
@@ -1,7 +1,9 @@
 mod subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
 pub use subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
-pub use subscriber_name::SubscriberName;
+pub use subscriber_email::{EmailNormalization, SubscriberEmail};
+pub use subscriber_name::{NamePolicy, SubscriberName};
+pub use subscription_status::SubscriptionStatus;
@@ -1,7 +1,20 @@
-use super::subscriber_email::SubscriberEmail;
-use super::subscriber_name::SubscriberName;
+use super::subscriber_email::{EmailNormalization, SubscriberEmail};
+use super::subscriber_name::{NamePolicy, SubscriberName};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
 }
+
+impl NewSubscriber {
+    pub fn parse(
+        email: String,
+        name: String,
+        normalization: &EmailNormalization,
+        name_policy: &NamePolicy,
+    ) -> Result<NewSubscriber, String> {
+        let name = SubscriberName::parse_with(name, name_policy)?;
+        let email = SubscriberEmail::parse_with(email, normalization)?;
+        Ok(Self { email, name })
+    }
+}
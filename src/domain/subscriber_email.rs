@@ -1,29 +1,103 @@
 use validator::ValidateEmail;
 
-#[derive(Debug)]
-pub struct SubscriberEmail(String);
+/// Providers that ignore everything after a `+` in the local part, so that `ursula+news@` and
+/// `ursula@` reach the same mailbox
+const PLUS_ADDRESSING_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "outlook.com",
+    "hotmail.com",
+    "live.com",
+    "icloud.com",
+    "fastmail.com",
+    "proton.me",
+    "protonmail.com",
+];
+
+/// Providers that ignore dots in the local part
+const DOT_INSENSITIVE_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+/// Rules applied when deriving the canonical form of an email, on top of lowercasing it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailNormalization {
+    /// fold aliases of the big providers into one address, e.g. Gmail's dots and `+tags`
+    pub fold_provider_aliases: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriberEmail {
+    email: String,
+    canonical: String,
+}
 
 // expose value as immutable reference
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.email
     }
 }
 
 impl SubscriberEmail {
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if ValidateEmail::validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(format!("Invalid subscriber email: {}", s))
+        Self::parse_with(s, &EmailNormalization::default())
+    }
+
+    /// Parse an email, trimming whitespace and lowercasing its domain, with internationalized
+    /// domains converted to punycode so that they can be compared byte by byte
+    pub fn parse_with(
+        s: String,
+        normalization: &EmailNormalization,
+    ) -> Result<SubscriberEmail, String> {
+        let invalid = || format!("Invalid subscriber email: {}", s);
+        let (local, domain) = s.trim().rsplit_once('@').ok_or_else(invalid)?;
+        // also lowercases the domain
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let email = format!("{}@{}", local, domain);
+        if !ValidateEmail::validate_email(&email) {
+            return Err(invalid());
+        }
+        let canonical = canonicalize(local, &domain, normalization);
+        Ok(Self { email, canonical })
+    }
+
+    /// Form of the email used to tell whether two emails belong to the same subscriber
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// Lowercased part of the address after the `@`
+    pub fn domain(&self) -> &str {
+        // validated emails always contain an `@`, the domain can't contain one
+        let (_, domain) = self.email.rsplit_once('@').unwrap_or_default();
+        domain
+    }
+}
+
+fn canonicalize(local: &str, domain: &str, normalization: &EmailNormalization) -> String {
+    // virtually all providers treat the local part case-insensitively, although RFC 5321 allows
+    // them not to
+    let mut local = local.to_lowercase();
+    let mut domain = domain;
+    if normalization.fold_provider_aliases {
+        if PLUS_ADDRESSING_DOMAINS.contains(&domain) {
+            if let Some((mailbox, _tag)) = local.split_once('+') {
+                local = mailbox.to_string();
+            }
+        }
+        if DOT_INSENSITIVE_DOMAINS.contains(&domain) {
+            local = local.replace('.', "");
+        }
+        if domain == "googlemail.com" {
+            domain = "gmail.com";
         }
     }
+    format!("{}@{}", local, domain)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
-    use claims::assert_err;
+    use super::{EmailNormalization, SubscriberEmail};
+    use claims::{assert_err, assert_ok};
     use fake::{faker::internet::en::SafeEmail, Fake};
     use rand::rngs::StdRng;
     use rand::SeedableRng;
@@ -68,4 +142,49 @@ mod tests {
         let email = "ursula@".to_string();
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn whitespace_is_trimmed_and_domain_lowercased() {
+        let email = assert_ok!(SubscriberEmail::parse(" Ursula@Example.COM\n".to_string()));
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+        assert_eq!(email.domain(), "example.com");
+        assert_eq!(email.canonical(), "ursula@example.com");
+    }
+
+    #[test]
+    fn internationalized_domain_is_converted_to_punycode() {
+        let email = assert_ok!(SubscriberEmail::parse("ursula@bücher.DE".to_string()));
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.de");
+    }
+
+    #[test]
+    fn provider_aliases_are_folded_if_enabled() {
+        let fold = EmailNormalization {
+            fold_provider_aliases: true,
+        };
+        for email in [
+            "ursula.le.guin@gmail.com",
+            "UrsulaLeGuin+news@gmail.com",
+            "ursula.leguin+x@googlemail.com",
+        ] {
+            let email = assert_ok!(SubscriberEmail::parse_with(email.to_string(), &fold));
+            assert_eq!(email.canonical(), "ursulaleguin@gmail.com");
+        }
+    }
+
+    #[test]
+    fn provider_aliases_are_kept_by_default_and_for_other_domains() {
+        let fold = EmailNormalization {
+            fold_provider_aliases: true,
+        };
+        let email = assert_ok!(SubscriberEmail::parse(
+            "ursula.le+guin@gmail.com".to_string()
+        ));
+        assert_eq!(email.canonical(), "ursula.le+guin@gmail.com");
+        let email = assert_ok!(SubscriberEmail::parse_with(
+            "ursula.le+guin@example.com".to_string(),
+            &fold
+        ));
+        assert_eq!(email.canonical(), "ursula.le+guin@example.com");
+    }
 }
@@ -1,5 +1,35 @@
+use serde_aux::field_attributes::deserialize_number_from_string;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Characters that change the direction of the text around them, which can be used to make a
+/// name display differently from what it is
+const BIDI_CONTROLS: &[char] = &[
+    '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}', '\u{2066}', '\u{2067}', '\u{2068}',
+    '\u{2069}',
+];
+
+/// Rules names have to follow, applied after normalizing them
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct NamePolicy {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_graphemes: usize,
+    /// characters a name may not contain, e.g. to keep HTML out of names
+    pub forbidden_chars: String,
+    /// accept signups with an email only
+    pub allow_empty: bool,
+}
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        Self {
+            max_graphemes: 256,
+            forbidden_chars: r#"/()"<>\{}"#.to_string(),
+            allow_empty: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
@@ -12,22 +42,35 @@ impl AsRef<str> for SubscriberName {
 
 impl SubscriberName {
     pub fn parse(s: String) -> Result<SubscriberName, String> {
-        let is_empty_or_whitespace = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let invalid_chars = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_invalid_chars = s.chars().any(|g| invalid_chars.contains(&g));
+        Self::parse_with(s, &NamePolicy::default())
+    }
+
+    /// Parse a name, normalizing it to NFC and collapsing runs of whitespace into single spaces
+    pub fn parse_with(s: String, policy: &NamePolicy) -> Result<SubscriberName, String> {
+        let invalid = || format!("Invalid subscriber name: {}", s);
+        // compose characters, so that the same name is always stored the same way and graphemes
+        // are counted the same way no matter how the browser encoded them
+        let name = s.nfc().collect::<String>();
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
 
-        if is_empty_or_whitespace || is_too_long || contains_invalid_chars {
-            Err(format!("Invalid subscriber name: {}", s))
+        let is_empty = name.is_empty() && !policy.allow_empty;
+        let is_too_long = name.graphemes(true).count() > policy.max_graphemes;
+        // whitespace controls like tabs have been replaced by spaces already
+        let contains_invalid_chars = name.chars().any(|c| {
+            c.is_control() || BIDI_CONTROLS.contains(&c) || policy.forbidden_chars.contains(c)
+        });
+
+        if is_empty || is_too_long || contains_invalid_chars {
+            Err(invalid())
         } else {
-            Ok(Self(s))
+            Ok(Self(name))
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberName;
+    use super::{NamePolicy, SubscriberName};
     use claims::{assert_err, assert_ok};
 
     #[test]
@@ -55,9 +98,51 @@ mod tests {
             assert_err!(SubscriberName::parse(name));
         }
     }
+
     #[test]
     fn valid_name_passes() {
         let name = "Ursula Le Guin".to_string();
         assert_ok!(SubscriberName::parse(name));
     }
+
+    #[test]
+    fn name_is_normalized_to_nfc() {
+        // "e" followed by a combining acute accent
+        let name = assert_ok!(SubscriberName::parse("Rene\u{301}e".to_string()));
+        assert_eq!(name.as_ref(), "Ren\u{e9}e");
+    }
+
+    #[test]
+    fn whitespace_is_trimmed_and_collapsed() {
+        let name = assert_ok!(SubscriberName::parse(
+            " Ursula\t Le\n\u{a0}Guin ".to_string()
+        ));
+        assert_eq!(name.as_ref(), "Ursula Le Guin");
+    }
+
+    #[test]
+    fn name_with_control_or_bidi_characters_returns_error() {
+        for name in [
+            "Ursula\u{0}",
+            "Ursula\u{7f}",
+            "Ursula\u{202e}niug",
+            "\u{2066}Ursula",
+        ] {
+            assert_err!(SubscriberName::parse(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn policy_is_applied() {
+        let policy = NamePolicy {
+            max_graphemes: 5,
+            forbidden_chars: "@".to_string(),
+            allow_empty: true,
+        };
+        let name = assert_ok!(SubscriberName::parse_with(" ".to_string(), &policy));
+        assert_eq!(name.as_ref(), "");
+        assert_ok!(SubscriberName::parse_with("(ä)".to_string(), &policy));
+        assert_err!(SubscriberName::parse_with("Ursula".to_string(), &policy));
+        assert_err!(SubscriberName::parse_with("U@L".to_string(), &policy));
+    }
 }
@@ -0,0 +1,17 @@
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::domain::SubscriberEmail;
 use reqwest::Client;
 use secrecy::{ExposeSecret, SecretBox};
+use std::sync::RwLock;
+use std::time::Duration;
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -16,24 +18,35 @@ struct SendEmailRequest<'a> {
 pub struct EmailClient {
     http_client: Client,
     sender_email: SubscriberEmail,
-    base_url: String,
+    base_url: reqwest::Url,
     auth_token: SecretBox<String>,
+    /// applied per request, so that it can be changed while the app is running
+    timeout: RwLock<Duration>,
 }
 
 impl EmailClient {
+    /// Emails are sent to the `email` endpoint below the base URL, which must be http(s), e.g. to
+    /// `/postmark/email` for `https://api.example.com/postmark` with or without trailing slash
     pub fn new(
-        base_url: String,
+        base_url: reqwest::Url,
         sender_email: SubscriberEmail,
-        timeout: std::time::Duration,
+        timeout: Duration,
         auth_token: SecretBox<String>,
     ) -> Self {
         Self {
-            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            http_client: Client::new(),
             base_url,
             sender_email,
             auth_token,
+            timeout: RwLock::new(timeout),
         }
     }
+
+    /// Change the timeout of the requests sent from now on
+    pub fn set_timeout(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
     pub async fn send_email(
         &self,
         receiver_email: SubscriberEmail,
@@ -41,7 +54,13 @@ impl EmailClient {
         html_body: &str,
         text_body: &str,
     ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
+        // `join` would replace the last segment of a base path without trailing slash
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("http(s) URLs have a path")
+            .pop_if_empty()
+            .push("email");
+        let timeout = *self.timeout.read().unwrap();
         let request_body = SendEmailRequest {
             from: self.sender_email.as_ref(),
             to: receiver_email.as_ref(),
@@ -50,7 +69,8 @@ impl EmailClient {
             text_body,
         };
         self.http_client
-            .post(&url)
+            .post(url)
+            .timeout(timeout)
             .json(&request_body)
             .header("X-Postmark-Server-Token", self.auth_token.expose_secret())
             .send()
@@ -104,6 +124,7 @@ mod tests {
     }
 
     fn make_email_client(base_url: String) -> EmailClient {
+        let base_url = reqwest::Url::parse(&base_url).unwrap();
         let sender = make_email();
         let auth_token = SecretBox::new(Faker.fake());
         let timeout = std::time::Duration::from_millis(200);
@@ -194,4 +215,55 @@ mod tests {
         // assert
         assert_err!(response);
     }
+
+    #[tokio::test]
+    async fn changed_timeout_applies_to_later_requests() {
+        // arrange
+        let server = MockServer::start().await;
+        let client = make_email_client(server.uri());
+        client.set_timeout(std::time::Duration::from_secs(5));
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // act
+        let body = make_body();
+        let response = client
+            .send_email(make_email(), &make_subject(), &body, &body)
+            .await;
+
+        // assert
+        assert_ok!(response);
+    }
+
+    #[tokio::test]
+    async fn emails_are_sent_below_the_path_of_the_base_url() {
+        for base_path in ["/postmark", "/postmark/"] {
+            // arrange
+            let server = MockServer::start().await;
+            let client = make_email_client(format!("{}{}", server.uri(), base_path));
+
+            Mock::given(path("/postmark/email"))
+                .and(method("POST"))
+                .respond_with(ResponseTemplate::new(200))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            // act
+            let body = make_body();
+            let response = client
+                .send_email(make_email(), &make_subject(), &body, &body)
+                .await;
+
+            // assert
+            assert_ok!(response, "base path: {}", base_path);
+        }
+    }
 }
@@ -0,0 +1,260 @@
+use futures_util::future::BoxFuture;
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::TokioAsyncResolver;
+use sqlx::PgPool;
+use std::collections::HashSet;
+
+use crate::config::EmailPolicyConfig;
+use crate::domain::SubscriberEmail;
+
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Looks up whether a domain is set up to receive mail
+pub trait MxResolver: Send + Sync {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, ResolveError>>;
+}
+
+fn is_no_records_found(e: &ResolveError) -> Option<ResponseCode> {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => Some(*response_code),
+        _ => None,
+    }
+}
+
+pub struct DnsMxResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsMxResolver {
+    pub fn new(timeout: std::time::Duration) -> Self {
+        let (config, mut options) = match hickory_resolver::system_conf::read_system_conf() {
+            Ok(conf) => conf,
+            Err(e) => {
+                tracing::warn!("Failed to read system DNS config, using defaults: {:?}", e);
+                Default::default()
+            }
+        };
+        options.timeout = timeout;
+        options.attempts = 1;
+        Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        }
+    }
+}
+
+impl MxResolver for DnsMxResolver {
+    fn accepts_mail<'a>(&'a self, domain: &'a str) -> BoxFuture<'a, Result<bool, ResolveError>> {
+        Box::pin(async move {
+            // fully qualified, so that the search domains of the host are not tried
+            let name = format!("{}.", domain.trim_end_matches('.'));
+            match self.resolver.mx_lookup(name.as_str()).await {
+                // a single MX record pointing to the root is a "null MX", see RFC 7505
+                Ok(mx) => Ok(!mx.iter().all(|record| record.exchange().is_root())),
+                Err(e) => match is_no_records_found(&e) {
+                    Some(ResponseCode::NXDomain) => Ok(false),
+                    // without MX records, mail goes to the address records of the domain itself,
+                    // see RFC 5321
+                    Some(_) => match self.resolver.lookup_ip(name.as_str()).await {
+                        Ok(_) => Ok(true),
+                        Err(e) if is_no_records_found(&e).is_some() => Ok(false),
+                        Err(e) => Err(e),
+                    },
+                    None => Err(e),
+                },
+            }
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockedKind {
+    Domain,
+    Address,
+}
+
+impl BlockedKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlockedKind::Domain => "domain",
+            BlockedKind::Address => "address",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EmailPolicyError {
+    Rejected(&'static str),
+    Unexpected(sqlx::Error),
+}
+
+/// The domain followed by all its parent domains, e.g. `a.example.com`, `example.com`, `com`
+fn domain_and_parents(domain: &str) -> Vec<&str> {
+    let mut domains = vec![domain];
+    let mut rest = domain;
+    while let Some((_, parent)) = rest.split_once('.') {
+        domains.push(parent);
+        rest = parent;
+    }
+    domains
+}
+
+/// Whether the address or its domain, including any parent domain, is on the admin blocklist;
+/// addresses are compared in their canonical form, the one they are blocked in
+#[tracing::instrument(name = "Check email blocklist", skip(db_pool, email))]
+pub async fn is_blocked(db_pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let domains = domain_and_parents(email.domain())
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let result = sqlx::query!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM blocked_emails
+            WHERE (kind = 'address' AND value = $1) OR (kind = 'domain' AND value = ANY($2))
+        ) AS "blocked!"
+        "#,
+        email.canonical(),
+        &domains,
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query email blocklist: {:?}", e);
+        e
+    })?;
+    Ok(result.blocked)
+}
+
+/// Rules on which addresses we accept subscriptions from, on top of their syntax
+pub struct EmailPolicy {
+    disposable_domains: HashSet<&'static str>,
+    mx_resolver: Option<Box<dyn MxResolver>>,
+}
+
+impl EmailPolicy {
+    pub fn new(block_disposable_domains: bool, mx_resolver: Option<Box<dyn MxResolver>>) -> Self {
+        let disposable_domains = if block_disposable_domains {
+            DISPOSABLE_DOMAINS
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        Self {
+            disposable_domains,
+            mx_resolver,
+        }
+    }
+
+    pub fn from_config(config: &EmailPolicyConfig) -> Self {
+        let mx_resolver = config
+            .check_mx
+            .then(|| Box::new(DnsMxResolver::new(config.mx_timeout)) as Box<dyn MxResolver>);
+        Self::new(config.block_disposable_domains, mx_resolver)
+    }
+
+    pub async fn check(
+        &self,
+        db_pool: &PgPool,
+        email: &SubscriberEmail,
+    ) -> Result<(), EmailPolicyError> {
+        if is_blocked(db_pool, email)
+            .await
+            .map_err(EmailPolicyError::Unexpected)?
+        {
+            return Err(EmailPolicyError::Rejected("address is blocked"));
+        }
+        self.check_domain(email.domain()).await
+    }
+
+    async fn check_domain(&self, domain: &str) -> Result<(), EmailPolicyError> {
+        if domain_and_parents(domain)
+            .iter()
+            .any(|d| self.disposable_domains.contains(d))
+        {
+            return Err(EmailPolicyError::Rejected("disposable email domain"));
+        }
+        if let Some(mx_resolver) = &self.mx_resolver {
+            match mx_resolver.accepts_mail(domain).await {
+                Ok(true) => {}
+                Ok(false) => return Err(EmailPolicyError::Rejected("domain does not accept mail")),
+                // DNS hiccups shouldn't keep real people from subscribing, the confirmation
+                // email will bounce if the domain is really broken
+                Err(e) => tracing::warn!("Failed to look up MX records of {}: {:?}", domain, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{domain_and_parents, EmailPolicy, EmailPolicyError, MxResolver};
+    use claims::{assert_matches, assert_ok};
+    use futures_util::future::BoxFuture;
+    use hickory_resolver::error::ResolveError;
+
+    /// Resolver answering from a fixed list of domains that accept mail
+    struct FakeMxResolver(Result<Vec<&'static str>, &'static str>);
+
+    impl MxResolver for FakeMxResolver {
+        fn accepts_mail<'a>(
+            &'a self,
+            domain: &'a str,
+        ) -> BoxFuture<'a, Result<bool, ResolveError>> {
+            let result = match &self.0 {
+                Ok(domains) => Ok(domains.contains(&domain)),
+                Err(e) => Err(ResolveError::from(*e)),
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    #[test]
+    fn parents_include_all_levels() {
+        assert_eq!(
+            domain_and_parents("a.example.com"),
+            vec!["a.example.com", "example.com", "com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = EmailPolicy::new(true, None);
+        for domain in ["mailinator.com", "eu.mailinator.com"] {
+            assert_matches!(
+                policy.check_domain(domain).await,
+                Err(EmailPolicyError::Rejected(_))
+            );
+        }
+        assert_ok!(policy.check_domain("gmail.com").await);
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_pass_if_not_blocked() {
+        let policy = EmailPolicy::new(false, None);
+        assert_ok!(policy.check_domain("mailinator.com").await);
+    }
+
+    #[tokio::test]
+    async fn domains_without_mx_are_rejected() {
+        let resolver = FakeMxResolver(Ok(vec!["gmail.com"]));
+        let policy = EmailPolicy::new(true, Some(Box::new(resolver)));
+        assert_ok!(policy.check_domain("gmail.com").await);
+        assert_matches!(
+            policy.check_domain("gmial.com").await,
+            Err(EmailPolicyError::Rejected(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn resolver_errors_let_the_email_pass() {
+        let resolver = FakeMxResolver(Err("timed out"));
+        let policy = EmailPolicy::new(true, Some(Box::new(resolver)));
+        assert_ok!(policy.check_domain("gmail.com").await);
+    }
+}
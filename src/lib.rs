@@ -1,6 +1,24 @@
+pub mod admin_users;
+pub mod api_keys;
+pub mod audit_log;
+pub mod auth;
+pub mod bot_protection;
+pub mod cleanup_worker;
+pub mod client_ip;
 pub mod config;
+pub mod config_reload;
+pub mod cors;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod email_policy;
+pub mod rate_limit;
 pub mod routes;
+pub mod secrets;
+pub mod security_headers;
 pub mod startup;
+pub mod subscriber_import;
+pub mod subscription_events;
 pub mod telemetry;
+pub mod webhook_client;
+pub mod webhooks;
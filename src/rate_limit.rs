@@ -0,0 +1,307 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::client_ip::ClientIp;
+use crate::config::{RateLimitConfig, RateLimitStoreKind};
+
+/// Storage of request counters per key and fixed time window; the Postgres store shares the
+/// counters between all instances of the app, the in-memory store only within one instance
+pub trait RateLimitStore: Send + Sync {
+    /// Count a hit for `key` in the window starting at `window_start`, returning the number of
+    /// hits in that window so far, including this one
+    fn hit<'a>(
+        &'a self,
+        key: &'a str,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, sqlx::Error>>;
+}
+
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    by_key: HashMap<String, (DateTime<Utc>, u32)>,
+    /// window in which counters of past windows were last dropped
+    swept_window: Option<DateTime<Utc>>,
+}
+
+impl RateLimitStore for InMemoryRateLimitStore {
+    fn hit<'a>(
+        &'a self,
+        key: &'a str,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, sqlx::Error>> {
+        let mut counters = self.counters.lock().unwrap();
+        // drop counters of past windows so that memory doesn't grow with every client we've seen,
+        // once per window rather than on every hit
+        if counters.swept_window < Some(window_start) {
+            counters
+                .by_key
+                .retain(|_, (start, _)| *start >= window_start);
+            counters.swept_window = Some(window_start);
+        }
+        let (start, hits) = counters
+            .by_key
+            .entry(key.to_string())
+            .or_insert((window_start, 0));
+        *start = window_start;
+        *hits += 1;
+        let hits = *hits;
+        Box::pin(async move { Ok(hits) })
+    }
+}
+
+pub struct PostgresRateLimitStore {
+    db_pool: PgPool,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool }
+    }
+}
+
+impl RateLimitStore for PostgresRateLimitStore {
+    fn hit<'a>(
+        &'a self,
+        key: &'a str,
+        window_start: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<u32, sqlx::Error>> {
+        Box::pin(async move {
+            let result = sqlx::query!(
+                r#"
+                INSERT INTO rate_limits (key, window_start, hits)
+                VALUES ($1, $2, 1)
+                ON CONFLICT (key) DO UPDATE SET
+                    hits = CASE
+                        WHEN rate_limits.window_start = EXCLUDED.window_start
+                        THEN rate_limits.hits + 1
+                        ELSE 1
+                    END,
+                    window_start = EXCLUDED.window_start
+                RETURNING hits
+                "#,
+                key,
+                window_start,
+            )
+            .fetch_one(&self.db_pool)
+            .await?;
+            Ok(result.hits as u32)
+        })
+    }
+}
+
+/// Delete counters of windows that ended before `cutoff`, returns the number of deleted rows
+pub async fn purge_rate_limits(
+    db_pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(r#"DELETE FROM rate_limits WHERE window_start < $1"#, cutoff)
+        .execute(db_pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Request was rejected, the client may retry after the given number of seconds
+#[derive(Debug, PartialEq, Eq)]
+pub struct RateLimited {
+    pub retry_after_secs: i64,
+}
+
+impl RateLimited {
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, self.retry_after_secs.to_string()))
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    window: TimeDelta,
+    max_requests_per_ip: u32,
+    max_requests_per_email: u32,
+}
+
+impl Limits {
+    fn from_config(config: &RateLimitConfig) -> Self {
+        Self {
+            window: TimeDelta::from_std(config.window)
+                .expect("window is bounded by the config validation"),
+            max_requests_per_ip: config.max_requests_per_ip,
+            max_requests_per_email: config.max_requests_per_email,
+        }
+    }
+}
+
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    /// swapped as a whole, so that a check never sees a mix of old and new limits
+    limits: RwLock<Limits>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, config: &RateLimitConfig) -> Self {
+        Self {
+            store,
+            limits: RwLock::new(Limits::from_config(config)),
+        }
+    }
+
+    /// Apply the window and limits of the config from now on; the store is kept
+    pub fn set_limits(&self, config: &RateLimitConfig) {
+        *self.limits.write().unwrap() = Limits::from_config(config);
+    }
+
+    pub fn from_config(config: &RateLimitConfig, db_pool: PgPool) -> Self {
+        let store: Arc<dyn RateLimitStore> = match config.store {
+            RateLimitStoreKind::Memory => Arc::new(InMemoryRateLimitStore::default()),
+            RateLimitStoreKind::Postgres => Arc::new(PostgresRateLimitStore::new(db_pool)),
+        };
+        Self::new(store, config)
+    }
+
+    pub async fn check_ip(&self, ip: IpAddr) -> Result<(), RateLimited> {
+        self.check(&format!("ip:{}", ip), |limits| limits.max_requests_per_ip)
+            .await
+    }
+
+    pub async fn check_email(&self, email: &str) -> Result<(), RateLimited> {
+        self.check(&format!("email:{}", email.to_lowercase()), |limits| {
+            limits.max_requests_per_email
+        })
+        .await
+    }
+
+    async fn check(&self, key: &str, max_requests: fn(&Limits) -> u32) -> Result<(), RateLimited> {
+        let limits = *self.limits.read().unwrap();
+        let max_requests = max_requests(&limits);
+        let now = Utc::now();
+        let window_secs = limits.window.num_seconds().max(1);
+        let window_start =
+            DateTime::from_timestamp(now.timestamp() - now.timestamp() % window_secs, 0)
+                .expect("window start is a valid timestamp");
+        match self.store.hit(key, window_start).await {
+            Ok(hits) if hits > max_requests => Err(RateLimited {
+                retry_after_secs: (window_start + limits.window - now).num_seconds().max(1),
+            }),
+            Ok(_) => Ok(()),
+            // we'd rather let a few requests too many through than reject everyone while the
+            // store is unavailable
+            Err(e) => {
+                tracing::error!("Failed to check rate limit: {:?}", e);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Middleware rejecting requests from clients that exceeded their limit with 429
+pub async fn rate_limit_by_ip(
+    rate_limiter: web::Data<RateLimiter>,
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if let Some(ip) = ClientIp::of(request.request()) {
+        if let Err(limited) = rate_limiter.check_ip(ip).await {
+            tracing::warn!("Rate limit exceeded for IP: {}", ip);
+            return Ok(request
+                .into_response(limited.into_response())
+                .map_into_right_body());
+        }
+    }
+    Ok(next.call(request).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryRateLimitStore, RateLimitStore, RateLimiter};
+    use crate::config::{RateLimitConfig, RateLimitStoreKind};
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err, assert_ok};
+    use std::sync::Arc;
+
+    fn make_rate_limiter(max_requests: u32) -> RateLimiter {
+        let config = RateLimitConfig {
+            store: RateLimitStoreKind::Memory,
+            window: std::time::Duration::from_secs(3600),
+            max_requests_per_ip: max_requests,
+            max_requests_per_email: max_requests,
+            trusted_proxies: vec![],
+        };
+        RateLimiter::new(Arc::new(InMemoryRateLimitStore::default()), &config)
+    }
+
+    #[tokio::test]
+    async fn requests_within_limit_pass() {
+        let rate_limiter = make_rate_limiter(2);
+        let ip = "1.1.1.1".parse().unwrap();
+        assert_ok!(rate_limiter.check_ip(ip).await);
+        assert_ok!(rate_limiter.check_ip(ip).await);
+    }
+
+    #[tokio::test]
+    async fn requests_over_limit_are_rejected_with_retry_after() {
+        let rate_limiter = make_rate_limiter(1);
+        let ip = "1.1.1.1".parse().unwrap();
+        assert_ok!(rate_limiter.check_ip(ip).await);
+        let limited = assert_err!(rate_limiter.check_ip(ip).await);
+        assert!((1..=3600).contains(&limited.retry_after_secs));
+    }
+
+    #[tokio::test]
+    async fn keys_are_limited_independently() {
+        let rate_limiter = make_rate_limiter(1);
+        assert_ok!(rate_limiter.check_ip("1.1.1.1".parse().unwrap()).await);
+        assert_ok!(rate_limiter.check_ip("2.2.2.2".parse().unwrap()).await);
+        assert_ok!(rate_limiter.check_email("ursula@gmail.com").await);
+        assert_err!(rate_limiter.check_email("Ursula@gmail.com").await);
+    }
+
+    #[tokio::test]
+    async fn in_memory_counters_of_past_windows_are_dropped_in_the_next_window() {
+        let store = InMemoryRateLimitStore::default();
+        let window_start = Utc::now();
+        assert_eq!(assert_ok!(store.hit("a", window_start).await), 1);
+        assert_eq!(assert_ok!(store.hit("b", window_start).await), 1);
+        assert_eq!(assert_ok!(store.hit("a", window_start).await), 2);
+
+        let next_window_start = window_start + TimeDelta::hours(1);
+        assert_eq!(assert_ok!(store.hit("a", next_window_start).await), 1);
+
+        let counters = store.counters.lock().unwrap();
+        assert_eq!(counters.by_key.len(), 1);
+        assert!(counters.by_key.contains_key("a"));
+    }
+
+    #[tokio::test]
+    async fn changed_limits_apply_to_later_requests() {
+        let rate_limiter = make_rate_limiter(1);
+        let ip = "1.1.1.1".parse().unwrap();
+        assert_ok!(rate_limiter.check_ip(ip).await);
+        assert_err!(rate_limiter.check_ip(ip).await);
+
+        rate_limiter.set_limits(&RateLimitConfig {
+            store: RateLimitStoreKind::Memory,
+            window: std::time::Duration::from_secs(3600),
+            max_requests_per_ip: 3,
+            max_requests_per_email: 1,
+            trusted_proxies: vec![],
+        });
+
+        assert_ok!(rate_limiter.check_ip(ip).await);
+        assert_err!(rate_limiter.check_ip(ip).await);
+    }
+}
@@ -0,0 +1,245 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::admin_users::get_admin_user;
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::{
+    confirm_totp_enrollment, disable_totp, provisioning_uri, qr_code_svg, replace_recovery_codes,
+    start_totp_enrollment, verify_second_factor, Principal, TwoFactorError,
+};
+use crate::config::AuthConfig;
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct TotpCode {
+    /// current code of the authenticator app; recovery codes are accepted too, except when
+    /// confirming the enrollment
+    code: String,
+}
+
+/// Secret to add to an authenticator app, either typed in or scanned as QR code
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct TotpEnrollment {
+    secret: String,
+    provisioning_uri: String,
+    qr_code_svg: String,
+}
+
+/// Single-use codes to log in without the authenticator app, only shown once
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+/// Two-factor authentication is set up by admin users for themselves, API keys don't have any
+fn user_id(principal: &Principal) -> Result<Uuid, Box<HttpResponse>> {
+    match principal {
+        Principal::User { id, .. } => Ok(*id),
+        Principal::ApiKey { .. } => Err(Box::new(
+            HttpResponse::Forbidden().body("Only admin users can set up two-factor authentication"),
+        )),
+    }
+}
+
+fn two_factor_error_response(e: TwoFactorError) -> HttpResponse {
+    match e {
+        TwoFactorError::InvalidCode => HttpResponse::BadRequest().body("Invalid code"),
+        TwoFactorError::AlreadyEnabled => {
+            HttpResponse::Conflict().body("Two-factor authentication is already enabled")
+        }
+        TwoFactorError::NotEnabled => {
+            HttpResponse::Conflict().body("Two-factor authentication is not enabled")
+        }
+        TwoFactorError::Unexpected(e) => {
+            tracing::error!("Failed to update two-factor authentication: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/account/totp",
+    tag = "admin",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Secret to confirm with a code", body = TotpEnrollment),
+        (status = 403, description = "Not logged in as admin user"),
+        (status = 409, description = "Two-factor authentication is already enabled")
+    )
+)]
+#[tracing::instrument(name = "Start TOTP enrollment", skip_all)]
+pub async fn post_totp_enrollment(
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = match user_id(&principal) {
+        Ok(id) => id,
+        Err(response) => return *response,
+    };
+    let user = match get_admin_user(&**db_pool, id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(e) => {
+            tracing::error!("Failed to get admin user: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let secret = match start_totp_enrollment(&**db_pool, id).await {
+        Ok(secret) => secret,
+        Err(e) => return two_factor_error_response(e),
+    };
+    let provisioning_uri = provisioning_uri(&config.totp_issuer, &user.email, &secret);
+    match qr_code_svg(&provisioning_uri) {
+        Ok(qr_code_svg) => HttpResponse::Ok().json(TotpEnrollment {
+            secret,
+            provisioning_uri,
+            qr_code_svg,
+        }),
+        Err(e) => {
+            tracing::error!("Failed to render QR code: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/account/totp/confirm",
+    tag = "admin",
+    security(("api_key" = [])),
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "Two-factor authentication is enabled", body = RecoveryCodes),
+        (status = 400, description = "Invalid code"),
+        (status = 409, description = "Enrollment wasn't started or is already confirmed")
+    )
+)]
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip_all)]
+pub async fn confirm_totp(
+    body: web::Json<TotpCode>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = match user_id(&principal) {
+        Ok(id) => id,
+        Err(response) => return *response,
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let recovery_codes = confirm_totp_enrollment(&mut transaction, id, &body.code).await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::UserTotpEnabled,
+            id,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, TwoFactorError>(recovery_codes)
+    }
+    .await;
+    match result {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodes { recovery_codes }),
+        Err(e) => two_factor_error_response(e),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/account/totp/recovery_codes",
+    tag = "admin",
+    security(("api_key" = [])),
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "New recovery codes, replacing the previous ones", body = RecoveryCodes),
+        (status = 400, description = "Invalid code or two-factor authentication isn't enabled")
+    )
+)]
+#[tracing::instrument(name = "Replace recovery codes", skip_all)]
+pub async fn post_recovery_codes(
+    body: web::Json<TotpCode>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = match user_id(&principal) {
+        Ok(id) => id,
+        Err(response) => return *response,
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        if !verify_second_factor(&mut transaction, id, &body.code).await? {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        let recovery_codes = replace_recovery_codes(&mut transaction, id).await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::UserRecoveryCodesReplaced,
+            id,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(recovery_codes)
+    }
+    .await;
+    match result {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodes { recovery_codes }),
+        Err(e) => two_factor_error_response(e),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/account/totp",
+    tag = "admin",
+    security(("api_key" = [])),
+    request_body = TotpCode,
+    responses(
+        (status = 204, description = "Two-factor authentication is disabled"),
+        (status = 400, description = "Invalid code or two-factor authentication isn't enabled"),
+        (status = 409, description = "The role of the user requires two-factor authentication")
+    )
+)]
+#[tracing::instrument(name = "Disable TOTP", skip_all)]
+pub async fn delete_totp(
+    body: web::Json<TotpCode>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = match user_id(&principal) {
+        Ok(id) => id,
+        Err(response) => return *response,
+    };
+    if let Principal::User { role, .. } = principal {
+        if config.totp_required(role) {
+            return HttpResponse::Conflict().body("Your role requires two-factor authentication");
+        }
+    }
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        if !verify_second_factor(&mut transaction, id, &body.code).await? {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        disable_totp(&mut transaction, id).await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::UserTotpDisabled,
+            id,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => two_factor_error_response(e),
+    }
+}
@@ -0,0 +1,136 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api_keys::{create_api_key, list_api_keys, revoke_api_key, ApiKey};
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::{Principal, Scope};
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct NewApiKey {
+    /// what the key is used for, e.g. "crm"
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+/// Created key, the only time the key itself is shown
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api_keys",
+    tag = "admin",
+    security(("api_key" = ["api_keys:manage"])),
+    responses((status = 200, description = "All API keys, including revoked ones", body = [ApiKey]))
+)]
+#[tracing::instrument(name = "List API keys", skip(db_pool))]
+pub async fn get_api_keys(db_pool: web::Data<PgPool>) -> HttpResponse {
+    match list_api_keys(&db_pool).await {
+        Ok(api_keys) => HttpResponse::Ok().json(api_keys),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api_keys",
+    tag = "admin",
+    security(("api_key" = ["api_keys:manage"])),
+    request_body = NewApiKey,
+    responses(
+        (status = 201, description = "Key is created", body = CreatedApiKey),
+        (status = 400, description = "Missing name or scopes"),
+        (status = 403, description = "Scopes the caller doesn't have themselves")
+    )
+)]
+#[tracing::instrument(name = "Create API key", skip(db_pool, principal))]
+pub async fn post_api_key(
+    new_key: web::Json<NewApiKey>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let name = new_key.name.trim();
+    if name.is_empty() || new_key.scopes.is_empty() {
+        return HttpResponse::BadRequest().body("API keys need a name and at least one scope");
+    }
+    if let Some(scope) = new_key.scopes.iter().find(|s| !principal.has_scope(**s)) {
+        return HttpResponse::Forbidden().body(format!("Missing scope: {}", scope.as_str()));
+    }
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let (api_key, key) = create_api_key(&mut *transaction, name, &new_key.scopes).await?;
+        let scopes = api_key
+            .scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>();
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::ApiKeyCreated,
+            api_key.id,
+            Some(&format!("{}: {}", api_key.name, scopes.join(" "))),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>((api_key, key))
+    }
+    .await;
+    match result {
+        Ok((api_key, key)) => HttpResponse::Created().json(CreatedApiKey { key, api_key }),
+        Err(e) => {
+            tracing::error!("Failed to create API key: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/api_keys/{id}",
+    tag = "admin",
+    security(("api_key" = ["api_keys:manage"])),
+    params(("id" = Uuid, Path, description = "API key id")),
+    responses(
+        (status = 204, description = "Key is revoked"),
+        (status = 404, description = "Unknown or already revoked key")
+    )
+)]
+#[tracing::instrument(name = "Revoke API key", skip(db_pool, principal))]
+pub async fn delete_api_key(
+    id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        if !revoke_api_key(&mut *transaction, id).await? {
+            return Ok(false);
+        }
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::ApiKeyRevoked,
+            id,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to revoke API key: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::Principal;
+use crate::config::SubscriptionsConfig;
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::email_policy::BlockedKind;
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct BlockedEntry {
+    value: String,
+    kind: String,
+    reason: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// Domain or address to block; values containing an `@` are addresses, anything else a domain
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct NewBlockedEntry {
+    value: String,
+    reason: Option<String>,
+}
+
+impl NewBlockedEntry {
+    /// Addresses are stored in the same canonical form as the emails of subscribers, so that
+    /// blocking one also blocks its aliases
+    fn parse(&self, normalization: &EmailNormalization) -> Result<(String, BlockedKind), String> {
+        let value = self.value.trim().to_lowercase();
+        if value.contains('@') {
+            let email = SubscriberEmail::parse_with(value, normalization)?;
+            return Ok((email.canonical().to_string(), BlockedKind::Address));
+        }
+        let domain = value.trim_end_matches('.');
+        let is_valid = !domain.is_empty()
+            && domain
+                .split('.')
+                .all(|label| !label.is_empty() && label.chars().all(is_domain_char));
+        if is_valid {
+            Ok((domain.to_string(), BlockedKind::Domain))
+        } else {
+            Err(format!("Invalid domain: {}", self.value))
+        }
+    }
+}
+
+fn is_domain_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-'
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/blocklist",
+    tag = "admin",
+    security(("api_key" = ["blocklist:manage"])),
+    responses((status = 200, description = "Blocked domains and addresses", body = [BlockedEntry]))
+)]
+#[tracing::instrument(name = "List blocked emails", skip(db_pool))]
+pub async fn list_blocked_emails(db_pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        BlockedEntry,
+        r#"SELECT value, kind, reason, created_at FROM blocked_emails ORDER BY value"#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await;
+    match result {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            tracing::error!("Failed to list blocked emails: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/blocklist",
+    tag = "admin",
+    security(("api_key" = ["blocklist:manage"])),
+    request_body = NewBlockedEntry,
+    responses(
+        (status = 200, description = "Domain or address is blocked", body = BlockedEntry),
+        (status = 400, description = "Invalid domain or address")
+    )
+)]
+#[tracing::instrument(name = "Block email", skip(db_pool, config, principal))]
+pub async fn block_email(
+    entry: web::Json<NewBlockedEntry>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<SubscriptionsConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let (value, kind) = match entry.parse(&config.email_normalization()) {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let blocked = sqlx::query_as!(
+            BlockedEntry,
+            r#"
+            INSERT INTO blocked_emails (value, kind, reason)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (value) DO UPDATE SET reason = EXCLUDED.reason
+            RETURNING value, kind, reason, created_at
+            "#,
+            value,
+            kind.as_str(),
+            entry.reason.as_deref(),
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::BlocklistEntryAdded,
+            &blocked.value,
+            blocked.reason.as_deref(),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(blocked)
+    }
+    .await;
+    match result {
+        Ok(entry) => HttpResponse::Ok().json(entry),
+        Err(e) => {
+            tracing::error!("Failed to block email: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/blocklist/{value}",
+    tag = "admin",
+    security(("api_key" = ["blocklist:manage"])),
+    params(("value" = String, Path, description = "Blocked domain or address")),
+    responses(
+        (status = 204, description = "Domain or address is unblocked"),
+        (status = 404, description = "Domain or address was not blocked")
+    )
+)]
+#[tracing::instrument(name = "Unblock email", skip(db_pool, principal))]
+pub async fn unblock_email(
+    value: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let value = value.to_lowercase();
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let deleted = sqlx::query!(r#"DELETE FROM blocked_emails WHERE value = $1"#, value)
+            .execute(&mut *transaction)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Ok(false);
+        }
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::BlocklistEntryRemoved,
+            &value,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to unblock email: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewBlockedEntry;
+    use crate::domain::EmailNormalization;
+    use crate::email_policy::BlockedKind;
+    use claims::{assert_err, assert_ok};
+
+    fn entry(value: &str) -> NewBlockedEntry {
+        NewBlockedEntry {
+            value: value.to_string(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn values_with_at_are_addresses() {
+        let (value, kind) =
+            assert_ok!(entry(" Spammer@Example.com").parse(&EmailNormalization::default()));
+        assert_eq!(value, "spammer@example.com");
+        assert_eq!(kind, BlockedKind::Address);
+    }
+
+    #[test]
+    fn addresses_are_stored_in_their_canonical_form() {
+        let fold = EmailNormalization {
+            fold_provider_aliases: true,
+        };
+        let (value, _) = assert_ok!(entry("U.rsula+news@GoogleMail.com").parse(&fold));
+        assert_eq!(value, "ursula@gmail.com");
+    }
+
+    #[test]
+    fn values_without_at_are_domains() {
+        let (value, kind) = assert_ok!(entry("Example.com.").parse(&EmailNormalization::default()));
+        assert_eq!(value, "example.com");
+        assert_eq!(kind, BlockedKind::Domain);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for value in [
+            "",
+            "example..com",
+            "exa mple.com",
+            "@example.com",
+            "example/com",
+        ] {
+            assert_err!(entry(value).parse(&EmailNormalization::default()));
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::{delete_login_failures, list_login_failures, LoginFailures, Principal};
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+pub struct LockoutsQuery {
+    /// also list emails and IPs with failed logins that aren't locked out, false by default
+    all: Option<bool>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lockouts",
+    tag = "admin",
+    security(("api_key" = ["users:manage"])),
+    params(LockoutsQuery),
+    responses((
+        status = 200,
+        description = "Emails and IPs locked out after failed logins, most recent failure first",
+        body = [LoginFailures]
+    ))
+)]
+#[tracing::instrument(name = "List lockouts", skip(db_pool))]
+pub async fn get_lockouts(
+    query: web::Query<LockoutsQuery>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match list_login_failures(&db_pool, !query.all.unwrap_or(false)).await {
+        Ok(lockouts) => HttpResponse::Ok().json(lockouts),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/lockouts/{id}",
+    tag = "admin",
+    security(("api_key" = ["users:manage"])),
+    params(("id" = Uuid, Path, description = "Lockout id")),
+    responses(
+        (status = 204, description = "Lockout is lifted and its failed logins forgotten"),
+        (status = 404, description = "Unknown lockout")
+    )
+)]
+#[tracing::instrument(name = "Clear lockout", skip(db_pool, principal))]
+pub async fn delete_lockout(
+    id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(lockout) = delete_login_failures(&mut *transaction, id).await? else {
+            return Ok(false);
+        };
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::LockoutCleared,
+            id,
+            Some(&format!("{} {}", lockout.kind, lockout.key)),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to clear lockout: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
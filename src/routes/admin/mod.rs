@@ -0,0 +1,19 @@
+mod account;
+mod api_keys;
+mod blocklist;
+mod lockouts;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
+mod users;
+mod webhooks;
+
+pub use account::*;
+pub use api_keys::*;
+pub use blocklist::*;
+pub use lockouts::*;
+pub use subscribers::*;
+pub use subscribers_export::*;
+pub use subscribers_import::*;
+pub use users::*;
+pub use webhooks::*;
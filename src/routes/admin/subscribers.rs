@@ -0,0 +1,424 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, QueryBuilder};
+use uuid::Uuid;
+
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::Principal;
+use crate::config::SubscriptionsConfig;
+use crate::domain::{SubscriberName, SubscriptionStatus};
+use crate::subscription_events::{
+    record_subscription_event, ConsentContext, SubscriptionEventKind,
+};
+
+/// Form source recorded with changes made by admins
+const ADMIN_FORM_SOURCE: &str = "admin";
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParameters {
+    status: Option<SubscriptionStatus>,
+    tag: Option<String>,
+    /// case-insensitive substring of the email
+    email: Option<String>,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<i64>,
+    /// opaque cursor returned as `next_cursor` by the previous page
+    after: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct SubscriberPage {
+    subscribers: Vec<Subscriber>,
+    next_cursor: Option<String>,
+}
+
+/// Position of a subscriber in the listing, encoded as `<subscribed_at micros>_<id>`
+#[derive(Debug)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn parse(s: &str) -> Result<Cursor, String> {
+        let invalid = || format!("Invalid cursor: {}", s);
+        let (micros, id) = s.split_once('_').ok_or_else(invalid)?;
+        let micros = micros.parse::<i64>().map_err(|_| invalid())?;
+        let subscribed_at = DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?;
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+        Ok(Cursor { subscribed_at, id })
+    }
+
+    fn encode(subscriber: &Subscriber) -> String {
+        format!(
+            "{}_{}",
+            subscriber.subscribed_at.timestamp_micros(),
+            subscriber.id
+        )
+    }
+}
+
+/// Escape the wildcards of a LIKE pattern so that user input only matches literally
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers",
+    tag = "admin",
+    security(("api_key" = ["subscribers:read"])),
+    params(ListParameters),
+    responses(
+        (status = 200, description = "Page of subscribers", body = SubscriberPage),
+        (status = 400, description = "Invalid limit or cursor")
+    )
+)]
+#[tracing::instrument(name = "List subscribers", skip(db_pool))]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let limit = parameters.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return HttpResponse::BadRequest()
+            .body(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+    let cursor = match parameters.after.as_deref().map(Cursor::parse).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let mut query =
+        QueryBuilder::new("SELECT id, email, name, status, subscribed_at, tags FROM subscriptions");
+    query.push(" WHERE TRUE");
+    if let Some(status) = parameters.status {
+        query
+            .push(" AND status = ")
+            .push_bind(status.as_str().to_string());
+    }
+    if let Some(tag) = &parameters.tag {
+        query
+            .push(" AND ")
+            .push_bind(tag.clone())
+            .push(" = ANY(tags)");
+    }
+    if let Some(email) = &parameters.email {
+        query
+            .push(" AND email ILIKE ")
+            .push_bind(format!("%{}%", escape_like(email)));
+    }
+    let (comparison, direction) = match parameters.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(cursor) = cursor {
+        query
+            .push(format!(" AND (subscribed_at, id) {} (", comparison))
+            .push_bind(cursor.subscribed_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    // fetch one more row than requested to know if there is a next page
+    query
+        .push(format!(
+            " ORDER BY subscribed_at {0}, id {0} LIMIT ",
+            direction
+        ))
+        .push_bind(limit + 1);
+
+    let mut subscribers = match query
+        .build_query_as::<Subscriber>()
+        .fetch_all(db_pool.get_ref())
+        .await
+    {
+        Ok(subscribers) => subscribers,
+        Err(e) => {
+            tracing::error!("Failed to list subscribers: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(Cursor::encode)
+    } else {
+        None
+    };
+    HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/{id}",
+    tag = "admin",
+    security(("api_key" = ["subscribers:read"])),
+    params(("id" = Uuid, Path, description = "Subscriber id")),
+    responses(
+        (status = 200, description = "Subscriber", body = Subscriber),
+        (status = 404, description = "Unknown subscriber")
+    )
+)]
+#[tracing::instrument(name = "Get subscriber", skip(db_pool))]
+pub async fn get_subscriber(id: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at, tags FROM subscriptions
+        WHERE id = $1
+        "#,
+        id.into_inner(),
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await;
+    match result {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to get subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Fields of a subscriber that admins can change; missing fields are left unchanged
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct SubscriberUpdate {
+    name: Option<String>,
+    /// changes are recorded in the audit log, and confirmations as consent events
+    status: Option<SubscriptionStatus>,
+    tags: Option<Vec<String>>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/admin/subscribers/{id}",
+    tag = "admin",
+    security(("api_key" = ["subscribers:write"])),
+    params(("id" = Uuid, Path, description = "Subscriber id")),
+    request_body = SubscriberUpdate,
+    responses(
+        (status = 200, description = "Updated subscriber", body = Subscriber),
+        (status = 400, description = "Invalid name"),
+        (status = 404, description = "Unknown subscriber")
+    )
+)]
+#[tracing::instrument(name = "Update subscriber", skip(request, db_pool, config, principal))]
+pub async fn update_subscriber(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    update: web::Json<SubscriberUpdate>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<SubscriptionsConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let update = update.into_inner();
+    let name = match update
+        .name
+        .map(|name| SubscriberName::parse_with(name, config.name_policy_for(None)))
+        .transpose()
+    {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let consent = ConsentContext::from_request(
+        &request,
+        Some(ADMIN_FORM_SOURCE.into()),
+        &config.consent_text_version,
+    );
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(previous) = sqlx::query!(
+            r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let subscriber = sqlx::query_as!(
+            Subscriber,
+            r#"
+            UPDATE subscriptions
+            SET name = COALESCE($2, name),
+                status = COALESCE($3, status),
+                tags = COALESCE($4, tags)
+            WHERE id = $1
+            RETURNING id, email, name, status, subscribed_at, tags
+            "#,
+            id,
+            name.as_ref().map(|n| n.as_ref()),
+            update.status.as_ref().map(|s| s.as_str()),
+            update.tags.as_deref(),
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        if subscriber.status != previous.status {
+            // recorded apart from confirmations through the link, so that the consent trail
+            // tells what the subscriber did from what an admin did; the audit log has who
+            let kind = if subscriber.status == SubscriptionStatus::Confirmed.as_str() {
+                SubscriptionEventKind::ConfirmedByAdmin
+            } else {
+                SubscriptionEventKind::UnconfirmedByAdmin
+            };
+            record_subscription_event(&mut transaction, id, &subscriber.email, kind, &consent)
+                .await?;
+            record_audit_event(
+                &mut *transaction,
+                &principal,
+                AuditAction::SubscriberStatusChanged,
+                id,
+                Some(&format!("{} -> {}", previous.status, subscriber.status)),
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some(subscriber))
+    }
+    .await;
+    match result {
+        Ok(Some(subscriber)) => HttpResponse::Ok().json(subscriber),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to update subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/subscribers/{id}",
+    tag = "admin",
+    security(("api_key" = ["subscribers:write"])),
+    params(("id" = Uuid, Path, description = "Subscriber id")),
+    responses(
+        (status = 204, description = "Subscriber is deleted"),
+        (status = 404, description = "Unknown subscriber")
+    )
+)]
+#[tracing::instrument(name = "Delete subscriber", skip(request, db_pool, config, principal))]
+pub async fn delete_subscriber(
+    request: HttpRequest,
+    id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<SubscriptionsConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let consent = ConsentContext::from_request(
+        &request,
+        Some(ADMIN_FORM_SOURCE.into()),
+        &config.consent_text_version,
+    );
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+            id,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        let Some(deleted) = sqlx::query!(
+            r#"DELETE FROM subscriptions WHERE id = $1 RETURNING email"#,
+            id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        else {
+            return Ok(false);
+        };
+        record_subscription_event(
+            &mut transaction,
+            id,
+            &deleted.email,
+            SubscriptionEventKind::Unsubscribed,
+            &consent,
+        )
+        .await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::SubscriberDeleted,
+            id,
+            Some(&deleted.email),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to delete subscriber: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like, Cursor};
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = assert_ok!(Cursor::parse(
+            "1742130000123456_67e55044-10b1-426f-9247-bb680e5fe0c8"
+        ));
+        assert_eq!(cursor.subscribed_at.timestamp_micros(), 1742130000123456);
+        assert_eq!(
+            cursor.id.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+    }
+
+    #[test]
+    fn invalid_cursor_returns_error() {
+        for cursor in [
+            "",
+            "123",
+            "abc_67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "123_abc",
+        ] {
+            assert_err!(Cursor::parse(cursor));
+        }
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("a_b%c\\"), "a\\_b\\%c\\\\");
+    }
+}
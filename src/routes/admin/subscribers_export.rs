@@ -0,0 +1,304 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::borrow::Cow;
+use uuid::Uuid;
+
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::Principal;
+use crate::domain::SubscriptionStatus;
+
+/// Number of rows fetched from the cursor and written to the response at a time
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
+const CSV_HEADER: &str = "id,email,name,status,subscribed_at\n";
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/jsonl",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "subscribers.csv",
+            ExportFormat::Jsonl => "subscribers.jsonl",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParameters {
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(sqlx::Error),
+    Encoding(String),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Database(e) => write!(f, "Failed to read subscribers: {}", e),
+            ExportError::Encoding(e) => write!(f, "Failed to encode subscribers: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// Stream subscribers to the client in batches read from a server-side cursor, so that we
+/// never hold more than one batch in memory, regardless of the number of subscribers
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/export",
+    tag = "admin",
+    security(("api_key" = ["subscribers:read"])),
+    params(ExportParameters),
+    responses((
+        status = 200,
+        description = "All matching subscribers, streamed",
+        content(("text/csv"), ("application/jsonl"))
+    ))
+)]
+#[tracing::instrument(name = "Export subscribers", skip(db_pool, principal))]
+pub async fn export_subscribers(
+    parameters: web::Query<ExportParameters>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    // recorded up front rather than in the transaction of the cursor, which is rolled back when
+    // the download is aborted, after some subscribers may have been sent already
+    let details = match parameters.status {
+        Some(status) => format!("{} of {}", parameters.format.as_str(), status.as_str()),
+        None => parameters.format.as_str().to_string(),
+    };
+    if record_audit_event(
+        db_pool.get_ref(),
+        &principal,
+        AuditAction::SubscribersExported,
+        "subscribers",
+        Some(&details),
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    // cursors only live as long as the transaction they are declared in, so the transaction
+    // is moved into the stream and committed once the cursor is exhausted
+    let mut transaction = match db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if declare_cursor(&mut transaction, parameters.status)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let format = parameters.format;
+    let header = match format {
+        ExportFormat::Csv => vec![Ok(Bytes::from_static(CSV_HEADER.as_bytes()))],
+        ExportFormat::Jsonl => vec![],
+    };
+    let rows = stream::try_unfold(transaction, move |mut transaction| async move {
+        let batch = fetch_batch(&mut transaction).await?;
+        if batch.is_empty() {
+            transaction.commit().await.map_err(ExportError::Database)?;
+            return Ok(None);
+        }
+        let bytes = encode_batch(&batch, format)?;
+        Ok(Some((bytes, transaction)))
+    })
+    .map(|result| {
+        result.inspect_err(|e: &ExportError| {
+            // the response has already started, so all we can do is to abort it and log
+            tracing::error!("Failed to export subscribers: {:?}", e);
+        })
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().into())],
+        })
+        .streaming(stream::iter(header).chain(rows))
+}
+
+async fn declare_cursor(
+    transaction: &mut Transaction<'_, Postgres>,
+    status: Option<SubscriptionStatus>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DECLARE subscribers_export NO SCROLL CURSOR FOR
+        SELECT id, email, name, status, subscribed_at FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+        ORDER BY subscribed_at, id
+        "#,
+    )
+    .bind(status.map(|s| s.as_str().to_string()))
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to declare export cursor: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn fetch_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<ExportedSubscriber>, ExportError> {
+    sqlx::query_as::<_, ExportedSubscriber>(&format!(
+        "FETCH FORWARD {} FROM subscribers_export",
+        EXPORT_BATCH_SIZE
+    ))
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(ExportError::Database)
+}
+
+/// Prefix text that spreadsheet apps would evaluate as a formula with `'`, so that opening an
+/// export can't run what a subscriber entered, e.g. `=HYPERLINK(...)` as name
+fn escape_formula(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+fn encode_batch(batch: &[ExportedSubscriber], format: ExportFormat) -> Result<Bytes, ExportError> {
+    let mut buffer = Vec::new();
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut buffer);
+            for subscriber in batch {
+                let row = (
+                    subscriber.id,
+                    escape_formula(&subscriber.email),
+                    escape_formula(&subscriber.name),
+                    escape_formula(&subscriber.status),
+                    subscriber.subscribed_at,
+                );
+                writer
+                    .serialize(row)
+                    .map_err(|e| ExportError::Encoding(e.to_string()))?;
+            }
+            writer
+                .flush()
+                .map_err(|e| ExportError::Encoding(e.to_string()))?;
+        }
+        ExportFormat::Jsonl => {
+            for subscriber in batch {
+                serde_json::to_writer(&mut buffer, subscriber)
+                    .map_err(|e| ExportError::Encoding(e.to_string()))?;
+                buffer.push(b'\n');
+            }
+        }
+    }
+    Ok(Bytes::from(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_batch, ExportFormat, ExportedSubscriber};
+    use chrono::{DateTime, Utc};
+    use uuid::Uuid;
+
+    fn subscriber(name: &str) -> ExportedSubscriber {
+        ExportedSubscriber {
+            id: Uuid::nil(),
+            email: "ursula_le_guin@gmail.com".into(),
+            name: name.into(),
+            status: "confirmed".into(),
+            subscribed_at: DateTime::<Utc>::UNIX_EPOCH,
+        }
+    }
+
+    fn names_in(format: ExportFormat, names: &[&str]) -> String {
+        let batch = names
+            .iter()
+            .map(|name| subscriber(name))
+            .collect::<Vec<_>>();
+        let bytes = encode_batch(&batch, format).unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn csv_export_escapes_formulas() {
+        let csv = names_in(
+            ExportFormat::Csv,
+            &[
+                "=HYPERLINK(\"http://evil.com\")",
+                "+1",
+                "-1",
+                "@SUM(A1)",
+                "\tx",
+                "le guin",
+            ],
+        );
+        let names = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .records()
+            .map(|record| record.unwrap()[2].to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "'=HYPERLINK(\"http://evil.com\")",
+                "'+1",
+                "'-1",
+                "'@SUM(A1)",
+                "'\tx",
+                "le guin"
+            ]
+        );
+    }
+
+    #[test]
+    fn jsonl_export_keeps_names_as_entered() {
+        let jsonl = names_in(ExportFormat::Jsonl, &["=1+1"]);
+        let row: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+        assert_eq!(row["name"], "=1+1");
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use crate::auth::Principal;
+use crate::config::SubscriptionsConfig;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscriber_import::{
+    import_subscribers, ImportError, ImportMode, ImportReport, IMPORT_FORM_SOURCE,
+};
+use crate::subscription_events::ConsentContext;
+
+/// Maximum size of an uploaded CSV file, enough for tens of thousands of contacts
+pub const MAX_IMPORT_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParameters {
+    mode: ImportMode,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/import",
+    tag = "admin",
+    security(("api_key" = ["subscribers:write"])),
+    params(ImportParameters),
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Imported subscribers and rows with errors", body = ImportReport),
+        (status = 400, description = "File is not CSV or misses a column")
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip(request, body, db_pool, email_client, base_url, config, principal)
+)]
+pub async fn import_subscribers_csv(
+    request: HttpRequest,
+    parameters: web::Query<ImportParameters>,
+    body: web::Bytes,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<SubscriptionsConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    let context = ConsentContext::from_request(
+        &request,
+        Some(IMPORT_FORM_SOURCE.into()),
+        &config.consent_text_version,
+    );
+    match import_subscribers(
+        body.as_ref(),
+        parameters.mode,
+        (&principal).into(),
+        &db_pool,
+        &email_client,
+        &base_url.0,
+        &context,
+        &config,
+    )
+    .await
+    {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e @ (ImportError::Csv(_) | ImportError::MissingColumn(_))) => {
+            HttpResponse::BadRequest().body(e.to_string())
+        }
+        Err(ImportError::Database(_)) => HttpResponse::InternalServerError().finish(),
+    }
+}
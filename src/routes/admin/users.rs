@@ -0,0 +1,196 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::admin_users::{
+    change_role, invite_admin_user, invite_link, list_admin_users, normalize_admin_email,
+    send_invite_email, AdminUser, AdminUserError,
+};
+use crate::audit_log::{list_audit_log, record_audit_event, AuditAction, AuditLogEntry};
+use crate::auth::{Principal, Role};
+use crate::config::AuthConfig;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationBaseUrl;
+
+/// Answer to API keys and other roles trying to invite users or change roles
+const OWNER_ONLY: &str = "Only owners can invite users and change roles";
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct NewAdminUser {
+    email: String,
+    role: Role,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct RoleChange {
+    role: Role,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+pub struct AuditLogQuery {
+    /// number of entries, newest first, 100 by default
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    security(("api_key" = ["users:manage"])),
+    responses((status = 200, description = "All admin users", body = [AdminUser]))
+)]
+#[tracing::instrument(name = "List admin users", skip(db_pool))]
+pub async fn get_admin_users(db_pool: web::Data<PgPool>) -> HttpResponse {
+    match list_admin_users(&db_pool).await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users",
+    tag = "admin",
+    security(("api_key" = ["users:manage"])),
+    request_body = NewAdminUser,
+    responses(
+        (status = 201, description = "User is created and invited by email", body = AdminUser),
+        (status = 400, description = "Invalid email"),
+        (status = 403, description = "Not logged in as owner"),
+        (status = 409, description = "A user with the email already exists")
+    )
+)]
+#[tracing::instrument(
+    name = "Invite admin user",
+    skip(new_user, db_pool, email_client, base_url, config, principal),
+    fields(email = %new_user.email, role = new_user.role.as_str())
+)]
+pub async fn post_admin_user(
+    new_user: web::Json<NewAdminUser>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<AuthConfig>,
+    principal: Principal,
+) -> HttpResponse {
+    if !principal.is_owner() {
+        return HttpResponse::Forbidden().body(OWNER_ONLY);
+    }
+    let email = match normalize_admin_email(&new_user.email) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let (user, token) = invite_admin_user(
+            &mut transaction,
+            &email,
+            new_user.role,
+            config.invite_expiry(),
+        )
+        .await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::UserInvited,
+            user.id,
+            Some(&format!("{} as {}", user.email, user.role.as_str())),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, AdminUserError>((user, token))
+    }
+    .await;
+    let (user, token) = match result {
+        Ok(invited) => invited,
+        Err(AdminUserError::AlreadyExists) => {
+            return HttpResponse::Conflict().body("A user with this email already exists")
+        }
+        Err(e) => {
+            tracing::error!("Failed to invite admin user: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let link = invite_link(&base_url.0, &token);
+    if send_invite_email(&email_client, &user.email, user.role, &link)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Created().json(user)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/admin/users/{id}",
+    tag = "admin",
+    security(("api_key" = ["users:manage"])),
+    params(("id" = Uuid, Path, description = "Admin user id")),
+    request_body = RoleChange,
+    responses(
+        (status = 200, description = "Updated user", body = AdminUser),
+        (status = 403, description = "Not logged in as owner"),
+        (status = 404, description = "Unknown user"),
+        (status = 409, description = "The last owner can't be demoted")
+    )
+)]
+#[tracing::instrument(name = "Change admin user role", skip(db_pool, principal))]
+pub async fn update_admin_user(
+    id: web::Path<Uuid>,
+    change: web::Json<RoleChange>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    if !principal.is_owner() {
+        return HttpResponse::Forbidden().body(OWNER_ONLY);
+    }
+    let id = id.into_inner();
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(user) = change_role(&mut transaction, id, change.role).await? else {
+            return Ok(None);
+        };
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::UserRoleChanged,
+            user.id,
+            Some(user.role.as_str()),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, AdminUserError>(Some(user))
+    }
+    .await;
+    match result {
+        Ok(Some(user)) => HttpResponse::Ok().json(user),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(AdminUserError::LastOwner) => {
+            HttpResponse::Conflict().body("The last owner can't be demoted")
+        }
+        Err(e) => {
+            tracing::error!("Failed to change admin user role: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit_log",
+    tag = "admin",
+    security(("api_key" = ["audit_log:read"])),
+    params(AuditLogQuery),
+    responses((status = 200, description = "Latest privileged actions", body = [AuditLogEntry]))
+)]
+#[tracing::instrument(name = "Get audit log", skip(db_pool))]
+pub async fn get_audit_log(
+    query: web::Query<AuditLogQuery>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match list_audit_log(&db_pool, query.limit).await {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
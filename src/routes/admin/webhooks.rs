@@ -0,0 +1,223 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit_log::{record_audit_event, AuditAction};
+use crate::auth::Principal;
+use crate::subscription_events::SubscriptionEventKind;
+use crate::webhooks::{
+    create_webhook_endpoint, disable_webhook_endpoint, list_webhook_deliveries,
+    list_webhook_endpoints, replay_webhook_delivery, DeliveryStatus, WebhookDelivery,
+    WebhookEndpoint,
+};
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct NewWebhookEndpoint {
+    /// http(s) URL events are posted to
+    url: String,
+    event_types: Vec<SubscriptionEventKind>,
+}
+
+impl NewWebhookEndpoint {
+    fn validate(&self) -> Result<(), String> {
+        let url = reqwest::Url::parse(&self.url).map_err(|e| format!("Invalid URL: {}", e))?;
+        if !["http", "https"].contains(&url.scheme()) {
+            return Err("URL must use http or https".into());
+        }
+        if self.event_types.is_empty() {
+            return Err("Webhooks need at least one event type".into());
+        }
+        Ok(())
+    }
+}
+
+/// Registered endpoint, the only time the signing secret is shown
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CreatedWebhookEndpoint {
+    secret: String,
+    #[serde(flatten)]
+    endpoint: WebhookEndpoint,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+pub struct DeliveriesQuery {
+    status: Option<DeliveryStatus>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("api_key" = ["webhooks:manage"])),
+    responses(
+        (status = 200, description = "All webhook endpoints, including disabled ones", body = [WebhookEndpoint])
+    )
+)]
+#[tracing::instrument(name = "List webhook endpoints", skip(db_pool))]
+pub async fn get_webhooks(db_pool: web::Data<PgPool>) -> HttpResponse {
+    match list_webhook_endpoints(&db_pool).await {
+        Ok(endpoints) => HttpResponse::Ok().json(endpoints),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    security(("api_key" = ["webhooks:manage"])),
+    request_body = NewWebhookEndpoint,
+    responses(
+        (status = 201, description = "Endpoint is registered", body = CreatedWebhookEndpoint),
+        (status = 400, description = "Invalid URL or missing event types")
+    )
+)]
+#[tracing::instrument(name = "Register webhook endpoint", skip(db_pool, principal))]
+pub async fn post_webhook(
+    new_endpoint: web::Json<NewWebhookEndpoint>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    if let Err(e) = new_endpoint.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let (endpoint, secret) = create_webhook_endpoint(
+            &mut *transaction,
+            &new_endpoint.url,
+            &new_endpoint.event_types,
+        )
+        .await?;
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::WebhookRegistered,
+            endpoint.id,
+            Some(&endpoint.url),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>((endpoint, secret))
+    }
+    .await;
+    match result {
+        Ok((endpoint, secret)) => {
+            HttpResponse::Created().json(CreatedWebhookEndpoint { secret, endpoint })
+        }
+        Err(e) => {
+            tracing::error!("Failed to register webhook endpoint: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    security(("api_key" = ["webhooks:manage"])),
+    params(("id" = Uuid, Path, description = "Webhook endpoint id")),
+    responses(
+        (status = 204, description = "Endpoint is disabled, its pending deliveries failed"),
+        (status = 404, description = "Unknown or already disabled endpoint")
+    )
+)]
+#[tracing::instrument(name = "Disable webhook endpoint", skip(db_pool, principal))]
+pub async fn delete_webhook(
+    id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        if !disable_webhook_endpoint(&mut transaction, id).await? {
+            return Ok(false);
+        }
+        record_audit_event(
+            &mut *transaction,
+            &principal,
+            AuditAction::WebhookDisabled,
+            id,
+            None,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to disable webhook endpoint: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{id}/deliveries",
+    tag = "admin",
+    security(("api_key" = ["webhooks:manage"])),
+    params(("id" = Uuid, Path, description = "Webhook endpoint id"), DeliveriesQuery),
+    responses(
+        (status = 200, description = "Latest deliveries to the endpoint", body = [WebhookDelivery])
+    )
+)]
+#[tracing::instrument(name = "List webhook deliveries", skip(db_pool))]
+pub async fn get_webhook_deliveries(
+    id: web::Path<Uuid>,
+    query: web::Query<DeliveriesQuery>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match list_webhook_deliveries(&db_pool, id.into_inner(), query.status).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/deliveries/{id}/replay",
+    tag = "admin",
+    security(("api_key" = ["webhooks:manage"])),
+    params(("id" = Uuid, Path, description = "Webhook delivery id")),
+    responses(
+        (status = 202, description = "Delivery is queued to be sent again"),
+        (status = 404, description = "Unknown delivery, not failed, or to a disabled endpoint")
+    )
+)]
+#[tracing::instrument(name = "Replay webhook delivery", skip(db_pool))]
+pub async fn replay_webhook(id: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> HttpResponse {
+    match replay_webhook_delivery(&db_pool, id.into_inner()).await {
+        Ok(true) => HttpResponse::Accepted().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewWebhookEndpoint;
+    use crate::subscription_events::SubscriptionEventKind;
+    use claims::{assert_err, assert_ok};
+
+    fn endpoint(url: &str, event_types: Vec<SubscriptionEventKind>) -> NewWebhookEndpoint {
+        NewWebhookEndpoint {
+            url: url.into(),
+            event_types,
+        }
+    }
+
+    #[test]
+    fn endpoints_need_an_http_url_and_event_types() {
+        let confirmed = vec![SubscriptionEventKind::Confirmed];
+        assert_ok!(endpoint("https://crm.example.com/hooks", confirmed.clone()).validate());
+        assert_err!(endpoint("crm.example.com/hooks", confirmed.clone()).validate());
+        assert_err!(endpoint("ftp://crm.example.com/hooks", confirmed).validate());
+        assert_err!(endpoint("https://crm.example.com/hooks", vec![]).validate());
+    }
+}
@@ -0,0 +1,172 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use super::{complete_first_factor, is_well_formed_token, TokenParameters};
+use crate::admin_users::{
+    create_user_token, find_credentials, get_admin_user, magic_link, send_magic_link_email,
+    use_user_token, AdminUser, TokenPurpose,
+};
+use crate::config::AuthConfig;
+use crate::csrf::CsrfToken;
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+use crate::startup::ApplicationBaseUrl;
+
+const MAGIC_LINK_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Log in by email</title></head>
+<body>
+<form action="/login/magic" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<label>Email <input type="email" name="email" autocomplete="username" required></label>
+<button type="submit">Send login link</button>
+</form>
+</body>
+</html>"#;
+
+/// Clicking the link only shows this form, so that mail scanners opening links don't use it up
+const CONFIRM_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Log in by email</title></head>
+<body>
+<form action="/login/magic/confirm" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<input type="hidden" name="token" value="{token}">
+<button type="submit">Log in</button>
+</form>
+</body>
+</html>"#;
+
+/// Same response whether or not a link was sent, so that it doesn't reveal who is an admin user
+const LINK_SENT: &str =
+    "If the email belongs to an admin user who can log in by email, we've sent them a login link";
+
+#[derive(serde::Deserialize)]
+pub struct MagicLinkRequest {
+    email: String,
+}
+
+pub async fn magic_link_form(csrf: CsrfToken) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(MAGIC_LINK_FORM.replace("{csrf_token}", csrf.as_str()))
+}
+
+/// User the link may be sent to: invite accepted and a role allowed to log in without password
+async fn find_magic_link_user(
+    db_pool: &PgPool,
+    email: &str,
+    config: &AuthConfig,
+) -> Result<Option<AdminUser>, sqlx::Error> {
+    let Some((user_id, Some(_))) = find_credentials(db_pool, email).await? else {
+        return Ok(None);
+    };
+    let user = get_admin_user(db_pool, user_id).await?;
+    Ok(user.filter(|user| config.magic_link_allowed(user.role)))
+}
+
+#[tracing::instrument(
+    name = "Request magic link",
+    skip(form, db_pool, email_client, base_url, config, rate_limiter),
+    fields(email = %form.email)
+)]
+pub async fn request_magic_link(
+    form: web::Form<MagicLinkRequest>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<AuthConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let email = form.email.trim().to_lowercase();
+    // limit emails per address, so that we can't be used to flood someone's inbox
+    if let Err(limited) = rate_limiter.check_email(&email).await {
+        tracing::warn!("Rate limit exceeded for email");
+        return limited.into_response();
+    }
+    let result = async {
+        let Some(user) = find_magic_link_user(&db_pool, &email, &config).await? else {
+            return Ok(None);
+        };
+        let mut transaction = db_pool.begin().await?;
+        let token = create_user_token(
+            &mut transaction,
+            user.id,
+            TokenPurpose::MagicLink,
+            config.magic_link_expiry(),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some((user, token)))
+    }
+    .await;
+    match result {
+        Ok(Some((user, token))) => {
+            let link = magic_link(&base_url.0, &token);
+            let sent = send_magic_link_email(
+                &email_client,
+                &user.email,
+                &link,
+                config.magic_link_expiry(),
+            )
+            .await;
+            match sent {
+                Ok(()) => HttpResponse::Ok().body(LINK_SENT),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Ok(None) => {
+            tracing::info!("No magic link sent");
+            HttpResponse::Ok().body(LINK_SENT)
+        }
+        Err(e) => {
+            tracing::error!("Failed to create magic link: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn magic_link_confirm_form(
+    parameters: web::Query<TokenParameters>,
+    csrf: CsrfToken,
+) -> HttpResponse {
+    if !is_well_formed_token(&parameters.token) {
+        return HttpResponse::BadRequest().body("Invalid login link");
+    }
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        CONFIRM_FORM
+            .replace("{token}", &parameters.token)
+            .replace("{csrf_token}", csrf.as_str()),
+    )
+}
+
+/// Use the token of a magic link and log the user in, asking for their second factor if enabled
+#[tracing::instrument(name = "Log in with magic link", skip_all)]
+pub async fn confirm_magic_link(
+    form: web::Form<TokenParameters>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+) -> HttpResponse {
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(user_id) =
+            use_user_token(&mut transaction, &form.token, TokenPurpose::MagicLink).await?
+        else {
+            return Ok(None);
+        };
+        let user = get_admin_user(&mut *transaction, user_id).await?;
+        transaction.commit().await?;
+        // the role may have changed since the link was sent
+        Ok::<_, sqlx::Error>(user.filter(|user| config.magic_link_allowed(user.role)))
+    }
+    .await;
+    match result {
+        Ok(Some(user)) => complete_first_factor(&db_pool, user.id, &config).await,
+        Ok(None) => HttpResponse::Gone().body("This login link is invalid, used or expired"),
+        Err(e) => {
+            tracing::error!("Failed to log in with magic link: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use actix_web::http::header::{ContentType, RETRY_AFTER};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, TimeDelta, Utc};
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::admin_users::{
+    create_user_token, fail_user_token, find_credentials, forgot_password_link, get_admin_user,
+    send_lockout_email, set_password, use_user_token, AdminUser, TokenPurpose,
+};
+use crate::auth::{
+    clear_failed_logins, create_session, delete_session, expired_session_cookie, hash_password,
+    locked_until, record_failed_login, session_cookie, totp_enabled, verify_password,
+    verify_second_factor, PasswordError, SESSION_COOKIE,
+};
+use crate::client_ip::ClientIp;
+use crate::config::AuthConfig;
+use crate::csrf::CsrfToken;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationBaseUrl;
+
+mod magic_link;
+mod password_reset;
+
+pub use magic_link::*;
+pub use password_reset::*;
+
+/// how long users have to enter the code from their authenticator app after the password
+const LOGIN_CHALLENGE_EXPIRY_MINUTES: i64 = 5;
+/// wrong codes allowed per login, before the password has to be entered again
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+const LOGIN_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Login</title></head>
+<body>
+<form action="/login" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<label>Email <input type="email" name="email" autocomplete="username" required></label>
+<label>Password <input type="password" name="password" autocomplete="current-password" required></label>
+<button type="submit">Log in</button>
+</form>
+<p><a href="/login/magic">Log in by email instead</a></p>
+<p><a href="/login/forgot">Forgot your password?</a></p>
+</body>
+</html>"#;
+
+const INVITE_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Accept invite</title></head>
+<body>
+<form action="/login/invite" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<input type="hidden" name="token" value="{token}">
+<label>Password <input type="password" name="password" autocomplete="new-password" minlength="12" required></label>
+<button type="submit">Set password</button>
+</form>
+</body>
+</html>"#;
+
+const TWO_FACTOR_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Two-factor authentication</title></head>
+<body>
+<form action="/login/totp" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<input type="hidden" name="challenge" value="{challenge}">
+<label>Code from your authenticator app or recovery code <input type="text" name="code" autocomplete="one-time-code" required></label>
+<button type="submit">Log in</button>
+</form>
+</body>
+</html>"#;
+
+#[derive(serde::Deserialize)]
+pub struct Credentials {
+    email: String,
+    password: SecretString,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TokenParameters {
+    token: String,
+}
+
+/// Password chosen through an emailed link, i.e. an invite or a password reset
+#[derive(serde::Deserialize)]
+pub struct NewPassword {
+    token: String,
+    password: SecretString,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChallengeParameters {
+    challenge: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct SecondFactor {
+    challenge: String,
+    code: SecretString,
+}
+
+/// Response to a correct password of a user with two-factor authentication
+#[derive(serde::Serialize)]
+struct TwoFactorChallenge {
+    challenge: String,
+}
+
+/// Tokens we generate only contain letters and digits; anything else can't be valid and must
+/// not end up in the HTML we render
+fn is_well_formed_token(token: &str) -> bool {
+    !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+pub async fn login_form(csrf: CsrfToken) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(LOGIN_FORM.replace("{csrf_token}", csrf.as_str()))
+}
+
+/// Logins for the email or from the IP are refused for now
+fn locked_out(until: DateTime<Utc>) -> HttpResponse {
+    tracing::warn!("Rejected login while locked out");
+    let retry_after_secs = (until - Utc::now()).num_seconds().max(1);
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after_secs.to_string()))
+        .body("Too many failed logins, try again later")
+}
+
+/// Count a wrong password or second factor, telling the user by email if this locked out their
+/// account; `notify` is false for emails that don't belong to a user
+async fn count_failed_login(
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    config: &AuthConfig,
+    email: &str,
+    ip: Option<IpAddr>,
+    notify: bool,
+) {
+    // errors are logged when recording, the login is rejected all the same
+    let Ok(Some(locked_until)) = record_failed_login(db_pool, email, ip, config).await else {
+        return;
+    };
+    if notify {
+        let link = forgot_password_link(&base_url.0);
+        if send_lockout_email(email_client, email, locked_until, &link)
+            .await
+            .is_err()
+        {
+            tracing::warn!("User wasn't told about the lockout");
+        }
+    }
+}
+
+/// Log the user in and respond with the user and the session cookie
+async fn start_session(db_pool: &PgPool, user_id: Uuid, config: &AuthConfig) -> HttpResponse {
+    let token = match create_session(db_pool, user_id, config).await {
+        Ok(token) => token,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_admin_user(db_pool, user_id).await {
+        Ok(Some(user)) => {
+            // the user proved who they are, earlier failures were likely their own typos
+            if let Err(e) = clear_failed_logins(db_pool, &user.email).await {
+                tracing::error!("Failed to clear failed logins: {:?}", e);
+            }
+            HttpResponse::Ok()
+                .cookie(session_cookie(token, config))
+                .json(user)
+        }
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(e) => {
+            tracing::error!("Failed to get admin user: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Respond with a challenge to complete the login with a second factor
+async fn start_challenge(db_pool: &PgPool, user_id: Uuid) -> HttpResponse {
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let challenge = create_user_token(
+            &mut transaction,
+            user_id,
+            TokenPurpose::LoginChallenge,
+            TimeDelta::minutes(LOGIN_CHALLENGE_EXPIRY_MINUTES),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(challenge)
+    }
+    .await;
+    match result {
+        Ok(challenge) => HttpResponse::Accepted().json(TwoFactorChallenge { challenge }),
+        Err(e) => {
+            tracing::error!("Failed to create login challenge: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Start a session, unless the user has to enter a code from their authenticator app first
+async fn complete_first_factor(
+    db_pool: &PgPool,
+    user_id: Uuid,
+    config: &AuthConfig,
+) -> HttpResponse {
+    match totp_enabled(db_pool, user_id).await {
+        Ok(true) => start_challenge(db_pool, user_id).await,
+        Ok(false) => start_session(db_pool, user_id, config).await,
+        Err(e) => {
+            tracing::error!("Failed to look up two-factor authentication: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Log in",
+    skip(form, request, db_pool, email_client, base_url, config),
+    fields(email = %form.email)
+)]
+pub async fn login(
+    form: web::Form<Credentials>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<AuthConfig>,
+) -> HttpResponse {
+    let Credentials { email, password } = form.into_inner();
+    // counted by the email as entered, so that lockouts don't reveal whether the user exists
+    let email = email.trim().to_lowercase();
+    let ip = ClientIp::of(&request);
+    match locked_until(&**db_pool, &email, ip).await {
+        Ok(Some(until)) => return locked_out(until),
+        Ok(None) => {}
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let (user_id, password_hash) = match find_credentials(&db_pool, &email).await {
+        Ok(Some((user_id, password_hash))) => (Some(user_id), password_hash),
+        Ok(None) => (None, None),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    // always verify a password, so that the response time doesn't reveal whether the user exists
+    match (verify_password(password, password_hash).await, user_id) {
+        (Ok(true), Some(user_id)) => complete_first_factor(&db_pool, user_id, &config).await,
+        (Ok(_), user_id) => {
+            tracing::warn!("Rejected login attempt");
+            let notify = user_id.is_some();
+            count_failed_login(
+                &db_pool,
+                &email_client,
+                &base_url,
+                &config,
+                &email,
+                ip,
+                notify,
+            )
+            .await;
+            HttpResponse::Unauthorized().body("Invalid email or password")
+        }
+        (Err(e), _) => {
+            tracing::error!("Failed to verify password: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn two_factor_form(
+    parameters: web::Query<ChallengeParameters>,
+    csrf: CsrfToken,
+) -> HttpResponse {
+    if !is_well_formed_token(&parameters.challenge) {
+        return HttpResponse::BadRequest().body("Invalid login challenge");
+    }
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        TWO_FACTOR_FORM
+            .replace("{challenge}", &parameters.challenge)
+            .replace("{csrf_token}", csrf.as_str()),
+    )
+}
+
+enum SecondFactorOutcome {
+    Verified(Uuid),
+    Rejected(AdminUser),
+    LockedOut(DateTime<Utc>),
+    Expired,
+}
+
+/// Complete a login with a code from the authenticator app or a recovery code
+#[tracing::instrument(name = "Log in with second factor", skip_all)]
+pub async fn login_second_factor(
+    form: web::Form<SecondFactor>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<AuthConfig>,
+) -> HttpResponse {
+    let SecondFactor { challenge, code } = form.into_inner();
+    let ip = ClientIp::of(&request);
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(user_id) =
+            use_user_token(&mut transaction, &challenge, TokenPurpose::LoginChallenge).await?
+        else {
+            return Ok(SecondFactorOutcome::Expired);
+        };
+        let Some(user) = get_admin_user(&mut *transaction, user_id).await? else {
+            return Ok(SecondFactorOutcome::Expired);
+        };
+        // wrong codes count as failed logins too, else whoever knows the password could keep
+        // asking for new challenges
+        if let Some(until) = locked_until(&mut *transaction, &user.email, ip).await? {
+            return Ok(SecondFactorOutcome::LockedOut(until));
+        }
+        // without commit, a wrong code leaves the challenge unused for another attempt
+        if verify_second_factor(&mut transaction, user_id, code.expose_secret()).await? {
+            transaction.commit().await?;
+            Ok::<_, sqlx::Error>(SecondFactorOutcome::Verified(user_id))
+        } else {
+            Ok(SecondFactorOutcome::Rejected(user))
+        }
+    }
+    .await;
+    match result {
+        Ok(SecondFactorOutcome::Verified(user_id)) => {
+            start_session(&db_pool, user_id, &config).await
+        }
+        Ok(SecondFactorOutcome::Rejected(user)) => {
+            tracing::warn!("Rejected second factor");
+            count_failed_login(
+                &db_pool,
+                &email_client,
+                &base_url,
+                &config,
+                &user.email,
+                ip,
+                true,
+            )
+            .await;
+            let failed = fail_user_token(
+                &**db_pool,
+                &challenge,
+                TokenPurpose::LoginChallenge,
+                MAX_CHALLENGE_ATTEMPTS,
+            )
+            .await;
+            if let Err(e) = failed {
+                tracing::error!("Failed to count rejected second factor: {:?}", e);
+            }
+            HttpResponse::Unauthorized().body("Invalid code")
+        }
+        Ok(SecondFactorOutcome::LockedOut(until)) => locked_out(until),
+        Ok(SecondFactorOutcome::Expired) => {
+            HttpResponse::Unauthorized().body("This login has expired, log in again")
+        }
+        Err(e) => {
+            tracing::error!("Failed to verify second factor: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Log out", skip_all)]
+pub async fn logout(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+) -> HttpResponse {
+    if let Some(cookie) = request.cookie(SESSION_COOKIE) {
+        if delete_session(&db_pool, cookie.value()).await.is_err() {
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    HttpResponse::NoContent()
+        .cookie(expired_session_cookie(&config))
+        .finish()
+}
+
+pub async fn invite_form(parameters: web::Query<TokenParameters>, csrf: CsrfToken) -> HttpResponse {
+    if !is_well_formed_token(&parameters.token) {
+        return HttpResponse::BadRequest().body("Invalid invite link");
+    }
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        INVITE_FORM
+            .replace("{token}", &parameters.token)
+            .replace("{csrf_token}", csrf.as_str()),
+    )
+}
+
+/// Set the password of an invited user and log them in
+#[tracing::instrument(name = "Accept invite", skip_all)]
+pub async fn accept_invite(
+    form: web::Form<NewPassword>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<AuthConfig>,
+) -> HttpResponse {
+    let NewPassword { token, password } = form.into_inner();
+    let password_hash = match hash_password(password).await {
+        Ok(password_hash) => password_hash,
+        Err(PasswordError::Invalid(e)) => return HttpResponse::BadRequest().body(e),
+        Err(e) => {
+            tracing::error!("Failed to hash password: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(user_id) = use_user_token(&mut transaction, &token, TokenPurpose::Invite).await?
+        else {
+            return Ok(None);
+        };
+        set_password(&mut *transaction, user_id, &password_hash).await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some(user_id))
+    }
+    .await;
+    match result {
+        Ok(Some(user_id)) => start_session(&db_pool, user_id, &config).await,
+        Ok(None) => HttpResponse::Gone().body("This invite link is invalid, used or expired"),
+        Err(e) => {
+            tracing::error!("Failed to accept invite: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_well_formed_token;
+
+    #[test]
+    fn tokens_with_markup_are_rejected() {
+        assert!(is_well_formed_token("aZ09"));
+        assert!(!is_well_formed_token(""));
+        assert!(!is_well_formed_token("\"><script>"));
+    }
+}
@@ -0,0 +1,182 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use super::{is_well_formed_token, NewPassword, TokenParameters};
+use crate::admin_users::{
+    create_user_token, find_credentials, get_admin_user, invalidate_user_tokens,
+    password_reset_link, send_password_reset_email, set_password, use_user_token, TokenPurpose,
+};
+use crate::auth::{clear_failed_logins, delete_user_sessions, hash_password, PasswordError};
+use crate::config::AuthConfig;
+use crate::csrf::CsrfToken;
+use crate::email_client::EmailClient;
+use crate::rate_limit::RateLimiter;
+use crate::startup::ApplicationBaseUrl;
+
+const FORGOT_PASSWORD_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Forgot password</title></head>
+<body>
+<form action="/login/forgot" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<label>Email <input type="email" name="email" autocomplete="username" required></label>
+<button type="submit">Send reset link</button>
+</form>
+</body>
+</html>"#;
+
+const RESET_PASSWORD_FORM: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Reset password</title></head>
+<body>
+<form action="/login/reset" method="post">
+<input type="hidden" name="csrf_token" value="{csrf_token}">
+<input type="hidden" name="token" value="{token}">
+<label>New password <input type="password" name="password" autocomplete="new-password" minlength="12" required></label>
+<button type="submit">Set password</button>
+</form>
+</body>
+</html>"#;
+
+/// Same response whether or not a link was sent, so that it doesn't reveal who is an admin user
+const LINK_SENT: &str =
+    "If the email belongs to an admin user, we've sent them a link to reset their password";
+
+#[derive(serde::Deserialize)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+pub async fn forgot_password_form(csrf: CsrfToken) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(FORGOT_PASSWORD_FORM.replace("{csrf_token}", csrf.as_str()))
+}
+
+#[tracing::instrument(
+    name = "Request password reset",
+    skip(form, db_pool, email_client, base_url, config, rate_limiter),
+    fields(email = %form.email)
+)]
+pub async fn forgot_password(
+    form: web::Form<ForgotPassword>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<AuthConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> HttpResponse {
+    let email = form.email.trim().to_lowercase();
+    // limit emails per address, so that we can't be used to flood someone's inbox
+    if let Err(limited) = rate_limiter.check_email(&email).await {
+        tracing::warn!("Rate limit exceeded for email");
+        return limited.into_response();
+    }
+    let result = async {
+        // invited users who haven't chosen a password yet use their invite link instead
+        let Some((user_id, Some(_))) = find_credentials(&db_pool, &email).await? else {
+            return Ok(None);
+        };
+        let Some(user) = get_admin_user(&**db_pool, user_id).await? else {
+            return Ok(None);
+        };
+        let mut transaction = db_pool.begin().await?;
+        let token = create_user_token(
+            &mut transaction,
+            user.id,
+            TokenPurpose::PasswordReset,
+            config.password_reset_expiry(),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok::<_, sqlx::Error>(Some((user, token)))
+    }
+    .await;
+    match result {
+        Ok(Some((user, token))) => {
+            let link = password_reset_link(&base_url.0, &token);
+            let sent = send_password_reset_email(
+                &email_client,
+                &user.email,
+                &link,
+                config.password_reset_expiry(),
+            )
+            .await;
+            match sent {
+                Ok(()) => HttpResponse::Ok().body(LINK_SENT),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Ok(None) => {
+            tracing::info!("No password reset link sent");
+            HttpResponse::Ok().body(LINK_SENT)
+        }
+        Err(e) => {
+            tracing::error!("Failed to create password reset link: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn reset_password_form(
+    parameters: web::Query<TokenParameters>,
+    csrf: CsrfToken,
+) -> HttpResponse {
+    if !is_well_formed_token(&parameters.token) {
+        return HttpResponse::BadRequest().body("Invalid password reset link");
+    }
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        RESET_PASSWORD_FORM
+            .replace("{token}", &parameters.token)
+            .replace("{csrf_token}", csrf.as_str()),
+    )
+}
+
+/// Set the new password, log the user out everywhere and lift a lockout of their email; they log
+/// in again with the new password, and their second factor if enabled
+#[tracing::instrument(name = "Reset password", skip_all)]
+pub async fn reset_password(
+    form: web::Form<NewPassword>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let NewPassword { token, password } = form.into_inner();
+    let password_hash = match hash_password(password).await {
+        Ok(password_hash) => password_hash,
+        Err(PasswordError::Invalid(e)) => return HttpResponse::BadRequest().body(e),
+        Err(e) => {
+            tracing::error!("Failed to hash password: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let result = async {
+        let mut transaction = db_pool.begin().await?;
+        let Some(user_id) =
+            use_user_token(&mut transaction, &token, TokenPurpose::PasswordReset).await?
+        else {
+            return Ok(false);
+        };
+        set_password(&mut *transaction, user_id, &password_hash).await?;
+        // whoever knew the old password or got hold of another link loses access
+        let sessions = delete_user_sessions(&mut *transaction, user_id).await?;
+        invalidate_user_tokens(&mut *transaction, user_id).await?;
+        // a lockout protected the old password, the new one starts with a clean slate
+        if let Some(user) = get_admin_user(&mut *transaction, user_id).await? {
+            clear_failed_logins(&mut *transaction, &user.email).await?;
+        }
+        transaction.commit().await?;
+        tracing::info!("Password reset, {} sessions ended", sessions);
+        Ok::<_, sqlx::Error>(true)
+    }
+    .await;
+    match result {
+        Ok(true) => HttpResponse::Ok().body("Your password was reset, log in with the new one"),
+        Ok(false) => {
+            HttpResponse::Gone().body("This password reset link is invalid, used or expired")
+        }
+        Err(e) => {
+            tracing::error!("Failed to reset password: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,5 +1,13 @@
+mod admin;
 mod health_check;
+mod login;
+mod openapi;
 mod subscriptions;
+mod subscriptions_confirm;
 
+pub use admin::*;
 pub use health_check::*;
+pub use login::*;
+pub use openapi::*;
 pub use subscriptions::*;
+pub use subscriptions_confirm::*;
@@ -0,0 +1,77 @@
+use actix_web::HttpResponse;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use super::*;
+use crate::subscriber_import::ImportMode;
+use crate::webhooks::DeliveryStatus;
+
+/// OpenAPI document of the routes served under `/api/v1`
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "zero2prod",
+        version = "1",
+        description = "Newsletter subscriptions and their administration"
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        health_check,
+        subscribe,
+        confirm,
+        form_token,
+        list_subscribers,
+        get_subscriber,
+        update_subscriber,
+        delete_subscriber,
+        import_subscribers_csv,
+        export_subscribers,
+        list_blocked_emails,
+        block_email,
+        unblock_email,
+        get_api_keys,
+        post_api_key,
+        delete_api_key,
+        get_webhooks,
+        post_webhook,
+        delete_webhook,
+        get_webhook_deliveries,
+        replay_webhook,
+        get_admin_users,
+        post_admin_user,
+        update_admin_user,
+        get_audit_log,
+        get_lockouts,
+        delete_lockout,
+        post_totp_enrollment,
+        confirm_totp,
+        post_recovery_codes,
+        delete_totp,
+    ),
+    // enums only used in query parameters aren't collected from the paths
+    components(schemas(SortOrder, ExportFormat, ImportMode, DeliveryStatus)),
+    modifiers(&ApiKeyAuth)
+)]
+pub struct ApiDoc;
+
+/// Admin routes take an API key as bearer token, see [`crate::api_keys`], or the session
+/// cookie of an admin user
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+pub async fn openapi_json() -> HttpResponse {
+    let mut doc = ApiDoc::openapi();
+    // taken from the crate metadata, which doesn't declare a license
+    doc.info.license = None;
+    HttpResponse::Ok().json(doc)
+}
@@ -1,64 +1,324 @@
-use actix_web::{web, HttpResponse};
+use actix_web::http::header::{self, Accept, Header, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
 use sqlx;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::bot_protection::{BotCheckError, BotProtection, BotSignals};
+use crate::client_ip::ClientIp;
+use crate::config::SubscriptionsConfig;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriptionStatus};
 use crate::email_client::EmailClient;
+use crate::email_policy::{EmailPolicy, EmailPolicyError};
+use crate::rate_limit::RateLimiter;
+use crate::startup::ApplicationBaseUrl;
+use crate::subscription_events::{
+    record_subscription_event, ConsentContext, SubscriptionEventKind,
+};
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
 pub struct FormData {
     email: String,
+    // may be left out if the name policy of the form allows empty names
+    #[serde(default)]
     name: String,
+    // optional identifier of the form the subscriber used, e.g. "footer" or "landing-page"
+    source: Option<String>,
+    // honeypot, hidden from humans, see `BotProtection`
+    website: Option<String>,
+    // signed timestamp of when the form was rendered, from `GET /subscriptions/form_token`
+    form_token: Option<String>,
+    #[serde(alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    captcha_token: Option<String>,
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct FormToken {
+    form_token: String,
+}
+
+/// Signed timestamp to embed in the subscription form when rendering it
+#[utoipa::path(
+    get,
+    path = "/subscriptions/form_token",
+    tag = "subscriptions",
+    responses((status = 200, description = "Token to post with the form", body = FormToken))
+)]
+pub async fn form_token(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok().json(FormToken {
+        form_token: bot_protection.issue_form_token(Utc::now()),
+    })
+}
+
+/// How to answer a subscription request: JSON for API clients, a redirect to the thank-you page
+/// for browsers posting a classic HTML form, and bare status codes otherwise
+#[derive(Debug, PartialEq, Eq)]
+enum ResponseStyle {
+    Json,
+    Redirect(String),
+    Plain,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct SubscribeResponse {
+    status: &'static str,
+    message: &'static str,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+struct ErrorResponse<'a> {
+    error: &'a str,
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        let subscriber = Self { email, name };
-        Ok(subscriber)
+impl ResponseStyle {
+    fn negotiate(request: &HttpRequest, is_json_body: bool, thank_you_url: Option<&str>) -> Self {
+        // without an Accept header, clients get the format they sent
+        let preferred = Accept::parse(request)
+            .map(|accept| accept.preference().essence_str().to_string())
+            .unwrap_or_default();
+        match (preferred.as_str(), thank_you_url) {
+            ("application/json", _) => Self::Json,
+            ("*/*" | "", _) if is_json_body => Self::Json,
+            (_, Some(url)) if !is_json_body => Self::Redirect(url.to_string()),
+            _ => Self::Plain,
+        }
+    }
+
+    fn success(&self) -> HttpResponse {
+        match self {
+            Self::Json => HttpResponse::Ok().json(SubscribeResponse {
+                status: "pending_confirmation",
+                message: "Please check your inbox to confirm your subscription.",
+            }),
+            Self::Redirect(url) => HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, url.as_str()))
+                .finish(),
+            Self::Plain => HttpResponse::Ok().finish(),
+        }
+    }
+
+    fn error(&self, status: StatusCode, message: &str) -> HttpResponse {
+        match self {
+            Self::Json => HttpResponse::build(status).json(ErrorResponse { error: message }),
+            // browsers show the message, there's no page to redirect errors to
+            Self::Redirect(_) | Self::Plain => {
+                HttpResponse::build(status).body(message.to_string())
+            }
+        }
     }
 }
 
+/// Parse the subscription from a JSON or a form-encoded body; returns whether it was JSON
+fn parse_body(
+    request: &HttpRequest,
+    body: &[u8],
+) -> Result<(FormData, bool), (StatusCode, String)> {
+    let bad_request = |e: &dyn std::fmt::Display| (StatusCode::BAD_REQUEST, e.to_string());
+    match request.content_type() {
+        "application/json" => serde_json::from_slice(body)
+            .map(|form| (form, true))
+            .map_err(|e| bad_request(&e)),
+        "application/x-www-form-urlencoded" => serde_urlencoded::from_bytes(body)
+            .map(|form| (form, false))
+            .map_err(|e| bad_request(&e)),
+        content_type => Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Unsupported content type: {}", content_type),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content(
+        (FormData = "application/json"),
+        (FormData = "application/x-www-form-urlencoded")
+    )),
+    responses(
+        (status = 200, description = "Subscriber is pending confirmation", body = SubscribeResponse),
+        (status = 303, description = "Browser form posts are redirected to the thank-you page"),
+        (status = 400, description = "Invalid or rejected subscription", body = ErrorResponse),
+        (status = 415, description = "Body is neither JSON nor form-encoded", body = ErrorResponse),
+        (status = 429, description = "Too many subscription attempts", body = ErrorResponse),
+        (status = 503, description = "Bot protection is unavailable", body = ErrorResponse)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Save subscription",
-    skip(form, db_pool),  // skip attaching arguments to context of the span
-    fields(  // manually add to the context of the span
-        %form.email,
-        %form.name
+    skip(body, db_pool, request, config, rate_limiter, bot_protection, email_policy),  // skip attaching arguments to context of the span
+    fields(  // filled in once the body is parsed
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    request: HttpRequest,
+    body: web::Bytes,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    config: web::Data<SubscriptionsConfig>,
+    rate_limiter: web::Data<RateLimiter>,
+    bot_protection: web::Data<BotProtection>,
+    email_policy: web::Data<EmailPolicy>,
 ) -> HttpResponse {
-    let subscriber = match NewSubscriber::try_from(form.0) {
+    let thank_you_url = config.thank_you_url.as_ref().map(reqwest::Url::as_str);
+    let (form, style) = match parse_body(&request, &body) {
+        Ok((form, is_json)) => (
+            form,
+            ResponseStyle::negotiate(&request, is_json, thank_you_url),
+        ),
+        Err((status, message)) => {
+            let is_json = request.content_type() == "application/json";
+            return ResponseStyle::negotiate(&request, is_json, thank_you_url)
+                .error(status, &message);
+        }
+    };
+    tracing::Span::current()
+        .record("subscriber_email", tracing::field::display(&form.email))
+        .record("subscriber_name", tracing::field::display(&form.name));
+    let internal_error = || style.error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error");
+
+    let signals = BotSignals {
+        honeypot: form.website.as_deref(),
+        form_token: form.form_token.as_deref(),
+        captcha_token: form.captcha_token.as_deref(),
+    };
+    match bot_protection.check(&signals, ClientIp::of(&request)).await {
+        Ok(()) => {}
+        Err(BotCheckError::Rejected(reason)) => {
+            tracing::warn!("Rejected automated submission: {}", reason);
+            return style.error(StatusCode::BAD_REQUEST, "Submission rejected");
+        }
+        Err(BotCheckError::Unavailable) => {
+            return style.error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Please try again in a few minutes",
+            )
+        }
+    }
+    let name_policy = config.name_policy_for(form.source.as_deref());
+    let subscriber = match NewSubscriber::parse(
+        form.email,
+        form.name,
+        &config.email_normalization(),
+        name_policy,
+    ) {
         Ok(subscriber) => subscriber,
-        Err(_) => return HttpResponse::BadRequest().finish(),
+        Err(e) => return style.error(StatusCode::BAD_REQUEST, &e),
+    };
+    let consent = ConsentContext::from_request(&request, form.source, &config.consent_text_version);
+    match email_policy.check(&db_pool, &subscriber.email).await {
+        Ok(()) => {}
+        Err(EmailPolicyError::Rejected(reason)) => {
+            tracing::warn!("Rejected subscriber email: {}", reason);
+            return style.error(
+                StatusCode::BAD_REQUEST,
+                "We can't accept subscriptions from this email address",
+            );
+        }
+        Err(EmailPolicyError::Unexpected(_)) => return internal_error(),
+    }
+    // limit confirmation emails per address, so that we can't be used to flood someone's inbox
+    if let Err(limited) = rate_limiter.check_email(subscriber.email.canonical()).await {
+        tracing::warn!("Rate limit exceeded for email");
+        let mut response = style.error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many subscription attempts, please try again later",
+        );
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(limited.retry_after_secs),
+        );
+        return response;
+    }
+
+    // write the subscriber, their token and the consent event in the same transaction, so that
+    // we never store a subscription without proof of consent
+    let mut transaction = match db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return internal_error(),
+    };
+    // subscribers who never confirmed can subscribe again to get a new confirmation link,
+    // e.g. after their previous link has expired; confirmed subscribers get the same answer as
+    // everyone else, but no email, so that the form doesn't tell who is subscribed
+    let (subscriber_id, event_kind) =
+        match get_existing_subscriber(&mut transaction, &subscriber.email).await {
+            Ok(Some(existing)) if existing.status == SubscriptionStatus::Confirmed.as_str() => {
+                tracing::info!("Subscriber is already confirmed, not sending another email");
+                return style.success();
+            }
+            Ok(Some(existing)) => (existing.id, SubscriptionEventKind::ConfirmationResent),
+            Ok(None) => match write_subscriber_to_db(&mut transaction, &subscriber).await {
+                Ok(Some(subscriber_id)) => (subscriber_id, SubscriptionEventKind::Subscribed),
+                Ok(None) => {
+                    // the request that won the race sends the confirmation email
+                    tracing::info!("Subscriber was added concurrently, not sending another email");
+                    return style.success();
+                }
+                Err(_) => return internal_error(),
+            },
+            Err(_) => return internal_error(),
+        };
+    let subscription_token = generate_subscription_token();
+    if store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .is_err()
+    {
+        return internal_error();
     };
-    if write_subscriber_to_db(&db_pool, &subscriber).await.is_err() {
-        return HttpResponse::InternalServerError().finish();
+    if record_subscription_event(
+        &mut transaction,
+        subscriber_id,
+        subscriber.email.as_ref(),
+        event_kind,
+        &consent,
+    )
+    .await
+    .is_err()
+    {
+        return internal_error();
+    };
+    if transaction.commit().await.is_err() {
+        return internal_error();
     };
 
-    if send_confirmation_email(&email_client, subscriber)
+    if send_confirmation_email(&email_client, subscriber, &base_url.0, &subscription_token)
         .await
         .is_err()
     {
-        return HttpResponse::InternalServerError().finish();
+        return internal_error();
     };
-    HttpResponse::Ok().finish()
+    style.success()
+}
+
+/// Generate a random, case-sensitive, 25-characters-long subscription token
+pub(crate) fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
 }
+
 #[tracing::instrument(name = "Send confirmation email", skip(subscriber, email_client))]
-async fn send_confirmation_email(
+pub(crate) async fn send_confirmation_email(
     email_client: &EmailClient,
     subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &str,
 ) -> Result<(), reqwest::Error> {
-    let confirmation_link = "https://domain.com/subscriptions/confirm";
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
     let subject = "Welcome!";
     let text_body = format!(
         "Welcome! Click: {} to confirm your subscription.",
@@ -74,11 +334,64 @@ async fn send_confirmation_email(
         .await
 }
 
-#[tracing::instrument(name = "Write subscriber to database", skip(subscriber, db_pool))]
+/// Subscriber with the same canonical email, whether confirmed or not
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+#[tracing::instrument(name = "Get existing subscriber", skip(email, transaction))]
+async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"SELECT id, status FROM subscriptions WHERE canonical_email = $1"#,
+        email.canonical(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query existing subscriber: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(
+    name = "Store subscription token",
+    skip(subscription_token, transaction)
+)]
+async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at)
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to store subscription token: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Insert a pending subscriber, returning `None` if one with the same canonical email was added
+/// since we looked, e.g. by a concurrent request
+#[tracing::instrument(name = "Write subscriber to database", skip(subscriber, transaction))]
 async fn write_subscriber_to_db(
-    db_pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     subscriber: &NewSubscriber,
-) -> Result<(), sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let id = Uuid::new_v4();
     let subscribed_at = Utc::now();
     tracing::info!(
@@ -86,21 +399,69 @@ async fn write_subscriber_to_db(
         id,
         subscribed_at
     );
-    sqlx::query!(
+    let result = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'confirmed')
+        INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
+        ON CONFLICT (canonical_email) DO NOTHING
         "#,
         id,
         subscriber.email.as_ref(),
+        subscriber.email.canonical(),
         subscriber.name.as_ref(),
         subscribed_at,
     )
-    .execute(db_pool)
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to write new subscription to database: {:?}", e);
         e
     })?; // using `?` to return early if error
-    Ok(())
+    Ok((result.rows_affected() == 1).then_some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseStyle;
+    use actix_web::test::TestRequest;
+
+    fn negotiate(
+        accept: Option<&str>,
+        is_json_body: bool,
+        thank_you_url: Option<&str>,
+    ) -> ResponseStyle {
+        let mut request = TestRequest::default();
+        if let Some(accept) = accept {
+            request = request.insert_header(("Accept", accept));
+        }
+        ResponseStyle::negotiate(&request.to_http_request(), is_json_body, thank_you_url)
+    }
+
+    #[test]
+    fn clients_asking_for_json_get_json() {
+        assert_eq!(
+            negotiate(Some("application/json"), false, Some("/thanks")),
+            ResponseStyle::Json
+        );
+        assert_eq!(
+            negotiate(Some("application/json"), true, None),
+            ResponseStyle::Json
+        );
+    }
+
+    #[test]
+    fn json_bodies_without_preference_get_json() {
+        assert_eq!(negotiate(None, true, Some("/thanks")), ResponseStyle::Json);
+        assert_eq!(negotiate(Some("*/*"), true, None), ResponseStyle::Json);
+    }
+
+    #[test]
+    fn browser_form_posts_are_redirected_if_configured() {
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        assert_eq!(
+            negotiate(Some(browser), false, Some("/thanks")),
+            ResponseStyle::Redirect("/thanks".into())
+        );
+        assert_eq!(negotiate(Some(browser), false, None), ResponseStyle::Plain);
+    }
 }
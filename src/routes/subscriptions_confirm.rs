@@ -0,0 +1,136 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::config::SubscriptionsConfig;
+use crate::subscription_events::{
+    record_subscription_event, ConsentContext, SubscriptionEventKind,
+};
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Parameters {
+    subscription_token: String,
+}
+
+struct StoredToken {
+    subscriber_id: Uuid,
+    email: String,
+    created_at: DateTime<Utc>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "Subscriber is confirmed"),
+        (status = 401, description = "Unknown token"),
+        (status = 410, description = "Confirmation link has expired")
+    )
+)]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, db_pool, request, config)
+)]
+pub async fn confirm(
+    request: HttpRequest,
+    parameters: web::Query<Parameters>,
+    db_pool: web::Data<PgPool>,
+    config: web::Data<SubscriptionsConfig>,
+) -> HttpResponse {
+    let mut transaction = match db_pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let token = match get_token(&mut transaction, &parameters.subscription_token).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if token.created_at + config.token_expiry() < Utc::now() {
+        return HttpResponse::Gone().body(
+            "This confirmation link has expired. \
+            Please subscribe again with the same email address to receive a new link.",
+        );
+    }
+
+    if confirm_subscriber(&mut transaction, token.subscriber_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let consent = ConsentContext::from_request(&request, None, &config.consent_text_version);
+    if record_subscription_event(
+        &mut transaction,
+        token.subscriber_id,
+        &token.email,
+        SubscriptionEventKind::Confirmed,
+        &consent,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, transaction))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        StoredToken,
+        r#"
+        SELECT t.subscriber_id, s.email, t.created_at
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
+        subscription_token,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to query subscription token: {:?}", e);
+        e
+    })?;
+    Ok(result)
+}
+
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(transaction))]
+async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to confirm subscriber: {:?}", e);
+        e
+    })?;
+    // confirmation links can only be used once
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to delete subscription tokens: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,386 @@
+use futures_util::future::BoxFuture;
+use secrecy::{ExposeSecret, SecretBox};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::VaultConfig;
+
+/// Keys of secret settings; each can be replaced by a reference with the suffix of a
+/// [`SecretProvider`], e.g. `password_file`, and is masked when printing the config
+pub const SECRET_KEYS: [&str; 5] = ["password", "auth_token", "form_secret", "secret", "token"];
+/// Suffix of references to a file, e.g. a secret mounted by Docker or Kubernetes
+pub const FILE_SUFFIX: &str = "_file";
+/// Suffix of references to a secret in Vault, written as `path#field`
+pub const VAULT_SUFFIX: &str = "_vault";
+
+/// Source of secrets that are referenced from the config rather than written into it
+pub trait SecretProvider: Send + Sync {
+    /// Fetch the secret the reference points to
+    fn fetch<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<SecretBox<String>, String>>;
+}
+
+/// Reads secrets from files, the reference being the path
+pub struct FileSecretProvider;
+
+impl SecretProvider for FileSecretProvider {
+    fn fetch<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<SecretBox<String>, String>> {
+        let secret = std::fs::read_to_string(reference)
+            .map(|content| {
+                // files written with `echo` or an editor end with a newline
+                let secret = content.trim_end_matches(['\n', '\r']).to_string();
+                SecretBox::new(Box::new(secret))
+            })
+            .map_err(|e| format!("Failed to read {}: {}", reference, e));
+        Box::pin(async move { secret })
+    }
+}
+
+/// Reads secrets from the KV version 2 engine of HashiCorp Vault, or a server with the same API
+pub struct VaultSecretProvider {
+    http_client: reqwest::Client,
+    address: reqwest::Url,
+    mount: String,
+    token: SecretBox<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct KvResponse {
+    data: KvData,
+}
+
+#[derive(serde::Deserialize)]
+struct KvData {
+    data: HashMap<String, Value>,
+}
+
+impl VaultSecretProvider {
+    pub fn from_config(config: VaultConfig) -> Result<Self, String> {
+        let http_client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+        // `join` would replace the last segment of a path without trailing slash, e.g. of a
+        // Vault behind a proxy at `https://example.com/vault`
+        let mut address = config.address;
+        if !address.path().ends_with('/') {
+            address.set_path(&format!("{}/", address.path()));
+        }
+        Ok(Self {
+            http_client,
+            address,
+            mount: config.mount.trim_matches('/').to_string(),
+            token: config.token,
+        })
+    }
+}
+
+impl SecretProvider for VaultSecretProvider {
+    fn fetch<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<SecretBox<String>, String>> {
+        Box::pin(async move {
+            let (path, field) = reference
+                .split_once('#')
+                .ok_or_else(|| format!("{} is not a reference like path#field", reference))?;
+            let url = self
+                .address
+                .join(&format!("v1/{}/data/{}", self.mount, path))
+                .map_err(|e| format!("Invalid path {}: {}", path, e))?;
+            let response = self
+                .http_client
+                .get(url)
+                .header("X-Vault-Token", self.token.expose_secret())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| format!("Failed to fetch {} from Vault: {}", path, e))?;
+            let body = response
+                .json::<KvResponse>()
+                .await
+                .map_err(|e| format!("Invalid response for {} from Vault: {}", path, e))?;
+            match body.data.data.get(field) {
+                Some(Value::String(secret)) => Ok(SecretBox::new(Box::new(secret.clone()))),
+                Some(_) => Err(format!("Field {} of {} is not a string", field, path)),
+                None => Err(format!("Vault secret {} has no field {}", path, field)),
+            }
+        })
+    }
+}
+
+/// Reference found in the config
+struct SecretReference {
+    /// key of the secret setting, e.g. `db.password`
+    key: String,
+    /// key of the reference, e.g. `db.password_file`
+    reference_key: String,
+    reference: String,
+}
+
+fn find_references(
+    value: &Value,
+    prefix: &str,
+    suffix: &str,
+    references: &mut Vec<SecretReference>,
+    problems: &mut Vec<String>,
+) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    let key_of = |name: &str| match prefix {
+        "" => name.to_string(),
+        prefix => format!("{}.{}", prefix, name),
+    };
+    for (name, value) in fields {
+        let secret_name = name
+            .strip_suffix(suffix)
+            .filter(|secret_name| SECRET_KEYS.contains(secret_name));
+        match (secret_name, value) {
+            (Some(secret_name), Value::String(reference)) => references.push(SecretReference {
+                key: key_of(secret_name),
+                reference_key: key_of(name),
+                reference: reference.clone(),
+            }),
+            (Some(_), _) => problems.push(format!("{}: must be a string", key_of(name))),
+            (None, value) => find_references(value, &key_of(name), suffix, references, problems),
+        }
+    }
+}
+
+/// Replace the secrets referenced with the suffix by what the provider fetched for them
+async fn resolve_with(
+    sources: config::Config,
+    suffix: &str,
+    provider: &dyn SecretProvider,
+    problems: &mut Vec<String>,
+) -> config::Config {
+    let json = match sources.clone().try_deserialize::<Value>() {
+        Ok(json) => json,
+        Err(e) => {
+            problems.push(format!("config: {}", e));
+            return sources;
+        }
+    };
+    let mut references = Vec::new();
+    find_references(&json, "", suffix, &mut references, problems);
+    if references.is_empty() {
+        return sources;
+    }
+    let mut builder = config::Config::builder().add_source(sources.clone());
+    for reference in references {
+        tracing::info!("Resolving {} ...", reference.reference_key);
+        match provider.fetch(&reference.reference).await {
+            Ok(secret) => {
+                let value = secret.expose_secret().as_str();
+                builder = match builder.set_override(&reference.key, value) {
+                    Ok(builder) => builder,
+                    Err(e) => {
+                        problems.push(format!("{}: {}", reference.reference_key, e));
+                        return sources;
+                    }
+                };
+            }
+            Err(e) => problems.push(format!("{}: {}", reference.reference_key, e)),
+        }
+    }
+    builder.build().unwrap_or_else(|e| {
+        problems.push(format!("config: {}", e));
+        sources
+    })
+}
+
+/// Resolve the references to secrets in the merged config sources, taking precedence over
+/// secrets written into them; files come first, so that the Vault token can be kept in one
+pub async fn resolve_secrets(sources: config::Config) -> Result<config::Config, Vec<String>> {
+    let mut problems = Vec::new();
+    let mut sources = resolve_with(sources, FILE_SUFFIX, &FileSecretProvider, &mut problems).await;
+    let mut vault_references = Vec::new();
+    if let Ok(json) = sources.clone().try_deserialize::<Value>() {
+        find_references(
+            &json,
+            "",
+            VAULT_SUFFIX,
+            &mut vault_references,
+            &mut Vec::new(),
+        );
+    }
+    if !vault_references.is_empty() {
+        let provider = VaultConfig::from_sources(&sources)
+            .map_err(|e| e.problems().to_vec())
+            .and_then(|config| {
+                VaultSecretProvider::from_config(config)
+                    .map_err(|e| vec![format!("secrets.vault: {}", e)])
+            });
+        match provider {
+            Ok(provider) => {
+                sources = resolve_with(sources, VAULT_SUFFIX, &provider, &mut problems).await;
+            }
+            Err(vault_problems) => problems.extend(vault_problems),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_secrets, FileSecretProvider, SecretProvider, VaultSecretProvider};
+    use crate::config::VaultConfig;
+    use claims::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, SecretBox};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use uuid::Uuid;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// File with the given content, removed when dropped
+    struct SecretFile(PathBuf);
+
+    impl SecretFile {
+        fn with_content(content: &str) -> Self {
+            let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for SecretFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn vault_config(address: String) -> VaultConfig {
+        VaultConfig {
+            address: address.parse().unwrap(),
+            token: SecretBox::new(Box::new("vault-token".into())),
+            mount: "secret".into(),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    async fn vault_with_secret(secret_path: &str, data: serde_json::Value) -> MockServer {
+        vault_below("", secret_path, data).await
+    }
+
+    async fn vault_below(
+        base_path: &str,
+        secret_path: &str,
+        data: serde_json::Value,
+    ) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "{}/v1/secret/data/{}",
+                base_path, secret_path
+            )))
+            .and(header("X-Vault-Token", "vault-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": { "data": data, "metadata": { "version": 1 } }
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn file_secrets_are_read_without_trailing_newline() {
+        let file = SecretFile::with_content("hunter2\n");
+        let secret = assert_ok!(FileSecretProvider.fetch(file.path()).await);
+        assert_eq!(secret.expose_secret(), "hunter2");
+        assert_err!(FileSecretProvider.fetch("/does/not/exist").await);
+    }
+
+    #[tokio::test]
+    async fn vault_secrets_are_read_from_the_kv_engine() {
+        let server =
+            vault_with_secret("newsletter/db", serde_json::json!({ "password": "pw" })).await;
+        let provider = VaultSecretProvider::from_config(vault_config(server.uri())).unwrap();
+
+        let secret = assert_ok!(provider.fetch("newsletter/db#password").await);
+
+        assert_eq!(secret.expose_secret(), "pw");
+        assert_err!(provider.fetch("newsletter/db#username").await);
+        assert_err!(provider.fetch("newsletter/other#password").await);
+        assert_err!(provider.fetch("newsletter/db").await);
+    }
+
+    #[tokio::test]
+    async fn vault_secrets_are_read_below_the_path_of_the_address() {
+        for address_path in ["/vault", "/vault/"] {
+            let server =
+                vault_below("/vault", "newsletter", serde_json::json!({ "token": "t" })).await;
+            let address = format!("{}{}", server.uri(), address_path);
+            let provider = VaultSecretProvider::from_config(vault_config(address)).unwrap();
+
+            let secret = assert_ok!(provider.fetch("newsletter#token").await);
+
+            assert_eq!(secret.expose_secret(), "t");
+        }
+    }
+
+    #[tokio::test]
+    async fn references_are_resolved_and_take_precedence() {
+        let password = SecretFile::with_content("from-file\n");
+        let vault_token = SecretFile::with_content("vault-token");
+        let server =
+            vault_with_secret("newsletter", serde_json::json!({ "token": "postmark" })).await;
+        let sources = config::Config::builder()
+            .set_override("db.password", "from-yaml")
+            .unwrap()
+            .set_override("db.password_file", password.path())
+            .unwrap()
+            .set_override("email_client.auth_token_vault", "newsletter#token")
+            .unwrap()
+            .set_override("secrets.vault.address", server.uri())
+            .unwrap()
+            .set_override("secrets.vault.token_file", vault_token.path())
+            .unwrap()
+            .set_override("secrets.vault.mount", "secret")
+            .unwrap()
+            .set_override("secrets.vault.timeout_ms", 1000)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let resolved = assert_ok!(resolve_secrets(sources).await);
+
+        assert_eq!(resolved.get_string("db.password").unwrap(), "from-file");
+        assert_eq!(
+            resolved.get_string("email_client.auth_token").unwrap(),
+            "postmark"
+        );
+    }
+
+    #[tokio::test]
+    async fn all_unresolvable_references_are_reported() {
+        let sources = config::Config::builder()
+            .set_override("db.password_file", "/does/not/exist")
+            .unwrap()
+            .set_override("bot_protection.form_secret_file", "/does/not/exist/either")
+            .unwrap()
+            .set_override("email_client.auth_token_vault", "newsletter#token")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let problems = resolve_secrets(sources).await.unwrap_err();
+
+        let keys = problems
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                "bot_protection.form_secret_file",
+                "db.password_file",
+                "secrets.vault"
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+    X_CONTENT_TYPE_OPTIONS,
+};
+use actix_web::middleware::Next;
+use actix_web::web;
+
+use crate::config::SecurityHeadersConfig;
+
+/// Headers set on every response, built once from the config
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders(Vec<(HeaderName, HeaderValue)>);
+
+impl SecurityHeaders {
+    pub fn from_config(config: &SecurityHeadersConfig) -> Result<Self, String> {
+        let mut headers = vec![
+            (
+                CONTENT_SECURITY_POLICY,
+                format!(
+                    "{}; frame-ancestors {}",
+                    config.content_security_policy.trim().trim_end_matches(';'),
+                    config.frame_ancestors
+                ),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (REFERRER_POLICY, config.referrer_policy.clone()),
+        ];
+        // browsers remember it for the whole max-age, so only send it where we're served over HTTPS
+        if let Some(max_age) = config.hsts_max_age_secs {
+            headers.push((
+                STRICT_TRANSPORT_SECURITY,
+                format!("max-age={}; includeSubDomains", max_age),
+            ));
+        }
+        headers
+            .into_iter()
+            .map(|(name, value)| match HeaderValue::try_from(value) {
+                Ok(value) => Ok((name, value)),
+                Err(_) => Err(format!("Invalid value for the {} header", name)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Middleware adding the [`SecurityHeaders`] to every response, unless a handler set them itself
+pub async fn set_security_headers(
+    security_headers: web::Data<SecurityHeaders>,
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let mut response = next.call(request).await?;
+    let headers = response.headers_mut();
+    for (name, value) in &security_headers.0 {
+        if !headers.contains_key(name) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecurityHeaders;
+    use crate::config::SecurityHeadersConfig;
+    use actix_web::http::header::{CONTENT_SECURITY_POLICY, STRICT_TRANSPORT_SECURITY};
+
+    fn config() -> SecurityHeadersConfig {
+        SecurityHeadersConfig {
+            content_security_policy: "default-src 'none';".into(),
+            frame_ancestors: "'none'".into(),
+            referrer_policy: "no-referrer".into(),
+            hsts_max_age_secs: None,
+        }
+    }
+
+    fn header(headers: &SecurityHeaders, name: &str) -> Option<String> {
+        headers
+            .0
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn frame_ancestors_are_added_to_the_policy() {
+        let headers = SecurityHeaders::from_config(&config()).unwrap();
+        assert_eq!(
+            header(&headers, CONTENT_SECURITY_POLICY.as_str()).unwrap(),
+            "default-src 'none'; frame-ancestors 'none'"
+        );
+    }
+
+    #[test]
+    fn hsts_is_only_sent_when_configured() {
+        let without = SecurityHeaders::from_config(&config()).unwrap();
+        let with = SecurityHeaders::from_config(&SecurityHeadersConfig {
+            hsts_max_age_secs: Some(31536000),
+            ..config()
+        })
+        .unwrap();
+        assert!(header(&without, STRICT_TRANSPORT_SECURITY.as_str()).is_none());
+        assert_eq!(
+            header(&with, STRICT_TRANSPORT_SECURITY.as_str()).unwrap(),
+            "max-age=31536000; includeSubDomains"
+        );
+    }
+
+    #[test]
+    fn invalid_header_values_are_rejected() {
+        let config = SecurityHeadersConfig {
+            referrer_policy: "no-referrer\n".into(),
+            ..config()
+        };
+        assert!(SecurityHeaders::from_config(&config).is_err());
+    }
+}
@@ -1,11 +1,31 @@
-use crate::config::{Config, DatabaseConfig};
+use crate::auth::{authenticate, require_scope, Scope};
+use crate::bot_protection::BotProtection;
+use crate::client_ip::{resolve_client_ip, TrustedProxies};
+use crate::config::{AuthConfig, Config, DatabaseConfig, EmailClientConfig, SubscriptionsConfig};
+use crate::cors::CorsPolicy;
+use crate::csrf::csrf_protection;
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe};
+use crate::email_policy::EmailPolicy;
+use crate::rate_limit::{rate_limit_by_ip, RateLimiter};
+use crate::routes::{
+    accept_invite, block_email, confirm, confirm_magic_link, confirm_totp, delete_api_key,
+    delete_lockout, delete_subscriber, delete_totp, delete_webhook, export_subscribers,
+    forgot_password, forgot_password_form, form_token, get_admin_users, get_api_keys,
+    get_audit_log, get_lockouts, get_subscriber, get_webhook_deliveries, get_webhooks,
+    health_check, import_subscribers_csv, invite_form, list_blocked_emails, list_subscribers,
+    login, login_form, login_second_factor, logout, magic_link_confirm_form, magic_link_form,
+    openapi_json, post_admin_user, post_api_key, post_recovery_codes, post_totp_enrollment,
+    post_webhook, replay_webhook, request_magic_link, reset_password, reset_password_form,
+    subscribe, two_factor_form, unblock_email, update_admin_user, update_subscriber,
+    MAX_IMPORT_BYTES,
+};
+use crate::security_headers::{set_security_headers, SecurityHeaders};
+use actix_web::middleware::from_fn;
 use actix_web::{dev::Server, web, App, HttpServer};
 use secrecy::{ExposeSecret, SecretBox};
 use sqlx::PgPool;
-use std::io::Error;
-use std::net::TcpListener;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, TcpListener};
 use tracing_actix_web::TracingLogger;
 
 pub fn create_db_connection_pool(config: &DatabaseConfig) -> PgPool {
@@ -14,44 +34,65 @@ pub fn create_db_connection_pool(config: &DatabaseConfig) -> PgPool {
     PgPool::connect_lazy_with(connection_string)
 }
 
+/// Public URL of the app, wrapped so that it can be retrieved from the app data by type
+#[derive(Debug)]
+pub struct ApplicationBaseUrl(pub String);
+
+impl ApplicationBaseUrl {
+    pub fn new(url: &reqwest::Url) -> Self {
+        // links are built by appending paths, but `Url` adds a `/` to an empty path
+        Self(url.as_str().trim_end_matches('/').to_string())
+    }
+}
+
+pub fn build_email_client(config: &EmailClientConfig) -> EmailClient {
+    // we move values out of config, but only have a shared reference here;
+    // that's why we clone the values; an alternative would be to pass on
+    // references to the values but this would requires further changes in EmailClient;
+    // another alternative would be to take owernship of config in the function
+    // signature, but this is undesirable because we may need config after passing
+    // it to this function; we could also take ownership but clone the entire config
+    // before passing it to this function, as suggested in the book, but in the latest
+    // version of secrecy, SecretBox does not implement the Clone trait anymore; passing
+    // a reference instead of taking ownership also seems more appropriate as we don't
+    // expect this function to make any changes to config SecretBox does not implement
+    // clone, so we here manually clone the secret
+    let auth_token = config.auth_token.expose_secret();
+    let cloned_auth_token = SecretBox::new(Box::new(auth_token.clone()));
+    EmailClient::new(
+        config.base_url.clone(),
+        config.sender_email.clone(),
+        config.timeout,
+        cloned_auth_token,
+    )
+}
+
 pub struct Application {
     server: Server,
     ip: String,
     port: u16,
+    email_client: web::Data<EmailClient>,
+    rate_limiter: web::Data<RateLimiter>,
 }
 
 impl Application {
     pub async fn launch(config: &Config) -> Result<Application, Error> {
         tracing::info!("Building app ...");
         // set up email client
-        let timeout = config.email_client.parse_timeout();
-        let sender_email = config
-            .email_client
-            .parse_sender_email()
-            .expect("could not parse sender email");
-
-        // we move values out of config, but only have a shared reference here;
-        // that's why we clone the values; an alternative would be to pass on
-        // references to the values but this would requires further changes in EmailClient;
-        // another alternative would be to take owernship of config in the function
-        // signature, but this is undesirable because we may need config after passing
-        // it to this function; we could also take ownership but clone the entire config
-        // before passing it to this function, as suggested in the book, but in the latest
-        // version of secrecy, SecretBox does not implement the Clone trait anymore; passing
-        // a reference instead of taking ownership also seems more appropriate as we don't
-        // expect this function to make any changes to config SecretBox does not implement
-        // clone, so we here manually clone the secret
-        let auth_token = config.email_client.auth_token.expose_secret();
-        let cloned_auth_token = SecretBox::new(Box::new(auth_token.clone()));
-        let email_client = EmailClient::new(
-            config.email_client.base_url.clone(),
-            sender_email,
-            timeout,
-            cloned_auth_token,
-        );
+        let email_client = web::Data::new(build_email_client(&config.email_client));
+
+        let security_headers = SecurityHeaders::from_config(&config.app.security_headers)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let cors_policy = CorsPolicy::from_config(&config.app.cors)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
 
         // set up database connection, with lazy connection when used for the first time
         let db_pool = create_db_connection_pool(&config.db);
+        // the rate limiter is shared by all workers, so that limits apply across the whole instance
+        let rate_limiter = web::Data::new(RateLimiter::from_config(
+            &config.app.rate_limit,
+            db_pool.clone(),
+        ));
 
         // bind to random port
         let address = format!("{}:{}", config.app.host, config.app.port);
@@ -62,8 +103,27 @@ impl Application {
 
         // launch server
         tracing::info!("Launching server ...");
-        let server = run_server(listener, db_pool, email_client)?;
-        Ok(Self { server, ip, port })
+        let server = run_server(
+            listener,
+            db_pool,
+            email_client.clone(),
+            ApplicationBaseUrl::new(&config.app.base_url),
+            config.subscriptions.clone(),
+            rate_limiter.clone(),
+            config.app.rate_limit.trusted_proxies.clone(),
+            BotProtection::from_config(&config.bot_protection),
+            EmailPolicy::from_config(&config.email_policy),
+            config.auth.clone(),
+            security_headers,
+            cors_policy,
+        )?;
+        Ok(Self {
+            server,
+            ip,
+            port,
+            email_client,
+            rate_limiter,
+        })
     }
 
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
@@ -74,27 +134,234 @@ impl Application {
     pub fn get_address(&self) -> String {
         format!("http://{}:{}", self.ip, self.port)
     }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Email client shared by all workers, e.g. to change its timeout while running
+    pub fn email_client(&self) -> web::Data<EmailClient> {
+        self.email_client.clone()
+    }
+
+    /// Rate limiter shared by all workers, e.g. to change its limits while running
+    pub fn rate_limiter(&self) -> web::Data<RateLimiter> {
+        self.rate_limiter.clone()
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_server(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: web::Data<EmailClient>,
+    base_url: ApplicationBaseUrl,
+    subscriptions_config: SubscriptionsConfig,
+    rate_limiter: web::Data<RateLimiter>,
+    trusted_proxies: Vec<IpAddr>,
+    bot_protection: BotProtection,
+    email_policy: EmailPolicy,
+    auth_config: AuthConfig,
+    security_headers: SecurityHeaders,
+    cors_policy: CorsPolicy,
 ) -> Result<Server, Error> {
     tracing::info!("Launching app ...");
+    let trusted_proxies = web::Data::new(TrustedProxies(trusted_proxies));
+    let bot_protection = web::Data::new(bot_protection);
+    let email_policy = web::Data::new(email_policy);
+    let auth_config = web::Data::new(auth_config);
+    let security_headers = web::Data::new(security_headers);
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
+    let base_url = web::Data::new(base_url);
+    let subscriptions_config = web::Data::new(subscriptions_config);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(set_security_headers))
+            .wrap(from_fn(resolve_client_ip))
             .wrap(TracingLogger::default())
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            .configure(configure_login_routes)
+            // unversioned public routes stay around for links in emails we've already sent and
+            // forms embedded on other sites
+            .configure(|cfg| configure_public_routes(cfg, &cors_policy))
+            .service(
+                web::scope("/api/v1")
+                    .route("/openapi.json", web::get().to(openapi_json))
+                    .configure(|cfg| configure_public_routes(cfg, &cors_policy))
+                    .configure(configure_admin_routes),
+            )
             .app_data(db_pool.clone())
             // when cloning the email client, we clone pointer to same HTTP connection pool, so we
             // can reuse open connections from the same pool across our application threads
             .app_data(email_client.clone())
+            .app_data(base_url.clone())
+            .app_data(subscriptions_config.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_policy.clone())
+            .app_data(auth_config.clone())
+            .app_data(security_headers.clone())
     })
     .listen(listener)?
     .run();
     Ok(server)
 }
+
+/// Browser flows of admin users, not part of the API; their forms are protected against
+/// cross-site request forgery
+fn configure_login_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/login")
+            .wrap(from_fn(csrf_protection))
+            .route("", web::get().to(login_form))
+            .route("", web::post().to(login))
+            .route("/totp", web::get().to(two_factor_form))
+            .route("/totp", web::post().to(login_second_factor))
+            .route("/magic", web::get().to(magic_link_form))
+            .route(
+                "/magic",
+                web::post()
+                    .to(request_magic_link)
+                    .wrap(from_fn(rate_limit_by_ip)),
+            )
+            .route("/magic/confirm", web::get().to(magic_link_confirm_form))
+            .route("/magic/confirm", web::post().to(confirm_magic_link))
+            .route("/forgot", web::get().to(forgot_password_form))
+            .route(
+                "/forgot",
+                web::post()
+                    .to(forgot_password)
+                    .wrap(from_fn(rate_limit_by_ip)),
+            )
+            .route("/reset", web::get().to(reset_password_form))
+            .route("/reset", web::post().to(reset_password))
+            .route("/invite", web::get().to(invite_form))
+            .route("/invite", web::post().to(accept_invite)),
+    )
+    .service(
+        web::resource("/logout")
+            .wrap(from_fn(csrf_protection))
+            .route(web::post().to(logout)),
+    );
+}
+
+/// Public routes of the API, mounted under `/api/v1` and unversioned; keep them in sync with
+/// [`ApiDoc`]; the routes for subscription forms on other sites allow cross-origin requests as
+/// configured
+///
+/// [`ApiDoc`]: crate::routes::ApiDoc
+fn configure_public_routes(cfg: &mut web::ServiceConfig, cors: &CorsPolicy) {
+    cfg.route("/health_check", web::get().to(health_check))
+        .service(
+            web::resource("/subscriptions")
+                .wrap(from_fn(rate_limit_by_ip))
+                // outermost, so that preflight requests don't count towards the rate limit
+                .wrap(cors.middleware())
+                .route(web::post().to(subscribe)),
+        )
+        .route("/subscriptions/confirm", web::get().to(confirm))
+        .service(
+            web::resource("/subscriptions/form_token")
+                .wrap(cors.middleware())
+                .route(web::get().to(form_token)),
+        );
+}
+
+/// Admin routes of the API, only mounted under `/api/v1`; keep them in sync with [`ApiDoc`]
+///
+/// [`ApiDoc`]: crate::routes::ApiDoc
+fn configure_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(from_fn(authenticate))
+            .service(
+                web::resource("/subscribers/import")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                    .route(
+                        web::post()
+                            .to(import_subscribers_csv)
+                            .wrap(from_fn(require_scope(Scope::SubscribersWrite))),
+                    ),
+            )
+            .route(
+                "/subscribers/export",
+                web::get()
+                    .to(export_subscribers)
+                    .wrap(from_fn(require_scope(Scope::SubscribersRead))),
+            )
+            .route(
+                "/subscribers",
+                web::get()
+                    .to(list_subscribers)
+                    .wrap(from_fn(require_scope(Scope::SubscribersRead))),
+            )
+            .service(
+                web::resource("/subscribers/{id}")
+                    .route(
+                        web::get()
+                            .to(get_subscriber)
+                            .wrap(from_fn(require_scope(Scope::SubscribersRead))),
+                    )
+                    .route(
+                        web::patch()
+                            .to(update_subscriber)
+                            .wrap(from_fn(require_scope(Scope::SubscribersWrite))),
+                    )
+                    .route(
+                        web::delete()
+                            .to(delete_subscriber)
+                            .wrap(from_fn(require_scope(Scope::SubscribersWrite))),
+                    ),
+            )
+            .service(
+                web::scope("/blocklist")
+                    .wrap(from_fn(require_scope(Scope::BlocklistManage)))
+                    .route("", web::get().to(list_blocked_emails))
+                    .route("", web::post().to(block_email))
+                    .route("/{value}", web::delete().to(unblock_email)),
+            )
+            .service(
+                web::scope("/api_keys")
+                    .wrap(from_fn(require_scope(Scope::ApiKeysManage)))
+                    .route("", web::get().to(get_api_keys))
+                    .route("", web::post().to(post_api_key))
+                    .route("/{id}", web::delete().to(delete_api_key)),
+            )
+            .service(
+                web::scope("/webhooks")
+                    .wrap(from_fn(require_scope(Scope::WebhooksManage)))
+                    .route("", web::get().to(get_webhooks))
+                    .route("", web::post().to(post_webhook))
+                    .route("/deliveries/{id}/replay", web::post().to(replay_webhook))
+                    .route("/{id}", web::delete().to(delete_webhook))
+                    .route("/{id}/deliveries", web::get().to(get_webhook_deliveries)),
+            )
+            .service(
+                // for the logged in user, whatever their role
+                web::scope("/account/totp")
+                    .route("", web::post().to(post_totp_enrollment))
+                    .route("", web::delete().to(delete_totp))
+                    .route("/confirm", web::post().to(confirm_totp))
+                    .route("/recovery_codes", web::post().to(post_recovery_codes)),
+            )
+            .service(
+                web::scope("/users")
+                    .wrap(from_fn(require_scope(Scope::UsersManage)))
+                    .route("", web::get().to(get_admin_users))
+                    .route("", web::post().to(post_admin_user))
+                    .route("/{id}", web::patch().to(update_admin_user)),
+            )
+            .service(
+                web::scope("/lockouts")
+                    .wrap(from_fn(require_scope(Scope::UsersManage)))
+                    .route("", web::get().to(get_lockouts))
+                    .route("/{id}", web::delete().to(delete_lockout)),
+            )
+            .route(
+                "/audit_log",
+                web::get()
+                    .to(get_audit_log)
+                    .wrap(from_fn(require_scope(Scope::AuditLogRead))),
+            ),
+    );
+}
@@ -0,0 +1,419 @@
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::audit_log::{record_audit_event, Actor, AuditAction};
+use crate::config::SubscriptionsConfig;
+use crate::domain::{EmailNormalization, NamePolicy, NewSubscriber};
+use crate::email_client::EmailClient;
+use crate::routes::{generate_subscription_token, send_confirmation_email};
+use crate::subscription_events::{
+    record_subscription_events, ConsentContext, SubscriptionEventKind,
+};
+
+/// Number of rows written to the database per multi-row insert
+const BATCH_SIZE: usize = 1000;
+
+/// Number of confirmation emails sent at the same time
+const EMAIL_CONCURRENCY: usize = 10;
+
+/// Form source recorded in the consent audit trail for imported subscribers
+pub const IMPORT_FORM_SOURCE: &str = "csv_import";
+
+#[derive(
+    serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// subscribers have already confirmed with the previous provider
+    PreConfirmed,
+    /// subscribers are stored as pending and are sent a confirmation email
+    SendConfirmation,
+}
+
+impl ImportMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportMode::PreConfirmed => "pre_confirmed",
+            ImportMode::SendConfirmation => "send_confirmation",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+}
+
+/// Problem with a single row of the imported file; `line` refers to the line in the file
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct RowError {
+    pub line: u64,
+    pub email: Option<String>,
+    pub error: String,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Csv(csv::Error),
+    MissingColumn(&'static str),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Csv(e) => write!(f, "Failed to read CSV: {}", e),
+            ImportError::MissingColumn(column) => write!(f, "Missing CSV column: {}", column),
+            ImportError::Database(e) => write!(f, "Failed to write subscribers: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Valid subscriber together with the line of the file it was read from
+struct ParsedRow {
+    line: u64,
+    subscriber: NewSubscriber,
+}
+
+/// Parse CSV with an `email` and a `name` column, validating every row and collecting errors
+/// instead of stopping at the first invalid row
+fn parse_csv<R: std::io::Read>(
+    reader: R,
+    normalization: &EmailNormalization,
+    name_policy: &NamePolicy,
+) -> Result<(Vec<ParsedRow>, Vec<RowError>), ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    // fail early if the header is unusable, rather than reporting an error for every row
+    let headers = reader.headers().map_err(ImportError::Csv)?.clone();
+    for column in ["email", "name"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(ImportError::MissingColumn(column));
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for result in reader.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(RowError {
+                    line: e.position().map(|p| p.line()).unwrap_or_default(),
+                    email: None,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let row: CsvRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                errors.push(RowError {
+                    line,
+                    email: None,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let email = row.email.clone();
+        match NewSubscriber::parse(row.email, row.name, normalization, name_policy) {
+            Ok(subscriber) if !seen.insert(subscriber.email.canonical().to_string()) => {
+                errors.push(RowError {
+                    line,
+                    email: Some(email),
+                    error: "Duplicate email in file".into(),
+                });
+            }
+            Ok(subscriber) => rows.push(ParsedRow { line, subscriber }),
+            Err(error) => errors.push(RowError {
+                line,
+                email: Some(email),
+                error,
+            }),
+        }
+    }
+    Ok((rows, errors))
+}
+
+/// Import subscribers from CSV, writing valid rows in batches and reporting invalid ones; each
+/// batch is recorded in the audit log as taken by the actor
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(csv, actor, db_pool, email_client, context, config)
+)]
+pub async fn import_subscribers<R: std::io::Read>(
+    csv: R,
+    mode: ImportMode,
+    actor: Actor<'_>,
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    context: &ConsentContext,
+    config: &SubscriptionsConfig,
+) -> Result<ImportReport, ImportError> {
+    let (rows, errors) = parse_csv(
+        csv,
+        &config.email_normalization(),
+        config.name_policy_for(Some(IMPORT_FORM_SOURCE)),
+    )?;
+    let mut report = ImportReport {
+        imported: 0,
+        errors,
+    };
+
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        let batch = rows.by_ref().take(BATCH_SIZE).collect::<Vec<_>>();
+        let mut transaction = db_pool.begin().await.map_err(ImportError::Database)?;
+        let inserted = insert_batch(&mut transaction, &batch, mode, context)
+            .await
+            .map_err(ImportError::Database)?;
+        let tokens = match mode {
+            ImportMode::PreConfirmed => Vec::new(),
+            ImportMode::SendConfirmation => store_tokens(&mut transaction, &inserted)
+                .await
+                .map_err(ImportError::Database)?,
+        };
+        if !inserted.is_empty() {
+            record_audit_event(
+                &mut *transaction,
+                actor,
+                AuditAction::SubscribersImported,
+                "subscribers",
+                Some(&format!("{} as {}", inserted.len(), mode.as_str())),
+            )
+            .await
+            .map_err(ImportError::Database)?;
+        }
+        transaction.commit().await.map_err(ImportError::Database)?;
+
+        let inserted_ids = inserted.into_iter().collect::<HashMap<_, _>>();
+        let mut tokens = tokens.into_iter().collect::<HashMap<_, _>>();
+        let mut confirmations = Vec::new();
+        for row in batch {
+            let email = row.subscriber.email.as_ref().to_string();
+            let Some(subscriber_id) = inserted_ids.get(&email) else {
+                report.errors.push(RowError {
+                    line: row.line,
+                    email: Some(email),
+                    error: "Already subscribed".into(),
+                });
+                continue;
+            };
+            report.imported += 1;
+            if let Some(token) = tokens.remove(subscriber_id) {
+                confirmations.push((row, token));
+            }
+        }
+        let mut failed = stream::iter(confirmations)
+            .map(|(row, token)| async move {
+                let email = row.subscriber.email.as_ref().to_string();
+                let result =
+                    send_confirmation_email(email_client, row.subscriber, base_url, &token).await;
+                // the subscriber is stored, so they can still get a new link by subscribing
+                result.err().map(|e| RowError {
+                    line: row.line,
+                    email: Some(email),
+                    error: format!("Imported, but failed to send confirmation email: {}", e),
+                })
+            })
+            .buffer_unordered(EMAIL_CONCURRENCY)
+            .filter_map(std::future::ready)
+            .collect::<Vec<_>>()
+            .await;
+        failed.sort_by_key(|e| e.line);
+        report.errors.extend(failed);
+    }
+    tracing::info!(
+        "Imported {} subscribers, {} rows with errors",
+        report.imported,
+        report.errors.len()
+    );
+    Ok(report)
+}
+
+/// Insert a batch of subscribers with a single statement, skipping subscribers that already exist;
+/// returns the emails and ids of the subscribers that were inserted
+async fn insert_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    batch: &[ParsedRow],
+    mode: ImportMode,
+    context: &ConsentContext,
+) -> Result<Vec<(String, Uuid)>, sqlx::Error> {
+    let status = match mode {
+        ImportMode::PreConfirmed => "confirmed",
+        ImportMode::SendConfirmation => "pending_confirmation",
+    };
+    let ids = batch.iter().map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+    let emails = batch
+        .iter()
+        .map(|r| r.subscriber.email.as_ref().to_string())
+        .collect::<Vec<_>>();
+    let canonical_emails = batch
+        .iter()
+        .map(|r| r.subscriber.email.canonical().to_string())
+        .collect::<Vec<_>>();
+    let names = batch
+        .iter()
+        .map(|r| r.subscriber.name.as_ref().to_string())
+        .collect::<Vec<_>>();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+        SELECT id, email, canonical_email, name, $5, $6
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[])
+            AS t(id, email, canonical_email, name)
+        ON CONFLICT (canonical_email) DO NOTHING
+        RETURNING email, id
+        "#,
+        &ids,
+        &emails,
+        &canonical_emails,
+        &names,
+        Utc::now(),
+        status,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to insert batch of subscribers: {:?}", e);
+        e
+    })?
+    .into_iter()
+    .map(|r| (r.email, r.id))
+    .collect::<Vec<_>>();
+
+    record_subscription_events(
+        transaction,
+        &inserted,
+        SubscriptionEventKind::Subscribed,
+        context,
+    )
+    .await?;
+    if mode == ImportMode::PreConfirmed {
+        record_subscription_events(
+            transaction,
+            &inserted,
+            SubscriptionEventKind::Confirmed,
+            context,
+        )
+        .await?;
+    }
+    Ok(inserted)
+}
+
+/// Store a new subscription token for each inserted subscriber; returns ids and tokens
+async fn store_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    inserted: &[(String, Uuid)],
+) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+    let ids = inserted.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+    let tokens = inserted
+        .iter()
+        .map(|_| generate_subscription_token())
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at)
+        SELECT token, id, $3 FROM UNNEST($1::text[], $2::uuid[]) AS t(token, id)
+        "#,
+        &tokens,
+        &ids,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to store subscription tokens: {:?}", e);
+        e
+    })?;
+    Ok(ids.into_iter().zip(tokens).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+    use crate::domain::{EmailNormalization, NamePolicy};
+    use claims::assert_ok;
+
+    #[test]
+    fn valid_rows_are_parsed() {
+        let csv = "email,name\nursula@gmail.com,Ursula\nle.guin@gmail.com,Le Guin\n";
+        let (rows, errors) = assert_ok!(parse_csv(
+            csv.as_bytes(),
+            &EmailNormalization::default(),
+            &NamePolicy::default()
+        ));
+        assert_eq!(rows.len(), 2);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn invalid_rows_are_reported_with_their_line() {
+        let csv = "email,name\nursula@gmail.com,Ursula\nnot-an-email,Le Guin\nle.guin@gmail.com,\n";
+        let (rows, errors) = assert_ok!(parse_csv(
+            csv.as_bytes(),
+            &EmailNormalization::default(),
+            &NamePolicy::default()
+        ));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+    }
+
+    #[test]
+    fn duplicate_emails_in_file_are_reported() {
+        let csv = "email,name\nursula@gmail.com,Ursula\nursula@gmail.com,Ursula\n";
+        let (rows, errors) = assert_ok!(parse_csv(
+            csv.as_bytes(),
+            &EmailNormalization::default(),
+            &NamePolicy::default()
+        ));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+    }
+
+    #[test]
+    fn emails_with_the_same_canonical_form_are_reported_as_duplicates() {
+        let csv = "email,name\nursula@gmail.com,Ursula\nUrsula@Gmail.com,Ursula\n";
+        let (rows, errors) = assert_ok!(parse_csv(
+            csv.as_bytes(),
+            &EmailNormalization::default(),
+            &NamePolicy::default()
+        ));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn missing_column_returns_error() {
+        let csv = "email\nursula@gmail.com\n";
+        assert!(parse_csv(
+            csv.as_bytes(),
+            &EmailNormalization::default(),
+            &NamePolicy::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use actix_web::HttpRequest;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::client_ip::ClientIp;
+use crate::webhooks::{enqueue_webhook_deliveries, WebhookEvent};
+
+/// Kind of change to a subscription that we record as proof of consent
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionEventKind {
+    Subscribed,
+    /// a pending subscriber subscribed again and was sent a new confirmation link
+    ConfirmationResent,
+    Confirmed,
+    /// an admin set the status to confirmed, rather than the subscriber following the link
+    ConfirmedByAdmin,
+    /// an admin set the status of a confirmed subscriber back to pending
+    UnconfirmedByAdmin,
+    Unsubscribed,
+}
+
+impl SubscriptionEventKind {
+    const ALL: [SubscriptionEventKind; 6] = [
+        SubscriptionEventKind::Subscribed,
+        SubscriptionEventKind::ConfirmationResent,
+        SubscriptionEventKind::Confirmed,
+        SubscriptionEventKind::ConfirmedByAdmin,
+        SubscriptionEventKind::UnconfirmedByAdmin,
+        SubscriptionEventKind::Unsubscribed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEventKind::Subscribed => "subscribed",
+            SubscriptionEventKind::ConfirmationResent => "confirmation_resent",
+            SubscriptionEventKind::Confirmed => "confirmed",
+            SubscriptionEventKind::ConfirmedByAdmin => "confirmed_by_admin",
+            SubscriptionEventKind::UnconfirmedByAdmin => "unconfirmed_by_admin",
+            SubscriptionEventKind::Unsubscribed => "unsubscribed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == s)
+    }
+}
+
+/// Context of the request in which the subscriber gave (or withdrew) their consent
+#[derive(Debug)]
+pub struct ConsentContext {
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub form_source: Option<String>,
+    pub consent_text_version: String,
+}
+
+impl ConsentContext {
+    pub fn from_request(
+        request: &HttpRequest,
+        form_source: Option<String>,
+        consent_text_version: &str,
+    ) -> Self {
+        let source_ip = ClientIp::of(request).map(|ip| ip.to_string());
+        let user_agent = request
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        Self {
+            source_ip,
+            user_agent,
+            form_source,
+            consent_text_version: consent_text_version.to_string(),
+        }
+    }
+}
+
+/// Append an event to the consent audit trail and queue it for webhooks; takes a transaction so
+/// that the event is only persisted together with the state change it records
+#[tracing::instrument(
+    name = "Record subscription event",
+    skip(transaction, email, context),
+    fields(event_type = kind.as_str())
+)]
+pub async fn record_subscription_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &str,
+    kind: SubscriptionEventKind,
+    context: &ConsentContext,
+) -> Result<(), sqlx::Error> {
+    let event_id = Uuid::new_v4();
+    let occurred_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (
+            id, subscriber_id, email, event_type, occurred_at,
+            source_ip, user_agent, form_source, consent_text_version
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+        event_id,
+        subscriber_id,
+        email,
+        kind.as_str(),
+        occurred_at,
+        context.source_ip,
+        context.user_agent,
+        context.form_source,
+        context.consent_text_version,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to record subscription event: {:?}", e);
+        e
+    })?;
+    let event = WebhookEvent {
+        event_id,
+        subscriber_id,
+        email,
+    };
+    enqueue_webhook_deliveries(transaction, kind, &[event], occurred_at).await
+}
+
+/// Append the same event for many subscribers at once, given as pairs of email and id
+#[tracing::instrument(
+    name = "Record subscription events",
+    skip(transaction, subscribers, context),
+    fields(event_type = kind.as_str(), n = subscribers.len())
+)]
+pub async fn record_subscription_events(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscribers: &[(String, Uuid)],
+    kind: SubscriptionEventKind,
+    context: &ConsentContext,
+) -> Result<(), sqlx::Error> {
+    let ids = subscribers
+        .iter()
+        .map(|_| Uuid::new_v4())
+        .collect::<Vec<_>>();
+    let (emails, subscriber_ids): (Vec<String>, Vec<Uuid>) = subscribers.iter().cloned().unzip();
+    let occurred_at = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_events (
+            id, subscriber_id, email, event_type, occurred_at,
+            source_ip, user_agent, form_source, consent_text_version
+        )
+        SELECT id, subscriber_id, email, $4, $5, $6, $7, $8, $9
+        FROM UNNEST($1::uuid[], $2::uuid[], $3::text[]) AS t(id, subscriber_id, email)
+        "#,
+        &ids,
+        &subscriber_ids,
+        &emails,
+        kind.as_str(),
+        occurred_at,
+        context.source_ip,
+        context.user_agent,
+        context.form_source,
+        context.consent_text_version,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to record subscription events: {:?}", e);
+        e
+    })?;
+    let events = subscribers
+        .iter()
+        .zip(&ids)
+        .map(|((email, subscriber_id), event_id)| WebhookEvent {
+            event_id: *event_id,
+            subscriber_id: *subscriber_id,
+            email,
+        })
+        .collect::<Vec<_>>();
+    enqueue_webhook_deliveries(transaction, kind, &events, occurred_at).await
+}
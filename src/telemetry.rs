@@ -3,23 +3,41 @@ use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::fmt::MakeWriter;
-use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, Registry};
+use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, reload, Registry};
+
+/// Handle to change the log level while the app is running
+#[derive(Clone)]
+pub struct LogLevel(reload::Handle<EnvFilter, Registry>);
+
+impl LogLevel {
+    /// Apply a level or filter like `info` or `info,sqlx=warn`; ignored while `RUST_LOG` is set,
+    /// which takes precedence over the config
+    pub fn set(&self, level: &str) -> Result<(), String> {
+        if std::env::var("RUST_LOG").is_ok() {
+            return Ok(());
+        }
+        let env_filter = EnvFilter::try_new(level).map_err(|e| e.to_string())?;
+        self.0.reload(env_filter).map_err(|e| e.to_string())
+    }
+}
 
 // using generic Sink type constrained in the where clause below
 fn build_tracing_subscriber<Sink>(
     name: String,
     level: String,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+) -> (impl Subscriber + Send + Sync, LogLevel)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(formatting_layer);
+    (subscriber, LogLevel(handle))
 }
 
 fn init_tracing_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -29,10 +47,11 @@ fn init_tracing_subscriber(subscriber: impl Subscriber + Send + Sync) {
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
 
-pub fn configure_tracing<Sink>(name: String, level: String, sink: Sink)
+pub fn configure_tracing<Sink>(name: String, level: String, sink: Sink) -> LogLevel
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let subscriber = build_tracing_subscriber(name, level, sink);
+    let (subscriber, log_level) = build_tracing_subscriber(name, level, sink);
     init_tracing_subscriber(subscriber);
+    log_level
 }
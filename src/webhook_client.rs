@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// Signature of a payload sent at `timestamp`, `t=<unix timestamp>,v1=<hex HMAC-SHA256>`;
+/// the MAC covers `<unix timestamp>.<payload>`, so that receivers can reject replayed requests
+pub fn sign_payload(secret: &str, timestamp: DateTime<Utc>, payload: &str) -> String {
+    let timestamp = timestamp.timestamp();
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    format!("t={},v1={}", timestamp, signature)
+}
+
+/// Request to one webhook endpoint
+pub struct WebhookRequest<'a> {
+    pub delivery_id: Uuid,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub event_type: &'a str,
+    pub payload: &'a str,
+}
+
+#[derive(Debug)]
+pub struct WebhookClient {
+    http_client: Client,
+}
+
+impl WebhookClient {
+    pub fn new(timeout: std::time::Duration) -> Self {
+        Self {
+            http_client: Client::builder().timeout(timeout).build().unwrap(),
+        }
+    }
+
+    /// Post the signed payload; returns the status code of the endpoint if it is a success
+    pub async fn send(&self, request: &WebhookRequest<'_>) -> Result<u16, reqwest::Error> {
+        let signature = sign_payload(request.secret, Utc::now(), request.payload);
+        let response = self
+            .http_client
+            .post(request.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .header(EVENT_HEADER, request.event_type)
+            .header(DELIVERY_HEADER, request.delivery_id.to_string())
+            .body(request.payload.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.status().as_u16())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign_payload, WebhookClient, WebhookRequest, SIGNATURE_HEADER};
+    use chrono::DateTime;
+    use claims::{assert_err, assert_ok_eq};
+    use uuid::Uuid;
+    use wiremock::matchers::{header, header_exists, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn make_request(url: &str) -> WebhookRequest<'_> {
+        WebhookRequest {
+            delivery_id: Uuid::new_v4(),
+            url,
+            secret: "secret",
+            event_type: "subscriber.confirmed",
+            payload: r#"{"type":"subscriber.confirmed"}"#,
+        }
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_payload() {
+        let timestamp = DateTime::from_timestamp(1760000000, 0).unwrap();
+        let signature = sign_payload("secret", timestamp, "{}");
+        // echo -n '1760000000.{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            signature,
+            "t=1760000000,v1=53dc054739ad94d3532227ba8d397f66ed2166a6b66940c2006692fa6db6829f"
+        );
+        assert_ne!(signature, sign_payload("other", timestamp, "{}"));
+        assert_ne!(signature, sign_payload("secret", timestamp, "{ }"));
+    }
+
+    #[tokio::test]
+    async fn send_posts_signed_payload() {
+        // arrange
+        let server = MockServer::start().await;
+        let client = WebhookClient::new(std::time::Duration::from_millis(200));
+        Mock::given(method("POST"))
+            .and(header_exists(SIGNATURE_HEADER))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // act
+        let result = client.send(&make_request(&server.uri())).await;
+
+        // assert
+        assert_ok_eq!(result, 202);
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_endpoint_responds_500() {
+        // arrange
+        let server = MockServer::start().await;
+        let client = WebhookClient::new(std::time::Duration::from_millis(200));
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // act
+        let result = client.send(&make_request(&server.uri())).await;
+
+        // assert
+        let e = assert_err!(result);
+        assert_eq!(e.status().unwrap().as_u16(), 500);
+    }
+}
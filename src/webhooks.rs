@@ -0,0 +1,472 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::config::WebhooksConfig;
+use crate::subscription_events::SubscriptionEventKind;
+use crate::webhook_client::{WebhookClient, WebhookRequest};
+
+/// Secrets start with this, so that they are easy to recognize, e.g. by secret scanners
+const SECRET_PREFIX: &str = "whsec_";
+const SECRET_LENGTH: usize = 32;
+/// Claimed deliveries are retried after this, should the worker die while sending them
+const DELIVERY_LEASE: TimeDelta = TimeDelta::minutes(5);
+const MAX_RETRY_DELAY: TimeDelta = TimeDelta::hours(6);
+/// Number of deliveries shown in the log of an endpoint
+const MAX_LISTED_DELIVERIES: i64 = 100;
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, utoipa::ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Succeeded => "succeeded",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Endpoint without its secret, as shown to admins
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub event_types: Vec<SubscriptionEventKind>,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+struct WebhookEndpointRow {
+    id: Uuid,
+    url: String,
+    event_types: Vec<String>,
+    created_at: DateTime<Utc>,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+impl From<WebhookEndpointRow> for WebhookEndpoint {
+    fn from(row: WebhookEndpointRow) -> Self {
+        Self {
+            id: row.id,
+            url: row.url,
+            event_types: row
+                .event_types
+                .iter()
+                .filter_map(|s| SubscriptionEventKind::parse(s))
+                .collect(),
+            created_at: row.created_at,
+            disabled_at: row.disabled_at,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    /// id of the event, the same for deliveries of the event to different endpoints
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body sent to the endpoints, e.g. `{"id": ..., "type": "subscriber.confirmed", ...}`
+#[derive(serde::Serialize)]
+struct WebhookPayload<'a> {
+    id: Uuid,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    occurred_at: DateTime<Utc>,
+    data: WebhookPayloadData<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct WebhookPayloadData<'a> {
+    subscriber_id: Uuid,
+    email: &'a str,
+}
+
+/// Event sent to endpoints, as recorded in the subscription events
+pub struct WebhookEvent<'a> {
+    pub event_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub email: &'a str,
+}
+
+fn event_type(kind: SubscriptionEventKind) -> String {
+    format!("subscriber.{}", kind.as_str())
+}
+
+fn generate_secret() -> String {
+    let mut rng = thread_rng();
+    let secret = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(SECRET_LENGTH)
+        .collect::<String>();
+    format!("{}{}", SECRET_PREFIX, secret)
+}
+
+/// Register an endpoint; returns the secret payloads are signed with, which can't be
+/// retrieved later
+#[tracing::instrument(name = "Create webhook endpoint", skip(db))]
+pub async fn create_webhook_endpoint(
+    db: impl PgExecutor<'_>,
+    url: &str,
+    event_types: &[SubscriptionEventKind],
+) -> Result<(WebhookEndpoint, String), sqlx::Error> {
+    let secret = generate_secret();
+    let event_types = event_types
+        .iter()
+        .map(|kind| kind.as_str().to_string())
+        .collect::<Vec<_>>();
+    let row = sqlx::query_as!(
+        WebhookEndpointRow,
+        r#"
+        INSERT INTO webhook_endpoints (id, url, secret, event_types, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, url, event_types, created_at, disabled_at
+        "#,
+        Uuid::new_v4(),
+        url,
+        secret,
+        &event_types,
+        Utc::now(),
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to create webhook endpoint: {:?}", e);
+        e
+    })?;
+    Ok((row.into(), secret))
+}
+
+#[tracing::instrument(name = "List webhook endpoints", skip(db_pool))]
+pub async fn list_webhook_endpoints(db_pool: &PgPool) -> Result<Vec<WebhookEndpoint>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        WebhookEndpointRow,
+        r#"
+        SELECT id, url, event_types, created_at, disabled_at
+        FROM webhook_endpoints
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list webhook endpoints: {:?}", e);
+        e
+    })?;
+    Ok(rows.into_iter().map(WebhookEndpoint::from).collect())
+}
+
+/// Stop sending events to an endpoint, failing its pending deliveries; returns whether there
+/// was an enabled endpoint with the id
+#[tracing::instrument(name = "Disable webhook endpoint", skip(transaction))]
+pub async fn disable_webhook_endpoint(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let disabled = sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints SET disabled_at = $2
+        WHERE id = $1 AND disabled_at IS NULL
+        "#,
+        id,
+        Utc::now(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries SET status = 'failed', last_error = 'endpoint disabled'
+        WHERE endpoint_id = $1 AND status = 'pending'
+        "#,
+        id,
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(disabled.rows_affected() > 0)
+}
+
+/// Latest deliveries to an endpoint, optionally only those with the given status
+#[tracing::instrument(name = "List webhook deliveries", skip(db_pool))]
+pub async fn list_webhook_deliveries(
+    db_pool: &PgPool,
+    endpoint_id: Uuid,
+    status: Option<DeliveryStatus>,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT id, endpoint_id, event_id, event_type, payload, status, attempts,
+            next_attempt_at, last_attempt_at, last_response_status, last_error, created_at
+        FROM webhook_deliveries
+        WHERE endpoint_id = $1 AND ($2::text IS NULL OR status = $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        endpoint_id,
+        status.map(|s| s.as_str()),
+        MAX_LISTED_DELIVERIES,
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to list webhook deliveries: {:?}", e);
+        e
+    })
+}
+
+/// Queue a failed delivery to be sent again right away, with a fresh set of attempts; returns
+/// whether there was a failed delivery to an enabled endpoint with the id
+#[tracing::instrument(name = "Replay webhook delivery", skip(db_pool))]
+pub async fn replay_webhook_delivery(db_pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE webhook_deliveries d
+        SET status = 'pending', attempts = 0, next_attempt_at = $2
+        FROM webhook_endpoints e
+        WHERE d.id = $1 AND d.status = 'failed'
+        AND e.id = d.endpoint_id AND e.disabled_at IS NULL
+        "#,
+        id,
+        Utc::now(),
+    )
+    .execute(db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to replay webhook delivery: {:?}", e);
+        e
+    })?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Queue deliveries of the events to all endpoints listening for them; takes the transaction
+/// recording the events, so that events are sent if and only if they happened
+#[tracing::instrument(
+    name = "Enqueue webhook deliveries",
+    skip(transaction, events),
+    fields(n = events.len())
+)]
+pub async fn enqueue_webhook_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    kind: SubscriptionEventKind,
+    events: &[WebhookEvent<'_>],
+    occurred_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let event_type = event_type(kind);
+    let event_ids = events.iter().map(|e| e.event_id).collect::<Vec<_>>();
+    let payloads = events
+        .iter()
+        .map(|e| {
+            serde_json::to_string(&WebhookPayload {
+                id: e.event_id,
+                event_type: &event_type,
+                occurred_at,
+                data: WebhookPayloadData {
+                    subscriber_id: e.subscriber_id,
+                    email: e.email,
+                },
+            })
+            .expect("payload can be serialized")
+        })
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries (
+            id, endpoint_id, event_id, event_type, payload, status, attempts,
+            next_attempt_at, created_at
+        )
+        SELECT gen_random_uuid(), e.id, p.event_id, $1, p.payload, 'pending', 0, $2, $2
+        FROM webhook_endpoints e
+        CROSS JOIN UNNEST($3::uuid[], $4::text[]) AS p(event_id, payload)
+        WHERE e.disabled_at IS NULL AND $5 = ANY(e.event_types)
+        "#,
+        event_type,
+        occurred_at,
+        &event_ids,
+        &payloads,
+        kind.as_str(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to enqueue webhook deliveries: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Delay before the next attempt, after `attempts` failed ones
+fn retry_delay(base_delay: TimeDelta, attempts: i32) -> TimeDelta {
+    let factor = 2_i32.saturating_pow(attempts.saturating_sub(1).clamp(0, 30) as u32);
+    base_delay
+        .checked_mul(factor)
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+struct DueDelivery {
+    id: Uuid,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Send deliveries that are due, at most one batch; returns the number of deliveries attempted
+#[tracing::instrument(name = "Deliver webhooks", skip_all)]
+pub async fn deliver_due_webhooks(
+    db_pool: &PgPool,
+    client: &WebhookClient,
+    config: &WebhooksConfig,
+) -> Result<usize, sqlx::Error> {
+    let now = Utc::now();
+    // claim the deliveries by pushing back their next attempt, so that other instances skip
+    // them while we are sending
+    let due = sqlx::query_as!(
+        DueDelivery,
+        r#"
+        UPDATE webhook_deliveries d
+        SET next_attempt_at = $2
+        FROM webhook_endpoints e
+        WHERE e.id = d.endpoint_id AND d.id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= $1
+            ORDER BY next_attempt_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING d.id, d.event_type, d.payload, d.attempts, e.url, e.secret
+        "#,
+        now,
+        now + DELIVERY_LEASE,
+        i64::from(config.batch_size),
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    let requests = due
+        .iter()
+        .map(|delivery| WebhookRequest {
+            delivery_id: delivery.id,
+            url: &delivery.url,
+            secret: &delivery.secret,
+            event_type: &delivery.event_type,
+            payload: &delivery.payload,
+        })
+        .collect::<Vec<_>>();
+    let results =
+        futures_util::future::join_all(requests.iter().map(|request| client.send(request))).await;
+
+    let now = Utc::now();
+    for (delivery, result) in due.iter().zip(results) {
+        let attempts = delivery.attempts + 1;
+        let (status, response_status, error) = match result {
+            Ok(response_status) => (DeliveryStatus::Succeeded, Some(response_status), None),
+            Err(e) => {
+                tracing::warn!("Failed to deliver webhook {}: {:?}", delivery.id, e);
+                let status = if attempts >= config.max_attempts as i32 {
+                    DeliveryStatus::Failed
+                } else {
+                    DeliveryStatus::Pending
+                };
+                (status, e.status().map(|s| s.as_u16()), Some(e.to_string()))
+            }
+        };
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $2, attempts = $3, next_attempt_at = $4, last_attempt_at = $5,
+                last_response_status = $6, last_error = $7
+            WHERE id = $1
+            "#,
+            delivery.id,
+            status.as_str(),
+            attempts,
+            now + retry_delay(
+                TimeDelta::from_std(config.retry_base_delay)
+                    .expect("delay is bounded by the config validation"),
+                attempts,
+            ),
+            now,
+            response_status.map(i32::from),
+            error,
+        )
+        .execute(db_pool)
+        .await?;
+    }
+    Ok(due.len())
+}
+
+/// Periodically send due webhook deliveries, retrying failed ones with exponential backoff
+pub async fn run_webhook_worker_until_stopped(
+    db_pool: PgPool,
+    config: WebhooksConfig,
+) -> Result<(), std::io::Error> {
+    tracing::info!("Launching webhook worker ...");
+    let client = WebhookClient::new(config.timeout);
+    loop {
+        match deliver_due_webhooks(&db_pool, &client, &config).await {
+            // a full batch means more deliveries are probably due, so we don't wait
+            Ok(n) if n == config.batch_size as usize => continue,
+            Ok(_) => {}
+            // a failed run is retried on the next tick, we don't want to bring down the app
+            Err(e) => tracing::error!("Failed to deliver webhooks: {:?}", e),
+        }
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{event_type, retry_delay, MAX_RETRY_DELAY};
+    use crate::subscription_events::SubscriptionEventKind;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn event_types_tell_subscriber_and_admin_changes_apart() {
+        assert_eq!(
+            event_type(SubscriptionEventKind::ConfirmationResent),
+            "subscriber.confirmation_resent"
+        );
+        assert_eq!(
+            event_type(SubscriptionEventKind::ConfirmedByAdmin),
+            "subscriber.confirmed_by_admin"
+        );
+        assert_eq!(
+            event_type(SubscriptionEventKind::UnconfirmedByAdmin),
+            "subscriber.unconfirmed_by_admin"
+        );
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_maximum() {
+        let base = TimeDelta::seconds(30);
+        assert_eq!(retry_delay(base, 1), TimeDelta::seconds(30));
+        assert_eq!(retry_delay(base, 2), TimeDelta::seconds(60));
+        assert_eq!(retry_delay(base, 4), TimeDelta::seconds(240));
+        assert_eq!(retry_delay(base, 20), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(base, i32::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::helpers::{spwan_app, TestApp};
+
+async fn post_api_key(app: &TestApp, body: &serde_json::Value) -> reqwest::Response {
+    app.admin_client()
+        .post(format!("{}/api/v1/admin/api_keys", app.address))
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_subscribers_with_key(app: &TestApp, key: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/api/v1/admin/subscribers", app.address))
+        .bearer_auth(key)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admin_routes_reject_requests_without_a_valid_key() {
+    // arange
+    let app = spwan_app().await;
+    let url = format!("{}/api/v1/admin/subscribers", app.address);
+
+    // act
+    let without_key = reqwest::get(&url).await.unwrap();
+    let with_unknown_key = get_subscribers_with_key(&app, "z2p_unknown").await;
+
+    // assert
+    for response in [without_key, with_unknown_key] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+    }
+}
+
+#[tokio::test]
+async fn keys_only_grant_their_scopes() {
+    // arange
+    let app = spwan_app().await;
+    let response = post_api_key(
+        &app,
+        &serde_json::json!({"name": "crm", "scopes": ["subscribers:read"]}),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let key = created["key"].as_str().unwrap();
+
+    // act
+    let read = get_subscribers_with_key(&app, key).await;
+    let manage = reqwest::Client::new()
+        .get(format!("{}/api/v1/admin/blocklist", app.address))
+        .bearer_auth(key)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(manage.status().as_u16(), 403);
+    assert_eq!(
+        manage.text().await.unwrap(),
+        "Missing scope: blocklist:manage"
+    );
+}
+
+#[tokio::test]
+async fn created_keys_are_listed_without_their_secret_and_track_last_use() {
+    // arange
+    let app = spwan_app().await;
+    let created: serde_json::Value = post_api_key(
+        &app,
+        &serde_json::json!({"name": "crm", "scopes": ["subscribers:read"]}),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let key = created["key"].as_str().unwrap();
+    assert!(key.starts_with(created["key_prefix"].as_str().unwrap()));
+    get_subscribers_with_key(&app, key).await;
+
+    // act
+    let keys: serde_json::Value = app
+        .admin_client()
+        .get(format!("{}/api/v1/admin/api_keys", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    let crm = keys
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|k| k["id"] == created["id"])
+        .unwrap();
+    assert_eq!(crm["name"], "crm");
+    assert_eq!(crm["scopes"], serde_json::json!(["subscribers:read"]));
+    assert!(crm.get("key").is_none());
+    assert!(!crm["last_used_at"].is_null());
+    let stored_hash = sqlx::query!("SELECT key_hash FROM api_keys WHERE name = 'crm'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .key_hash;
+    assert!(!stored_hash.contains(key));
+}
+
+#[tokio::test]
+async fn revoked_keys_are_rejected() {
+    // arange
+    let app = spwan_app().await;
+    let created: serde_json::Value = post_api_key(
+        &app,
+        &serde_json::json!({"name": "crm", "scopes": ["subscribers:read"]}),
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    let url = format!(
+        "{}/api/v1/admin/api_keys/{}",
+        app.address,
+        created["id"].as_str().unwrap()
+    );
+
+    // act
+    let revoked = app.admin_client().delete(&url).send().await.unwrap();
+    let revoked_again = app.admin_client().delete(&url).send().await.unwrap();
+    let response = get_subscribers_with_key(&app, created["key"].as_str().unwrap()).await;
+
+    // assert
+    assert_eq!(revoked.status().as_u16(), 204);
+    assert_eq!(revoked_again.status().as_u16(), 404);
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn keys_need_a_name_and_known_scopes() {
+    // arange
+    let app = spwan_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": " ", "scopes": ["subscribers:read"]}),
+            400,
+        ),
+        (serde_json::json!({"name": "crm", "scopes": []}), 400),
+        (
+            serde_json::json!({"name": "crm", "scopes": ["everything"]}),
+            400,
+        ),
+    ];
+
+    for (body, status) in test_cases {
+        // act
+        let response = post_api_key(&app, &body).await;
+
+        // assert
+        assert_eq!(response.status().as_u16(), status, "body: {}", body);
+    }
+}
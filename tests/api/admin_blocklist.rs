@@ -0,0 +1,167 @@
+use crate::helpers::{spwan_app, spwan_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+fn subscription_body(email: &str) -> String {
+    format!("name=le%20guin&email={}", email.replace('@', "%40"))
+}
+
+#[tokio::test]
+async fn subscribe_rejects_blocked_domains_and_their_subdomains() {
+    // arange
+    let app = spwan_app().await;
+    mount_email_server(&app).await;
+    let response = app
+        .post_blocklist(&serde_json::json!({"value": "Spam.com", "reason": "abuse"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for email in ["ursula@spam.com", "ursula@mail.spam.com", "ursula@SPAM.com"] {
+        // act
+        let response = app.post_subscription(subscription_body(email)).await;
+
+        // assert
+        assert_eq!(response.status().as_u16(), 400, "email: {}", email);
+    }
+    let response = app
+        .post_subscription(subscription_body("ursula@notspam.com"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_blocked_addresses() {
+    // arange
+    let app = spwan_app().await;
+    mount_email_server(&app).await;
+    app.post_blocklist(&serde_json::json!({"value": "ursula@gmail.com"}))
+        .await;
+
+    // act
+    let blocked = app
+        .post_subscription(subscription_body("Ursula@gmail.com"))
+        .await;
+    let other = app
+        .post_subscription(subscription_body("le_guin@gmail.com"))
+        .await;
+
+    // assert
+    assert_eq!(blocked.status().as_u16(), 400);
+    assert_eq!(other.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_aliases_of_blocked_addresses() {
+    // arange
+    let app = spwan_app_with(|config| config.subscriptions.fold_provider_aliases = true).await;
+    mount_email_server(&app).await;
+    app.post_blocklist(&serde_json::json!({"value": "Ursula.LeGuin@gmail.com"}))
+        .await;
+
+    for email in [
+        "ursulaleguin@gmail.com",
+        "ursula.le.guin%2Bnews@googlemail.com",
+    ] {
+        // act
+        let response = app.post_subscription(subscription_body(email)).await;
+
+        // assert
+        assert_eq!(response.status().as_u16(), 400, "email: {}", email);
+    }
+}
+
+#[tokio::test]
+async fn unblocked_domains_are_accepted_again() {
+    // arange
+    let app = spwan_app().await;
+    mount_email_server(&app).await;
+    app.post_blocklist(&serde_json::json!({"value": "spam.com"}))
+        .await;
+
+    // act
+    let response = app
+        .admin_client()
+        .delete(format!("{}/api/v1/admin/blocklist/spam.com", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 204);
+    let response = app
+        .post_subscription(subscription_body("ursula@spam.com"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn blocklist_lists_entries_with_their_kind() {
+    // arange
+    let app = spwan_app().await;
+    app.post_blocklist(&serde_json::json!({"value": "spam.com", "reason": "abuse"}))
+        .await;
+    app.post_blocklist(&serde_json::json!({"value": "ursula@gmail.com"}))
+        .await;
+
+    // act
+    let entries: serde_json::Value = app
+        .admin_client()
+        .get(format!("{}/api/v1/admin/blocklist", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["value"], "spam.com");
+    assert_eq!(entries[0]["kind"], "domain");
+    assert_eq!(entries[0]["reason"], "abuse");
+    assert_eq!(entries[1]["value"], "ursula@gmail.com");
+    assert_eq!(entries[1]["kind"], "address");
+}
+
+#[tokio::test]
+async fn blocklist_rejects_invalid_values() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let response = app
+        .post_blocklist(&serde_json::json!({"value": "not a domain"}))
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_disposable_domains_if_configured() {
+    for (block_disposable_domains, expected_status) in [(true, 400), (false, 200)] {
+        // arange
+        let app = spwan_app_with(|config| {
+            config.email_policy.block_disposable_domains = block_disposable_domains;
+        })
+        .await;
+        mount_email_server(&app).await;
+
+        // act
+        let response = app
+            .post_subscription(subscription_body("ursula@mailinator.com"))
+            .await;
+
+        // assert
+        assert_eq!(response.status().as_u16(), expected_status);
+    }
+}
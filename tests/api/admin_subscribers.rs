@@ -0,0 +1,274 @@
+use crate::helpers::{spwan_app, TestApp};
+use uuid::Uuid;
+
+/// Import confirmed subscribers named `subscriber<i>`, returned in the order they were listed
+async fn create_subscribers(app: &TestApp, n: usize) -> Vec<serde_json::Value> {
+    let csv = std::iter::once("email,name".to_string())
+        .chain((0..n).map(|i| format!("subscriber{}@gmail.com,subscriber{}", i, i)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    app.post_subscribers_import(csv, "pre_confirmed").await;
+    let page: serde_json::Value = app.get_subscribers("limit=500").await.json().await.unwrap();
+    page["subscribers"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn list_subscribers_paginates_through_all_subscribers() {
+    // arange
+    let app = spwan_app().await;
+    let all = create_subscribers(&app, 5).await;
+
+    // act, follow the cursor until there are no pages left
+    let mut listed = Vec::new();
+    let mut query = "limit=2".to_string();
+    loop {
+        let response = app.get_subscribers(&query).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let page: serde_json::Value = response.json().await.unwrap();
+        listed.extend(page["subscribers"].as_array().unwrap().clone());
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=2&after={}", cursor),
+            None => break,
+        }
+    }
+
+    // assert
+    assert_eq!(listed, all);
+}
+
+#[tokio::test]
+async fn list_subscribers_in_descending_order() {
+    // arange
+    let app = spwan_app().await;
+    let mut all = create_subscribers(&app, 3).await;
+
+    // act
+    let page: serde_json::Value = app
+        .get_subscribers("order=desc")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    all.reverse();
+    assert_eq!(page["subscribers"].as_array().unwrap(), &all);
+}
+
+#[tokio::test]
+async fn list_subscribers_filters_by_email_substring_status_and_tag() {
+    // arange
+    let app = spwan_app().await;
+    let all = create_subscribers(&app, 12).await;
+    let tagged_id = all[0]["id"].as_str().unwrap();
+    app.admin_client()
+        .patch(format!(
+            "{}/api/v1/admin/subscribers/{}",
+            app.address, tagged_id
+        ))
+        .json(&serde_json::json!({"tags": ["vip"]}))
+        .send()
+        .await
+        .unwrap();
+    let test_cases = vec![
+        ("email=SUBSCRIBER1", 3, "email substring, case-insensitive"),
+        ("email=%25", 0, "email with wildcard"),
+        ("status=confirmed", 12, "status"),
+        ("status=pending_confirmation", 0, "status without matches"),
+        ("tag=vip", 1, "tag"),
+    ];
+
+    for (query, expected, description) in test_cases {
+        // act
+        let page: serde_json::Value = app.get_subscribers(query).await.json().await.unwrap();
+
+        // assert
+        assert_eq!(
+            page["subscribers"].as_array().unwrap().len(),
+            expected,
+            "unexpected number of subscribers for: {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn list_subscribers_returns_400_for_invalid_parameters() {
+    // arange
+    let app = spwan_app().await;
+    let test_cases = vec![
+        ("limit=0", "limit too small"),
+        ("limit=501", "limit too large"),
+        ("after=invalid", "invalid cursor"),
+        ("status=unknown", "unknown status"),
+        ("order=sideways", "unknown order"),
+    ];
+
+    for (query, description) in test_cases {
+        // act
+        let response = app.get_subscribers(query).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "did not fail when: {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn get_update_and_delete_a_subscriber() {
+    // arange
+    let app = spwan_app().await;
+    let all = create_subscribers(&app, 1).await;
+    let url = format!(
+        "{}/api/v1/admin/subscribers/{}",
+        app.address,
+        all[0]["id"].as_str().unwrap()
+    );
+    let client = app.admin_client();
+
+    // act & assert, get
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber, all[0]);
+
+    // act & assert, update
+    let response = client
+        .patch(&url)
+        .json(&serde_json::json!({"name": "Ursula", "status": "pending_confirmation"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["name"], "Ursula");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert_eq!(subscriber["email"], all[0]["email"]);
+
+    // act & assert, delete
+    let response = client.delete(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn status_changes_are_recorded_as_consent_events_and_in_the_audit_log() {
+    // arange
+    let app = spwan_app().await;
+    let all = create_subscribers(&app, 1).await;
+    let url = format!(
+        "{}/api/v1/admin/subscribers/{}",
+        app.address,
+        all[0]["id"].as_str().unwrap()
+    );
+    let client = app.admin_client();
+
+    // act
+    for status in ["pending_confirmation", "confirmed", "confirmed"] {
+        let response = client
+            .patch(&url)
+            .json(&serde_json::json!({ "status": status }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // assert
+    let events = sqlx::query!(
+        "SELECT event_type FROM subscription_events WHERE form_source = 'admin'
+        ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .map(|e| e.event_type.as_str())
+            .collect::<Vec<_>>(),
+        vec!["unconfirmed_by_admin", "confirmed_by_admin"]
+    );
+    let entries = sqlx::query!(
+        "SELECT action, target, details FROM audit_log
+        WHERE action = 'subscriber.status_changed' ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|e| (e.action.as_str(), e.details.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "subscriber.status_changed",
+                Some("confirmed -> pending_confirmation")
+            ),
+            (
+                "subscriber.status_changed",
+                Some("pending_confirmation -> confirmed")
+            ),
+        ]
+    );
+    assert!(entries
+        .iter()
+        .all(|e| e.target.as_deref() == all[0]["id"].as_str()));
+}
+
+#[tokio::test]
+async fn update_subscriber_returns_400_for_invalid_name() {
+    // arange
+    let app = spwan_app().await;
+    let all = create_subscribers(&app, 1).await;
+    let url = format!(
+        "{}/api/v1/admin/subscribers/{}",
+        app.address,
+        all[0]["id"].as_str().unwrap()
+    );
+
+    // act
+    let response = app
+        .admin_client()
+        .patch(&url)
+        .json(&serde_json::json!({"name": "<script>"}))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unknown_subscriber_returns_404() {
+    // arange
+    let app = spwan_app().await;
+    let url = format!(
+        "{}/api/v1/admin/subscribers/{}",
+        app.address,
+        Uuid::new_v4()
+    );
+    let client = app.admin_client();
+
+    // act
+    let get = client.get(&url).send().await.unwrap();
+    let patch = client
+        .patch(&url)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    let delete = client.delete(&url).send().await.unwrap();
+
+    // assert
+    assert_eq!(get.status().as_u16(), 404);
+    assert_eq!(patch.status().as_u16(), 404);
+    assert_eq!(delete.status().as_u16(), 404);
+}
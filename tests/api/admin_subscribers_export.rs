@@ -0,0 +1,101 @@
+use crate::helpers::{spwan_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Import confirmed subscribers and subscribe one more who stays pending
+async fn create_subscribers(app: &TestApp, n_confirmed: usize) {
+    let csv = std::iter::once("email,name".to_string())
+        .chain((0..n_confirmed).map(|i| format!("subscriber{}@gmail.com,Subscriber {}", i, i)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    app.post_subscribers_import(csv, "pre_confirmed").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+}
+
+#[tokio::test]
+async fn export_csv_returns_header_and_all_subscribers() {
+    // arange
+    let app = spwan_app().await;
+    create_subscribers(&app, 2).await;
+
+    // act
+    let response = app.get_subscribers_export("format=csv").await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv");
+    let body = response.text().await.unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert_eq!(lines.len(), 4);
+}
+
+#[tokio::test]
+async fn export_jsonl_filters_by_status() {
+    // arange
+    let app = spwan_app().await;
+    create_subscribers(&app, 2).await;
+
+    // act
+    let response = app
+        .get_subscribers_export("format=jsonl&status=pending_confirmation")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    let rows = body
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(rows[0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn export_streams_more_subscribers_than_fit_into_one_batch() {
+    // arange
+    let app = spwan_app().await;
+    create_subscribers(&app, 2500).await;
+
+    // act
+    let response = app
+        .get_subscribers_export("format=jsonl&status=confirmed")
+        .await;
+
+    // assert
+    let body = response.text().await.unwrap();
+    assert_eq!(body.lines().count(), 2500);
+}
+
+#[tokio::test]
+async fn export_returns_400_for_invalid_parameters() {
+    // arange
+    let app = spwan_app().await;
+    let test_cases = vec![
+        ("", "missing format"),
+        ("format=xml", "unknown format"),
+        ("format=csv&status=unknown", "unknown status"),
+    ];
+
+    for (query, description) in test_cases {
+        // act
+        let response = app.get_subscribers_export(query).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "did not fail when: {}",
+            description
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::helpers::spwan_app;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn import_pre_confirmed_stores_valid_rows_and_reports_invalid_ones() {
+    // arange
+    let app = spwan_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\n\
+        ursula@gmail.com,Ursula\n\
+        not-an-email,Le Guin\n\
+        octavia@gmail.com,Octavia\n";
+
+    // act
+    let response = app
+        .post_subscribers_import(csv.into(), "pre_confirmed")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["errors"].as_array().unwrap().len(), 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+    assert_eq!(report["errors"][0]["email"], "not-an-email");
+
+    let statuses = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses.iter().all(|r| r.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn import_send_confirmation_sends_an_email_per_imported_subscriber() {
+    // arange
+    let app = spwan_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula@gmail.com,Ursula\noctavia@gmail.com,Octavia\n";
+
+    // act
+    let response = app
+        .post_subscribers_import(csv.into(), "send_confirmation")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let statuses = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(statuses.iter().all(|r| r.status == "pending_confirmation"));
+}
+
+#[tokio::test]
+async fn import_reports_failed_confirmation_emails_and_imports_the_rest() {
+    // arange
+    let app = spwan_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_string_contains("octavia@gmail.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name
+        ursula@gmail.com,Ursula
+        octavia@gmail.com,Octavia
+        le.guin@gmail.com,Le Guin
+";
+
+    // act
+    let response = app
+        .post_subscribers_import(csv.into(), "send_confirmation")
+        .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 3);
+    assert_eq!(report["errors"].as_array().unwrap().len(), 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+    assert_eq!(report["errors"][0]["email"], "octavia@gmail.com");
+}
+
+#[tokio::test]
+async fn import_reports_already_existing_subscribers() {
+    // arange
+    let app = spwan_app().await;
+    let csv = "email,name\nursula@gmail.com,Ursula\n";
+    app.post_subscribers_import(csv.into(), "pre_confirmed")
+        .await;
+
+    // act, import the same subscriber again, once with their email spelled differently
+    let response = app
+        .post_subscribers_import(
+            "email,name\nursula@gmail.com,Ursula\nUrsula@GMAIL.com,Ursula\n".into(),
+            "pre_confirmed",
+        )
+        .await;
+
+    // assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["errors"][0]["error"], "Duplicate email in file");
+    assert_eq!(report["errors"][1]["error"], "Already subscribed");
+}
+
+#[tokio::test]
+async fn import_records_consent_events() {
+    // arange
+    let app = spwan_app().await;
+    let csv = "email,name\nursula@gmail.com,Ursula\n";
+
+    // act
+    app.post_subscribers_import(csv.into(), "pre_confirmed")
+        .await;
+
+    // assert
+    let events = sqlx::query!("SELECT event_type, form_source FROM subscription_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events
+        .iter()
+        .all(|e| e.form_source.as_deref() == Some("csv_import")));
+}
+
+#[tokio::test]
+async fn import_returns_400_for_invalid_mode_or_header() {
+    // arange
+    let app = spwan_app().await;
+    let test_cases = vec![
+        ("email,name\n", "unknown", "invalid mode"),
+        (
+            "email\nursula@gmail.com\n",
+            "pre_confirmed",
+            "missing name column",
+        ),
+    ];
+
+    for (csv, mode, description) in test_cases {
+        // act
+        let response = app.post_subscribers_import(csv.into(), mode).await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "did not fail when: {}",
+            description
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::helpers::{
+    browser_client, client_with_cookie, session_cookie, spwan_app, spwan_app_with, TestApp,
+    TEST_PASSWORD,
+};
+use zero2prod::auth::{totp, Algorithm, Role, DIGITS, STEP_SECS};
+
+/// Code of the authenticator app, `steps` time steps from now; each code is only accepted once,
+/// so tests use the next step's code for a second login
+fn code(secret: &str, steps: u64) -> String {
+    let secret = base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+    let now = chrono::Utc::now().timestamp() as u64;
+    totp(Algorithm::Sha1, &secret, now + steps * STEP_SECS, DIGITS)
+}
+
+/// Log in with the password, returning the session client or the challenge for the second step
+async fn log_in(app: &TestApp, email: &str) -> reqwest::Response {
+    app.post_login(email, TEST_PASSWORD).await
+}
+
+async fn post_second_factor(app: &TestApp, challenge: &str, code: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/totp", app.address))
+        .form(&[("challenge", challenge), ("code", code)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn challenge(app: &TestApp, email: &str) -> String {
+    let response = log_in(app, email).await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert!(session_cookie(&response).is_none());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["challenge"].as_str().unwrap().to_string()
+}
+
+/// Enroll the user of the session and return the secret and the recovery codes
+async fn enroll(app: &TestApp, session: &reqwest::Client) -> (String, Vec<String>) {
+    let enrollment: serde_json::Value = session
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let secret = enrollment["secret"].as_str().unwrap().to_string();
+    let response = session
+        .post(format!("{}/api/v1/admin/account/totp/confirm", app.address))
+        .json(&serde_json::json!({ "code": code(&secret, 0) }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+    (secret, recovery_codes)
+}
+
+async fn logged_in_user(app: &TestApp, role: Role) -> (String, reqwest::Client) {
+    let email = app.create_admin_user(role).await;
+    let response = log_in(app, &email).await;
+    let session = client_with_cookie(&session_cookie(&response).unwrap());
+    (email, session)
+}
+
+#[tokio::test]
+async fn enrollment_returns_a_provisioning_uri_and_a_qr_code() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+
+    // act
+    let response = session
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let enrollment: serde_json::Value = response.json().await.unwrap();
+    let secret = enrollment["secret"].as_str().unwrap();
+    assert_eq!(
+        enrollment["provisioning_uri"],
+        format!(
+            "otpauth://totp/Newsletter:{}?secret={}&issuer=Newsletter&algorithm=SHA1&digits=6&period=30",
+            email, secret
+        )
+    );
+    assert!(enrollment["qr_code_svg"].as_str().unwrap().contains("<svg"));
+}
+
+#[tokio::test]
+async fn enrollment_must_be_confirmed_with_a_valid_code() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+    let confirm = |code: String| {
+        session
+            .post(format!("{}/api/v1/admin/account/totp/confirm", app.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+    };
+
+    // act
+    let not_started = confirm("123456".into()).await.unwrap();
+    session
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .send()
+        .await
+        .unwrap();
+    let invalid = confirm("000000".into()).await.unwrap();
+    let login = log_in(&app, &email).await;
+
+    // assert
+    assert_eq!(not_started.status().as_u16(), 409);
+    assert_eq!(invalid.status().as_u16(), 400);
+    // an unconfirmed enrollment doesn't change the login
+    assert_eq!(login.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn logins_of_enrolled_users_need_a_code() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Editor).await;
+    let (secret, recovery_codes) = enroll(&app, &session).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    // act
+    let first = challenge(&app, &email).await;
+    let wrong_code = post_second_factor(&app, &first, "000000").await;
+    let next_code = code(&secret, 1);
+    let accepted = post_second_factor(&app, &first, &next_code).await;
+    let used_challenge = post_second_factor(&app, &first, &next_code).await;
+    let second = challenge(&app, &email).await;
+    let replayed = post_second_factor(&app, &second, &next_code).await;
+
+    // assert
+    assert_eq!(wrong_code.status().as_u16(), 401);
+    assert_eq!(wrong_code.text().await.unwrap(), "Invalid code");
+    assert_eq!(accepted.status().as_u16(), 200);
+    let session = client_with_cookie(&session_cookie(&accepted).unwrap());
+    let blocklist = session
+        .get(format!("{}/api/v1/admin/blocklist", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(blocklist.status().as_u16(), 200);
+    assert_eq!(used_challenge.status().as_u16(), 401);
+    // codes are only accepted once
+    assert_eq!(replayed.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once_instead_of_a_code() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+    let (_, recovery_codes) = enroll(&app, &session).await;
+
+    // act
+    let first = challenge(&app, &email).await;
+    let used = post_second_factor(&app, &first, &recovery_codes[0].to_uppercase()).await;
+    let second = challenge(&app, &email).await;
+    let reused = post_second_factor(&app, &second, &recovery_codes[0]).await;
+    let other = post_second_factor(&app, &second, &recovery_codes[1]).await;
+
+    // assert
+    assert_eq!(used.status().as_u16(), 200);
+    assert_eq!(reused.status().as_u16(), 401);
+    assert_eq!(other.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn challenges_expire_after_too_many_wrong_codes() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+    let (secret, _) = enroll(&app, &session).await;
+    let challenge = challenge(&app, &email).await;
+
+    // act
+    for _ in 0..5 {
+        post_second_factor(&app, &challenge, "000000").await;
+    }
+    let response = post_second_factor(&app, &challenge, &code(&secret, 1)).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "This login has expired, log in again"
+    );
+}
+
+#[tokio::test]
+async fn roles_requiring_two_factor_have_no_access_until_enrolled() {
+    // arange
+    let app = spwan_app_with(|config| config.auth.totp_required_roles = vec![Role::Owner]).await;
+    let (_, owner) = logged_in_user(&app, Role::Owner).await;
+    let (_, viewer) = logged_in_user(&app, Role::Viewer).await;
+    let get = |client: &reqwest::Client, route: &str| {
+        client
+            .get(format!("{}/api/v1/admin/{}", app.address, route))
+            .send()
+    };
+    let before = get(&owner, "users").await.unwrap();
+    assert_eq!(get(&viewer, "subscribers").await.unwrap().status(), 200);
+
+    // act
+    let (secret, _) = enroll(&app, &owner).await;
+    let after = get(&owner, "users").await.unwrap();
+    let disable = owner
+        .delete(format!("{}/api/v1/admin/account/totp", app.address))
+        .json(&serde_json::json!({ "code": code(&secret, 1) }))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(before.status().as_u16(), 403);
+    assert_eq!(
+        before.text().await.unwrap(),
+        "Set up two-factor authentication first"
+    );
+    assert_eq!(after.status().as_u16(), 200);
+    assert_eq!(disable.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn users_can_disable_two_factor_with_a_code() {
+    // arange
+    let app = spwan_app().await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+    let (secret, _) = enroll(&app, &session).await;
+    let disable = |code: String| {
+        session
+            .delete(format!("{}/api/v1/admin/account/totp", app.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+    };
+
+    // act
+    let invalid = disable("000000".into()).await.unwrap();
+    let disabled = disable(code(&secret, 1)).await.unwrap();
+    let login = log_in(&app, &email).await;
+
+    // assert
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(disabled.status().as_u16(), 204);
+    assert_eq!(login.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn api_keys_cant_enroll() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let response = app
+        .admin_client()
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn wrong_codes_count_towards_the_lockout() {
+    // arange
+    let app = spwan_app_with(|config| config.auth.max_failed_logins_per_email = 3).await;
+    let (email, session) = logged_in_user(&app, Role::Viewer).await;
+    let (secret, _) = enroll(&app, &session).await;
+    let challenge = challenge(&app, &email).await;
+
+    // act
+    for _ in 0..3 {
+        post_second_factor(&app, &challenge, "000000").await;
+    }
+    let response = post_second_factor(&app, &challenge, &code(&secret, 1)).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+}
@@ -0,0 +1,468 @@
+use crate::helpers::{
+    browser_client, client_with_cookie, session_cookie, spwan_app, TestApp, TEST_PASSWORD,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::auth::Role;
+
+async fn post_admin_user(
+    client: &reqwest::Client,
+    app: &TestApp,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    client
+        .post(format!("{}/api/v1/admin/users", app.address))
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_admin_users(app: &TestApp) -> Vec<serde_json::Value> {
+    app.admin_client()
+        .get(format!("{}/api/v1/admin/users", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn patch_role(
+    client: &reqwest::Client,
+    app: &TestApp,
+    id: &str,
+    role: &str,
+) -> reqwest::Response {
+    client
+        .patch(format!("{}/api/v1/admin/users/{}", app.address, id))
+        .json(&serde_json::json!({ "role": role }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn user_id(app: &TestApp, email: &str) -> String {
+    let users = get_admin_users(app).await;
+    let user = users.iter().find(|user| user["email"] == email).unwrap();
+    user["id"].as_str().unwrap().to_string()
+}
+
+/// Invite link sent in the only email
+async fn invite_link(app: &TestApp) -> reqwest::Url {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_admin_email_link(email_request)
+}
+
+async fn post_accept_invite(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/invite", app.address))
+        .form(&[("token", token), ("password", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn login_with_valid_credentials_starts_a_session() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+
+    // act
+    let response = app.post_login(&email, TEST_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let set_cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("SameSite=Lax"));
+    assert!(session_cookie(&response).is_some());
+    let user: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(user["email"], email.as_str());
+    assert_eq!(user["role"], "viewer");
+}
+
+#[tokio::test]
+async fn login_with_wrong_password_or_unknown_email_is_rejected() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Owner).await;
+
+    // act
+    let wrong_password = app.post_login(&email, "not the password").await;
+    let unknown_email = app.post_login("nobody@example.com", TEST_PASSWORD).await;
+
+    // assert
+    for response in [wrong_password, unknown_email] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(session_cookie(&response).is_none());
+        assert_eq!(response.text().await.unwrap(), "Invalid email or password");
+    }
+}
+
+#[tokio::test]
+async fn sessions_grant_the_scopes_of_the_role() {
+    // arange
+    let app = spwan_app().await;
+    let viewer = app.session_client(Role::Viewer).await;
+    let editor = app.session_client(Role::Editor).await;
+    let owner = app.session_client(Role::Owner).await;
+    let get = |client: &reqwest::Client, route: &str| {
+        client
+            .get(format!("{}/api/v1/admin/{}", app.address, route))
+            .send()
+    };
+
+    // act & assert
+    assert_eq!(get(&viewer, "subscribers").await.unwrap().status(), 200);
+    assert_eq!(get(&viewer, "blocklist").await.unwrap().status(), 403);
+    assert_eq!(get(&editor, "blocklist").await.unwrap().status(), 200);
+    assert_eq!(get(&editor, "users").await.unwrap().status(), 403);
+    assert_eq!(get(&editor, "api_keys").await.unwrap().status(), 403);
+    assert_eq!(get(&owner, "users").await.unwrap().status(), 200);
+    assert_eq!(get(&owner, "audit_log").await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+async fn invited_users_set_their_password_and_are_logged_in() {
+    // arange
+    let app = spwan_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let owner = app.session_client(Role::Owner).await;
+
+    // act
+    let response = post_admin_user(
+        &owner,
+        &app,
+        &serde_json::json!({"email": "Editor@Example.com", "role": "editor"}),
+    )
+    .await;
+    let link = invite_link(&app).await;
+    let form = reqwest::get(link.clone()).await.unwrap();
+    let token = link
+        .query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .to_string();
+    let too_short = post_accept_invite(&app, &token, "short").await;
+    let accepted = post_accept_invite(&app, &token, TEST_PASSWORD).await;
+    let reused = post_accept_invite(&app, &token, TEST_PASSWORD).await;
+    let login = app.post_login("editor@example.com", TEST_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 201);
+    let invited: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(invited["email"], "editor@example.com");
+    assert_eq!(invited["invite_pending"], true);
+    assert_eq!(form.status().as_u16(), 200);
+    assert_eq!(too_short.status().as_u16(), 400);
+    assert_eq!(accepted.status().as_u16(), 200);
+    let session = session_cookie(&accepted).unwrap();
+    let user: serde_json::Value = accepted.json().await.unwrap();
+    assert_eq!(user["role"], "editor");
+    assert_eq!(user["invite_pending"], false);
+    let blocklist = client_with_cookie(&session)
+        .get(format!("{}/api/v1/admin/blocklist", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(blocklist.status().as_u16(), 200);
+    assert_eq!(reused.status().as_u16(), 410);
+    assert_eq!(login.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn users_cant_be_invited_twice() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    let owner = app.session_client(Role::Owner).await;
+
+    // act
+    let response = post_admin_user(
+        &owner,
+        &app,
+        &serde_json::json!({"email": email, "role": "editor"}),
+    )
+    .await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn only_owners_can_invite_users_and_change_roles() {
+    // arange, the API key of the test app has all scopes, including `users:manage`
+    let app = spwan_app().await;
+    let editor = app.session_client(Role::Editor).await;
+    let viewer_id = user_id(&app, &app.create_admin_user(Role::Viewer).await).await;
+    let new_owner = serde_json::json!({"email": "owner@example.com", "role": "owner"});
+
+    for (client, description) in [(app.admin_client(), "API key"), (editor, "editor")] {
+        // act
+        let invite = post_admin_user(&client, &app, &new_owner).await;
+        let promote = patch_role(&client, &app, &viewer_id, "owner").await;
+
+        // assert
+        assert_eq!(
+            invite.status().as_u16(),
+            403,
+            "{} could invite",
+            description
+        );
+        assert_eq!(
+            promote.status().as_u16(),
+            403,
+            "{} could promote",
+            description
+        );
+    }
+    let users = get_admin_users(&app).await;
+    assert_eq!(users.len(), 2);
+    assert!(users.iter().all(|user| user["role"] != "owner"));
+}
+
+#[tokio::test]
+async fn the_last_owner_cant_be_demoted() {
+    // arange
+    let app = spwan_app().await;
+    let first = app.create_admin_user(Role::Owner).await;
+    let first_id = user_id(&app, &first).await;
+    let response = app.post_login(&first, TEST_PASSWORD).await;
+    let owner = client_with_cookie(&session_cookie(&response).unwrap());
+
+    // act
+    let last_owner = patch_role(&owner, &app, &first_id, "viewer").await;
+    let second = app.create_admin_user(Role::Viewer).await;
+    let second_id = user_id(&app, &second).await;
+    let promoted = patch_role(&owner, &app, &second_id, "owner").await;
+    let demoted = patch_role(&owner, &app, &first_id, "viewer").await;
+
+    // assert
+    assert_eq!(last_owner.status().as_u16(), 409);
+    assert_eq!(promoted.status().as_u16(), 200);
+    assert_eq!(demoted.status().as_u16(), 200);
+    let user: serde_json::Value = demoted.json().await.unwrap();
+    assert_eq!(user["role"], "viewer");
+}
+
+#[tokio::test]
+async fn role_changes_apply_to_existing_sessions() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    let response = app.post_login(&email, TEST_PASSWORD).await;
+    let session = client_with_cookie(&session_cookie(&response).unwrap());
+    let url = format!("{}/api/v1/admin/blocklist", app.address);
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 403);
+
+    let owner = app.session_client(Role::Owner).await;
+
+    // act
+    patch_role(&owner, &app, &user_id(&app, &email).await, "editor").await;
+
+    // assert
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 200);
+}
+
+#[tokio::test]
+async fn logout_ends_the_session() {
+    // arange
+    let app = spwan_app().await;
+    let session = app.session_client(Role::Viewer).await;
+    let url = format!("{}/api/v1/admin/subscribers", app.address);
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 200);
+
+    // act
+    let response = session
+        .post(format!("{}/logout", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(session_cookie(&response).as_deref(), Some(""));
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn privileged_actions_are_recorded_in_the_audit_log() {
+    // arange
+    let app = spwan_app().await;
+    let owner_email = app.create_admin_user(Role::Owner).await;
+    let response = app.post_login(&owner_email, TEST_PASSWORD).await;
+    let owner = client_with_cookie(&session_cookie(&response).unwrap());
+    let viewer_email = app.create_admin_user(Role::Viewer).await;
+    let viewer_id = user_id(&app, &viewer_email).await;
+
+    // act
+    owner
+        .patch(format!("{}/api/v1/admin/users/{}", app.address, viewer_id))
+        .json(&serde_json::json!({"role": "editor"}))
+        .send()
+        .await
+        .unwrap();
+    app.admin_client()
+        .post(format!("{}/api/v1/admin/api_keys", app.address))
+        .json(&serde_json::json!({"name": "crm", "scopes": ["subscribers:read"]}))
+        .send()
+        .await
+        .unwrap();
+    let entries: Vec<serde_json::Value> = app
+        .admin_client()
+        .get(format!("{}/api/v1/admin/audit_log", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["action"], "api_key.created");
+    assert_eq!(entries[0]["actor_type"], "api_key");
+    assert_eq!(entries[1]["action"], "user.role_changed");
+    assert_eq!(entries[1]["actor_type"], "user");
+    assert_eq!(entries[1]["actor_id"], user_id(&app, &owner_email).await);
+    assert_eq!(entries[1]["target"], viewer_id);
+    assert_eq!(entries[1]["details"], "editor");
+}
+
+#[tokio::test]
+async fn changes_to_subscribers_and_the_blocklist_are_recorded_in_the_audit_log() {
+    // arange
+    let app = spwan_app().await;
+    let client = app.admin_client();
+
+    // act
+    app.post_subscribers_import(
+        "email,name\nursula@gmail.com,Ursula\n".into(),
+        "pre_confirmed",
+    )
+    .await;
+    app.get_subscribers_export("format=csv&status=confirmed")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    client
+        .delete(format!(
+            "{}/api/v1/admin/subscribers/{}",
+            app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    app.post_blocklist(&serde_json::json!({"value": "spam.com", "reason": "abuse"}))
+        .await;
+    client
+        .delete(format!("{}/api/v1/admin/blocklist/spam.com", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    let entries = sqlx::query!(
+        "SELECT actor_type, action, target, details FROM audit_log ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let subscriber_id = subscriber_id.to_string();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|e| (e.action.as_str(), e.target.as_deref(), e.details.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "subscribers.imported",
+                Some("subscribers"),
+                Some("1 as pre_confirmed")
+            ),
+            (
+                "subscribers.exported",
+                Some("subscribers"),
+                Some("csv of confirmed")
+            ),
+            (
+                "subscriber.deleted",
+                Some(subscriber_id.as_str()),
+                Some("ursula@gmail.com")
+            ),
+            ("blocklist.entry_added", Some("spam.com"), Some("abuse")),
+            ("blocklist.entry_removed", Some("spam.com"), None),
+        ]
+    );
+    assert!(entries.iter().all(|e| e.actor_type == "api_key"));
+}
+
+#[tokio::test]
+async fn the_audit_log_is_append_only() {
+    // arange
+    let app = spwan_app().await;
+    app.admin_client()
+        .post(format!("{}/api/v1/admin/api_keys", app.address))
+        .json(&serde_json::json!({"name": "crm", "scopes": ["subscribers:read"]}))
+        .send()
+        .await
+        .unwrap();
+
+    // act
+    let update = sqlx::query("UPDATE audit_log SET details = 'tampered'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    // assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
+
+#[tokio::test]
+async fn users_cant_grant_api_key_scopes_they_lack() {
+    // arange
+    let app = spwan_app().await;
+    let key = app
+        .admin_client()
+        .post(format!("{}/api/v1/admin/api_keys", app.address))
+        .json(&serde_json::json!({"name": "keys", "scopes": ["api_keys:manage"]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // act
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/admin/api_keys", app.address))
+        .bearer_auth(key)
+        .json(&serde_json::json!({"name": "crm", "scopes": ["subscribers:write"]}))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+}
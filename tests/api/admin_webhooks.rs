@@ -0,0 +1,271 @@
+use crate::helpers::{spwan_app, TestApp};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::config::WebhooksConfig;
+use zero2prod::webhook_client::{WebhookClient, EVENT_HEADER, SIGNATURE_HEADER};
+use zero2prod::webhooks::deliver_due_webhooks;
+
+fn webhooks_config(max_attempts: u32) -> WebhooksConfig {
+    WebhooksConfig {
+        timeout: Duration::from_secs(1),
+        max_attempts,
+        retry_base_delay: Duration::ZERO,
+        poll_interval: Duration::from_millis(100),
+        batch_size: 20,
+    }
+}
+
+/// Send all due deliveries, as the background worker would
+async fn deliver(app: &TestApp, max_attempts: u32) -> usize {
+    let config = webhooks_config(max_attempts);
+    deliver_due_webhooks(&app.db_pool, &WebhookClient::new(config.timeout), &config)
+        .await
+        .unwrap()
+}
+
+async fn register_endpoint(
+    app: &TestApp,
+    receiver: &MockServer,
+    event_types: &[&str],
+) -> serde_json::Value {
+    let response = app
+        .admin_client()
+        .post(format!("{}/api/v1/admin/webhooks", app.address))
+        .json(&serde_json::json!({
+            "url": format!("{}/hooks", receiver.uri()),
+            "event_types": event_types,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    response.json().await.unwrap()
+}
+
+async fn get_deliveries(app: &TestApp, endpoint: &serde_json::Value) -> serde_json::Value {
+    app.admin_client()
+        .get(format!(
+            "{}/api/v1/admin/webhooks/{}/deliveries",
+            app.address,
+            endpoint["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn subscribe(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_sends_a_signed_event_to_registered_endpoints() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    let endpoint = register_endpoint(&app, &receiver, &["subscribed"]).await;
+    subscribe(&app).await;
+
+    // act
+    let n = deliver(&app, 3).await;
+
+    // assert
+    assert_eq!(n, 1);
+    let request = &receiver.received_requests().await.unwrap()[0];
+    assert_eq!(request.headers[EVENT_HEADER], "subscriber.subscribed");
+    let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(payload["type"], "subscriber.subscribed");
+    assert_eq!(payload["data"]["email"], "ursula_le_guin@gmail.com");
+
+    // receivers check the signature with the secret they got when registering
+    let signature = request.headers[SIGNATURE_HEADER].to_str().unwrap();
+    let (timestamp, signature) = signature.split_once(",v1=").unwrap();
+    let timestamp = timestamp.strip_prefix("t=").unwrap();
+    let secret = endpoint["secret"].as_str().unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(&request.body);
+    assert!(mac.verify_slice(&hex::decode(signature).unwrap()).is_ok());
+
+    let deliveries = get_deliveries(&app, &endpoint).await;
+    assert_eq!(deliveries[0]["status"], "succeeded");
+    assert_eq!(deliveries[0]["last_response_status"], 200);
+}
+
+#[tokio::test]
+async fn endpoints_only_get_the_event_types_they_registered_for() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&receiver)
+        .await;
+    register_endpoint(&app, &receiver, &["confirmed", "unsubscribed"]).await;
+
+    // act
+    subscribe(&app).await;
+
+    // assert
+    assert_eq!(deliver(&app, 3).await, 0);
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_sends_a_confirmation_resent_event() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    register_endpoint(&app, &receiver, &["confirmation_resent"]).await;
+    subscribe(&app).await;
+
+    // act
+    subscribe(&app).await;
+
+    // assert
+    assert_eq!(deliver(&app, 3).await, 1);
+    let request = &receiver.received_requests().await.unwrap()[0];
+    assert_eq!(
+        request.headers[EVENT_HEADER],
+        "subscriber.confirmation_resent"
+    );
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_sends_an_unsubscribed_event() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&receiver)
+        .await;
+    register_endpoint(&app, &receiver, &["unsubscribed"]).await;
+    subscribe(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // act
+    app.admin_client()
+        .delete(format!(
+            "{}/api/v1/admin/subscribers/{}",
+            app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    deliver(&app, 3).await;
+
+    // assert
+    let request = &receiver.received_requests().await.unwrap()[0];
+    let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(payload["type"], "subscriber.unsubscribed");
+    assert_eq!(payload["data"]["subscriber_id"], subscriber_id.to_string());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_and_can_be_replayed() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .mount(&receiver)
+        .await;
+    let endpoint = register_endpoint(&app, &receiver, &["subscribed"]).await;
+    subscribe(&app).await;
+
+    // act & assert, retry until the attempts are used up
+    assert_eq!(deliver(&app, 2).await, 1);
+    let deliveries = get_deliveries(&app, &endpoint).await;
+    assert_eq!(deliveries[0]["status"], "pending");
+    assert_eq!(deliveries[0]["attempts"], 1);
+    assert_eq!(deliver(&app, 2).await, 1);
+    let deliveries = get_deliveries(&app, &endpoint).await;
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert_eq!(deliveries[0]["last_response_status"], 500);
+    assert_eq!(deliver(&app, 2).await, 0);
+
+    // act & assert, replay once the receiver is back
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&receiver)
+        .await;
+    let replay_url = format!(
+        "{}/api/v1/admin/webhooks/deliveries/{}/replay",
+        app.address,
+        deliveries[0]["id"].as_str().unwrap()
+    );
+    let response = app.admin_client().post(&replay_url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(deliver(&app, 2).await, 1);
+    let deliveries = get_deliveries(&app, &endpoint).await;
+    assert_eq!(deliveries[0]["status"], "succeeded");
+    let response = app.admin_client().post(&replay_url).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn disabled_endpoints_get_no_more_events() {
+    // arange
+    let app = spwan_app().await;
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&receiver)
+        .await;
+    let endpoint = register_endpoint(&app, &receiver, &["subscribed"]).await;
+    subscribe(&app).await;
+
+    // act
+    let response = app
+        .admin_client()
+        .delete(format!(
+            "{}/api/v1/admin/webhooks/{}",
+            app.address,
+            endpoint["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(deliver(&app, 3).await, 0);
+    let deliveries = get_deliveries(&app, &endpoint).await;
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert_eq!(deliveries[0]["last_error"], "endpoint disabled");
+}
@@ -0,0 +1,158 @@
+use crate::helpers::{spwan_app, spwan_app_with, TestApp};
+use secrecy::SecretBox;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::config::CaptchaConfig;
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn get_form_token(app: &TestApp) -> String {
+    let body: serde_json::Value = reqwest::get(format!("{}/subscriptions/form_token", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["form_token"].as_str().unwrap().to_string()
+}
+
+async fn count_subscriptions(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+        .unwrap()
+}
+
+#[tokio::test]
+async fn subscribe_rejects_filled_in_honeypot() {
+    // arange
+    let app = spwan_app().await;
+    mount_email_server(&app).await;
+
+    // act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.com";
+    let response = app.post_subscription(body.into()).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_empty_honeypot() {
+    // arange
+    let app = spwan_app().await;
+    mount_email_server(&app).await;
+
+    // act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=";
+    let response = app.post_subscription(body.into()).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_requires_form_token_if_configured() {
+    // arange
+    let app = spwan_app_with(|config| {
+        config.bot_protection.require_form_token = true;
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    // act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscription(body.into()).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_form_submitted_too_quickly() {
+    // arange
+    let app = spwan_app_with(|config| {
+        config.bot_protection.min_submit_time = Duration::from_secs(60);
+    })
+    .await;
+    mount_email_server(&app).await;
+    let form_token = get_form_token(&app).await;
+
+    // act
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        form_token
+    );
+    let response = app.post_subscription(body).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(count_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_valid_form_token() {
+    // arange
+    let app = spwan_app_with(|config| {
+        config.bot_protection.require_form_token = true;
+        config.bot_protection.min_submit_time = Duration::ZERO;
+    })
+    .await;
+    mount_email_server(&app).await;
+    let form_token = get_form_token(&app).await;
+
+    // act
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+        form_token
+    );
+    let response = app.post_subscription(body).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_consults_captcha_service_before_touching_the_database() {
+    for (success, expected_status, expected_count) in [(true, 200, 1), (false, 400, 0)] {
+        // arange
+        let captcha_server = MockServer::start().await;
+        Mock::given(path("/siteverify"))
+            .and(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": success })),
+            )
+            .expect(1)
+            .mount(&captcha_server)
+            .await;
+        let app = spwan_app_with(|config| {
+            config.bot_protection.captcha = Some(CaptchaConfig {
+                verify_url: format!("{}/siteverify", captcha_server.uri())
+                    .parse()
+                    .unwrap(),
+                secret: SecretBox::new(Box::new("secret".into())),
+                timeout: Duration::from_secs(1),
+            });
+        })
+        .await;
+        mount_email_server(&app).await;
+
+        // act, post the token under the field name used by the hCaptcha widget
+        let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=token";
+        let response = app.post_subscription(body.into()).await;
+
+        // assert
+        assert_eq!(response.status().as_u16(), expected_status);
+        assert_eq!(count_subscriptions(&app).await, expected_count);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::helpers::{spwan_app, spwan_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const ALLOWED_ORIGIN: &str = "https://marketing.example.com";
+
+async fn spwan_app_with_cors() -> TestApp {
+    spwan_app_with(|config| config.app.cors.allowed_origins = vec![ALLOWED_ORIGIN.into()]).await
+}
+
+async fn preflight(app: &TestApp, route: &str, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}{}", app.address, route),
+        )
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post_subscription_from(app: &TestApp, origin: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Origin", origin)
+        .json(&serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"}))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn allowed_origin(response: &reqwest::Response) -> Option<&str> {
+    response
+        .headers()
+        .get("Access-Control-Allow-Origin")
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn allowed_origins_can_subscribe_from_scripts() {
+    // arange
+    let app = spwan_app_with_cors().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act
+    let preflight = preflight(&app, "/api/v1/subscriptions", ALLOWED_ORIGIN).await;
+    let subscription = post_subscription_from(&app, ALLOWED_ORIGIN).await;
+    let form_token = reqwest::Client::new()
+        .get(format!("{}/subscriptions/form_token", app.address))
+        .header("Origin", ALLOWED_ORIGIN)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert!(preflight.status().is_success());
+    assert_eq!(allowed_origin(&preflight), Some(ALLOWED_ORIGIN));
+    let methods = preflight.headers()["Access-Control-Allow-Methods"]
+        .to_str()
+        .unwrap();
+    assert!(methods.contains("POST"));
+    assert!(subscription.status().is_success());
+    assert_eq!(allowed_origin(&subscription), Some(ALLOWED_ORIGIN));
+    assert_eq!(allowed_origin(&form_token), Some(ALLOWED_ORIGIN));
+}
+
+#[tokio::test]
+async fn other_origins_get_no_cors_headers() {
+    // arange
+    let app = spwan_app_with_cors().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act
+    let preflight = preflight(&app, "/subscriptions", "https://evil.example.com").await;
+    let subscription = post_subscription_from(&app, "https://evil.example.com").await;
+
+    // assert
+    assert!(allowed_origin(&preflight).is_none());
+    // still handled, plain HTML forms on other sites don't need CORS
+    assert!(subscription.status().is_success());
+    assert!(allowed_origin(&subscription).is_none());
+}
+
+#[tokio::test]
+async fn admin_routes_stay_same_origin_only() {
+    // arange
+    let app = spwan_app_with_cors().await;
+
+    // act
+    let preflight = preflight(&app, "/api/v1/admin/subscribers", ALLOWED_ORIGIN).await;
+    let request = app
+        .admin_client()
+        .get(format!("{}/api/v1/admin/subscribers", app.address))
+        .header("Origin", ALLOWED_ORIGIN)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert!(allowed_origin(&preflight).is_none());
+    assert_eq!(request.status().as_u16(), 200);
+    assert!(allowed_origin(&request).is_none());
+}
+
+#[tokio::test]
+async fn no_origin_is_allowed_by_default() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let preflight = preflight(&app, "/subscriptions", ALLOWED_ORIGIN).await;
+
+    // assert
+    assert!(allowed_origin(&preflight).is_none());
+}
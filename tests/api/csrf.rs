@@ -0,0 +1,140 @@
+use crate::helpers::{
+    browser_client, session_cookie, spwan_app, TestApp, TEST_CSRF_TOKEN, TEST_PASSWORD,
+};
+use zero2prod::auth::{Role, SESSION_COOKIE};
+use zero2prod::csrf::CSRF_COOKIE;
+
+fn csrf_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next()?.split_once('='))
+        .find(|(name, _)| *name == CSRF_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Value of the hidden CSRF field of a form
+fn csrf_field(html: &str) -> String {
+    let (_, rest) = html
+        .split_once(r#"name="csrf_token" value=""#)
+        .expect("form has no CSRF field");
+    rest.split('"').next().unwrap().to_string()
+}
+
+async fn post_login_with(
+    app: &TestApp,
+    email: &str,
+    cookie: Option<&str>,
+    field: Option<&str>,
+) -> reqwest::Response {
+    let mut form = vec![("email", email), ("password", TEST_PASSWORD)];
+    if let Some(field) = field {
+        form.push(("csrf_token", field));
+    }
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/login", app.address))
+        .form(&form);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", format!("{}={}", CSRF_COOKIE, cookie));
+    }
+    request.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn forms_carry_the_token_of_their_cookie() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+
+    // act
+    let form = reqwest::get(format!("{}/login", app.address))
+        .await
+        .unwrap();
+    let cookie = csrf_cookie(&form).unwrap();
+    let field = csrf_field(&form.text().await.unwrap());
+    let login = post_login_with(&app, &email, Some(&cookie), Some(&field)).await;
+
+    // assert
+    assert_eq!(cookie, field);
+    assert_eq!(login.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn browsers_keep_their_token() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let form = browser_client()
+        .get(format!("{}/login/forgot", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert!(csrf_cookie(&form).is_none());
+    assert_eq!(csrf_field(&form.text().await.unwrap()), TEST_CSRF_TOKEN);
+}
+
+#[tokio::test]
+async fn form_posts_without_a_matching_token_are_rejected() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    let other_token = "OtherCsrfToken0123456789abcdefgh";
+
+    // act
+    let no_cookie = post_login_with(&app, &email, None, Some(TEST_CSRF_TOKEN)).await;
+    let no_field = post_login_with(&app, &email, Some(TEST_CSRF_TOKEN), None).await;
+    let mismatch = post_login_with(&app, &email, Some(TEST_CSRF_TOKEN), Some(other_token)).await;
+
+    // assert
+    for response in [no_cookie, no_field, mismatch] {
+        assert_eq!(response.status().as_u16(), 403);
+        assert!(session_cookie(&response).is_none());
+    }
+}
+
+#[tokio::test]
+async fn session_requests_changing_state_need_the_csrf_header() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    let login = app.post_login(&email, TEST_PASSWORD).await;
+    let cookie = format!(
+        "{}={}; {}={}",
+        SESSION_COOKIE,
+        session_cookie(&login).unwrap(),
+        CSRF_COOKIE,
+        TEST_CSRF_TOKEN
+    );
+    let client = reqwest::Client::new();
+
+    // act
+    let read = client
+        .get(format!("{}/api/v1/admin/subscribers", app.address))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    let without_header = client
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .header("Cookie", &cookie)
+        .send()
+        .await
+        .unwrap();
+    let with_header = client
+        .post(format!("{}/api/v1/admin/account/totp", app.address))
+        .header("Cookie", &cookie)
+        .header("X-CSRF-Token", TEST_CSRF_TOKEN)
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(read.status().as_u16(), 200);
+    assert_eq!(without_header.status().as_u16(), 403);
+    assert_eq!(with_header.status().as_u16(), 200);
+}
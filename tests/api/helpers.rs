@@ -1,18 +1,87 @@
+use chrono::TimeDelta;
 use once_cell::sync::Lazy;
+use secrecy::SecretString;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use wiremock::MockServer;
-use zero2prod::config::{read_config, DatabaseConfig};
+use zero2prod::admin_users::{invite_admin_user, set_password};
+use zero2prod::api_keys::create_api_key;
+use zero2prod::auth::{hash_password, Role, Scope, SESSION_COOKIE};
+use zero2prod::config::{read_config, Config, DatabaseConfig};
+use zero2prod::csrf::{CSRF_COOKIE, CSRF_HEADER};
 use zero2prod::startup::{create_db_connection_pool, Application};
 use zero2prod::telemetry::configure_tracing;
 
+pub const ALL_SCOPES: [Scope; 7] = Scope::ALL;
+
+pub const TEST_PASSWORD: &str = "correct horse battery staple";
+
+/// Token test clients send in the CSRF cookie and header, as if they had loaded one of our forms
+pub const TEST_CSRF_TOKEN: &str = "TestCsrfToken0123456789abcdefghi";
+
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    /// key with all scopes, sent by [`TestApp::admin_client`]
+    pub api_key: String,
+}
+
+/// Confirmation links embedded in the request to the email API
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub text: reqwest::Url,
 }
 
 impl TestApp {
+    /// Client authenticating with the API key of the test app, for the admin routes
+    pub fn admin_client(&self) -> reqwest::Client {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let mut authorization: reqwest::header::HeaderValue =
+            format!("Bearer {}", self.api_key).parse().unwrap();
+        authorization.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, authorization);
+        reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap()
+    }
+
+    /// Create an admin user with [`TEST_PASSWORD`] as password, skipping the invite
+    pub async fn create_admin_user(&self, role: Role) -> String {
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let mut transaction = self.db_pool.begin().await.unwrap();
+        let (user, _) = invite_admin_user(&mut transaction, &email, role, TimeDelta::hours(1))
+            .await
+            .unwrap();
+        let password_hash = hash_password(SecretString::from(TEST_PASSWORD))
+            .await
+            .unwrap();
+        set_password(&mut *transaction, user.id, &password_hash)
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        email
+    }
+
+    pub async fn post_login(&self, email: &str, password: &str) -> reqwest::Response {
+        browser_client()
+            .post(format!("{}/login", self.address))
+            .form(&[("email", email), ("password", password)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Client sending the session cookie of a freshly logged in admin user with the role
+    pub async fn session_client(&self, role: Role) -> reqwest::Client {
+        let email = self.create_admin_user(role).await;
+        let response = self.post_login(&email, TEST_PASSWORD).await;
+        assert_eq!(response.status().as_u16(), 200);
+        client_with_cookie(&session_cookie(&response).unwrap())
+    }
+
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         let client = reqwest::Client::new();
         client
@@ -23,6 +92,87 @@ impl TestApp {
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn post_subscription_json(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(&self, csv: String, mode: &str) -> reqwest::Response {
+        self.admin_client()
+            .post(format!(
+                "{}/api/v1/admin/subscribers/import?mode={}",
+                self.address, mode
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.admin_client()
+            .get(format!(
+                "{}/api/v1/admin/subscribers/export?{}",
+                self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers(&self, query: &str) -> reqwest::Response {
+        self.admin_client()
+            .get(format!(
+                "{}/api/v1/admin/subscribers?{}",
+                self.address, query
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_blocklist(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.admin_client()
+            .post(format!("{}/api/v1/admin/blocklist", self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Extract the confirmation links from a request to the email API, pointing them to the
+    /// randomly assigned port of the test app
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let get_link = |s: &str| {
+            let links = find_links(s);
+            assert_eq!(links.len(), 1);
+            let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+            link.set_port(Some(self.port)).unwrap();
+            link
+        };
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, text }
+    }
+
+    /// The only link in the text body of an email to an admin user, pointing to the randomly
+    /// assigned port of the test app
+    pub fn get_admin_email_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let links = find_links(body["TextBody"].as_str().unwrap());
+        assert_eq!(links.len(), 1);
+        let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
 }
 
 /// Configure database for testing
@@ -64,6 +214,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 
 /// spawn app for testing
 pub async fn spwan_app() -> TestApp {
+    spwan_app_with(|_| {}).await
+}
+
+/// spawn app for testing, customizing the config before launching the app
+pub async fn spwan_app_with(customize_config: impl FnOnce(&mut Config)) -> TestApp {
     // configure tracing only once; all other calls are skipped
     Lazy::force(&TRACING);
 
@@ -71,11 +226,12 @@ pub async fn spwan_app() -> TestApp {
     let email_server = MockServer::start().await;
 
     // read config
-    let mut config = read_config().expect("failed to read config");
+    let mut config = read_config().await.expect("failed to read config");
     tracing::info!("Randomizing config for testing ...");
     config.db.name = Uuid::new_v4().to_string(); // randomize database name for testing
     config.app.port = 0; // use random, system assigned port
-    config.email_client.base_url = email_server.uri();
+    config.email_client.base_url = email_server.uri().parse().unwrap();
+    customize_config(&mut config);
 
     // configure database
     configure_db(&config.db).await;
@@ -85,6 +241,7 @@ pub async fn spwan_app() -> TestApp {
         .await
         .expect("failed to build server");
     let address = app.get_address();
+    let port = app.get_port();
 
     // tokio::spawn spaws a new task (our server) when a new tokio runtime is launched and shuts
     // down all tasks when the runtime is stopped; tokio::test launches the new runtime
@@ -93,14 +250,58 @@ pub async fn spwan_app() -> TestApp {
     let _ = tokio::spawn(app.run_until_stopped());
 
     let db_pool = create_db_connection_pool(&config.db);
+    let (_, api_key) = create_api_key(&db_pool, "test", &ALL_SCOPES)
+        .await
+        .expect("failed to create API key");
     TestApp {
         db_pool,
         address,
+        port,
         email_server,
+        api_key,
     }
 }
 
-pub fn find_links(text: &str) -> Vec<linkify::Link> {
+/// Value of the session cookie set by a response, if any
+pub fn session_cookie(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next()?.split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Client sending the CSRF cookie and header with every request, like a browser with a script
+/// that read the cookie
+pub fn browser_client() -> reqwest::Client {
+    client_with_cookies(&format!("{}={}", CSRF_COOKIE, TEST_CSRF_TOKEN))
+}
+
+/// Client sending the session cookie with every request, as a browser would, along with the CSRF
+/// cookie and header
+pub fn client_with_cookie(session: &str) -> reqwest::Client {
+    client_with_cookies(&format!(
+        "{}={}; {}={}",
+        SESSION_COOKIE, session, CSRF_COOKIE, TEST_CSRF_TOKEN
+    ))
+}
+
+fn client_with_cookies(cookies: &str) -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let mut cookie: reqwest::header::HeaderValue = cookies.parse().unwrap();
+    cookie.set_sensitive(true);
+    headers.insert(reqwest::header::COOKIE, cookie);
+    headers.insert(CSRF_HEADER, TEST_CSRF_TOKEN.parse().unwrap());
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+pub fn find_links(text: &str) -> Vec<linkify::Link<'_>> {
     linkify::LinkFinder::new()
         .links(text)
         .filter(|l| *l.kind() == linkify::LinkKind::Url)
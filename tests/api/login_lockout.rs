@@ -0,0 +1,182 @@
+use crate::helpers::{spwan_app, spwan_app_with, TestApp, TEST_PASSWORD};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::auth::Role;
+
+const WRONG_PASSWORD: &str = "not the right passphrase";
+
+async fn fail_logins(app: &TestApp, email: &str, times: usize) {
+    for _ in 0..times {
+        let response = app.post_login(email, WRONG_PASSWORD).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+}
+
+async fn mock_email(app: &TestApp, expected: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn too_many_wrong_passwords_lock_out_the_email_and_notify_the_user() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Editor).await;
+    let other = app.create_admin_user(Role::Editor).await;
+
+    // act
+    fail_logins(&app, &email, 5).await;
+    let locked = app.post_login(&email, TEST_PASSWORD).await;
+    let other_login = app.post_login(&other, TEST_PASSWORD).await;
+
+    // assert
+    assert_eq!(locked.status().as_u16(), 429);
+    let retry_after: i64 = locked.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=300).contains(&retry_after));
+    assert_eq!(
+        locked.text().await.unwrap(),
+        "Too many failed logins, try again later"
+    );
+    assert_eq!(other_login.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    assert_eq!(
+        app.get_admin_email_link(email_request).path(),
+        "/login/forgot"
+    );
+}
+
+#[tokio::test]
+async fn unknown_emails_are_locked_out_the_same_way() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 0).await;
+
+    // act
+    fail_logins(&app, "nobody@example.com", 5).await;
+    let response = app.post_login("nobody@example.com", WRONG_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn too_many_wrong_passwords_lock_out_the_ip() {
+    // arange
+    let app = spwan_app_with(|config| config.auth.max_failed_logins_per_ip = 3).await;
+    let email = app.create_admin_user(Role::Viewer).await;
+
+    // act
+    for n in 0..3 {
+        fail_logins(&app, &format!("guess{}@example.com", n), 1).await;
+    }
+    let response = app.post_login(&email, TEST_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn successful_logins_reset_the_failures_of_the_email() {
+    // arange
+    let app = spwan_app().await;
+    let email = app.create_admin_user(Role::Viewer).await;
+
+    // act
+    fail_logins(&app, &email, 4).await;
+    let first = app.post_login(&email, TEST_PASSWORD).await;
+    fail_logins(&app, &email, 4).await;
+    let second = app.post_login(&email, TEST_PASSWORD).await;
+
+    // assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn owners_can_list_and_clear_lockouts() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 1).await;
+    let owner = app.session_client(Role::Owner).await;
+    let email = app.create_admin_user(Role::Editor).await;
+    fail_logins(&app, &email, 5).await;
+    let url = format!("{}/api/v1/admin/lockouts", app.address);
+
+    // act
+    let lockouts: serde_json::Value = owner.get(&url).send().await.unwrap().json().await.unwrap();
+    let all: serde_json::Value = owner
+        .get(format!("{}?all=true", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = lockouts[0]["id"].as_str().unwrap();
+    let cleared = owner
+        .delete(format!("{}/{}", url, id))
+        .send()
+        .await
+        .unwrap();
+    let cleared_again = owner
+        .delete(format!("{}/{}", url, id))
+        .send()
+        .await
+        .unwrap();
+    let login = app.post_login(&email, TEST_PASSWORD).await;
+
+    // assert
+    let lockouts = lockouts.as_array().unwrap();
+    assert_eq!(lockouts.len(), 1);
+    assert_eq!(lockouts[0]["kind"], "email");
+    assert_eq!(lockouts[0]["key"], email.as_str());
+    assert_eq!(lockouts[0]["lockouts"], 1);
+    // the IP has failures too, but isn't locked out
+    let ip = all
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|failures| failures["kind"] == "ip")
+        .unwrap();
+    assert_eq!(ip["failed_attempts"], 5);
+    assert!(ip["locked_until"].is_null());
+    assert_eq!(cleared.status().as_u16(), 204);
+    assert_eq!(cleared_again.status().as_u16(), 404);
+    assert_eq!(login.status().as_u16(), 200);
+    let audit_log: serde_json::Value = owner
+        .get(format!("{}/api/v1/admin/audit_log", app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit_log[0]["action"], "lockout.cleared");
+    assert_eq!(audit_log[0]["details"], format!("email {}", email));
+}
+
+#[tokio::test]
+async fn editors_cant_clear_lockouts() {
+    // arange
+    let app = spwan_app().await;
+    let editor = app.session_client(Role::Editor).await;
+
+    // act
+    let response = editor
+        .get(format!("{}/api/v1/admin/lockouts", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,135 @@
+use crate::helpers::{
+    browser_client, client_with_cookie, session_cookie, spwan_app, spwan_app_with, TestApp,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::auth::Role;
+
+const LINK_SENT: &str =
+    "If the email belongs to an admin user who can log in by email, we've sent them a login link";
+
+async fn post_magic_link(app: &TestApp, email: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/magic", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post_confirm(app: &TestApp, token: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/magic/confirm", app.address))
+        .form(&[("token", token)])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Token of the login link in the only email
+async fn magic_link_token(app: &TestApp) -> String {
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_admin_email_link(email_request);
+    assert_eq!(link.path(), "/login/magic/confirm");
+    link.query_pairs()
+        .find(|(name, _)| name == "token")
+        .unwrap()
+        .1
+        .to_string()
+}
+
+async fn mock_email(app: &TestApp, expected: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn magic_links_log_editors_in_once() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Editor).await;
+
+    // act
+    let response = post_magic_link(&app, &email.to_uppercase()).await;
+    let token = magic_link_token(&app).await;
+    let form = reqwest::get(format!(
+        "{}/login/magic/confirm?token={}",
+        app.address, token
+    ))
+    .await
+    .unwrap();
+    let confirmed = post_confirm(&app, &token).await;
+    let reused = post_confirm(&app, &token).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().await.unwrap(), LINK_SENT);
+    // opening the link doesn't log in yet, so that mail scanners can't use it up
+    assert_eq!(form.status().as_u16(), 200);
+    assert!(session_cookie(&form).is_none());
+    assert_eq!(confirmed.status().as_u16(), 200);
+    let session = client_with_cookie(&session_cookie(&confirmed).unwrap());
+    let blocklist = session
+        .get(format!("{}/api/v1/admin/blocklist", app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(blocklist.status().as_u16(), 200);
+    assert_eq!(reused.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn no_link_is_sent_to_unknown_emails_or_roles_that_need_a_password() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 0).await;
+    let owner = app.create_admin_user(Role::Owner).await;
+
+    // act
+    let unknown = post_magic_link(&app, "nobody@example.com").await;
+    let not_allowed = post_magic_link(&app, &owner).await;
+
+    // assert
+    for response in [unknown, not_allowed] {
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), LINK_SENT);
+    }
+}
+
+#[tokio::test]
+async fn expired_magic_links_are_rejected() {
+    // arange
+    let app = spwan_app_with(|config| config.auth.magic_link_expiry_minutes = 0).await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    post_magic_link(&app, &email).await;
+    let token = magic_link_token(&app).await;
+
+    // act
+    let response = post_confirm(&app, &token).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(session_cookie(&response).is_none());
+}
+
+#[tokio::test]
+async fn magic_links_are_rate_limited_per_email() {
+    // arange
+    let app = spwan_app_with(|config| config.app.rate_limit.max_requests_per_email = 1).await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Viewer).await;
+
+    // act
+    let first = post_magic_link(&app, &email).await;
+    let second = post_magic_link(&app, &email).await;
+
+    // assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+}
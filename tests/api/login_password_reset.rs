@@ -0,0 +1,158 @@
+use crate::helpers::{
+    browser_client, client_with_cookie, session_cookie, spwan_app, spwan_app_with, TestApp,
+    TEST_PASSWORD,
+};
+use chrono::TimeDelta;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::admin_users::invite_admin_user;
+use zero2prod::auth::Role;
+
+const LINK_SENT: &str =
+    "If the email belongs to an admin user, we've sent them a link to reset their password";
+const NEW_PASSWORD: &str = "a brand new passphrase";
+
+async fn post_forgot(app: &TestApp, email: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/forgot", app.address))
+        .form(&[("email", email)])
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post_reset(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    browser_client()
+        .post(format!("{}/login/reset", app.address))
+        .form(&[("token", token), ("password", password)])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Tokens of the reset links in all emails sent so far, oldest first
+async fn reset_tokens(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|email_request| {
+            let link = app.get_admin_email_link(email_request);
+            assert_eq!(link.path(), "/login/reset");
+            link.query_pairs()
+                .find(|(name, _)| name == "token")
+                .unwrap()
+                .1
+                .to_string()
+        })
+        .collect()
+}
+
+async fn mock_email(app: &TestApp, expected: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn reset_links_set_a_new_password_and_end_all_sessions() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Editor).await;
+    let login = app.post_login(&email, TEST_PASSWORD).await;
+    let session = client_with_cookie(&session_cookie(&login).unwrap());
+    let url = format!("{}/api/v1/admin/subscribers", app.address);
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 200);
+
+    // act
+    let response = post_forgot(&app, &email).await;
+    let token = reset_tokens(&app).await.remove(0);
+    let form = reqwest::get(format!("{}/login/reset?token={}", app.address, token))
+        .await
+        .unwrap();
+    let too_short = post_reset(&app, &token, "short").await;
+    let reset = post_reset(&app, &token, NEW_PASSWORD).await;
+    let reused = post_reset(&app, &token, NEW_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().await.unwrap(), LINK_SENT);
+    assert_eq!(form.status().as_u16(), 200);
+    assert_eq!(too_short.status().as_u16(), 400);
+    assert_eq!(reset.status().as_u16(), 200);
+    assert_eq!(reused.status().as_u16(), 410);
+    assert_eq!(session.get(&url).send().await.unwrap().status(), 401);
+    let old_password = app.post_login(&email, TEST_PASSWORD).await;
+    assert_eq!(old_password.status().as_u16(), 401);
+    let new_password = app.post_login(&email, NEW_PASSWORD).await;
+    assert_eq!(new_password.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forgot_password_doesnt_reveal_whether_an_account_exists() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 0).await;
+    let mut transaction = app.db_pool.begin().await.unwrap();
+    invite_admin_user(
+        &mut transaction,
+        "invited@example.com",
+        Role::Viewer,
+        TimeDelta::hours(1),
+    )
+    .await
+    .unwrap();
+    transaction.commit().await.unwrap();
+
+    // act
+    let unknown = post_forgot(&app, "nobody@example.com").await;
+    let invite_pending = post_forgot(&app, "invited@example.com").await;
+
+    // assert
+    for response in [unknown, invite_pending] {
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.text().await.unwrap(), LINK_SENT);
+    }
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    // arange
+    let app = spwan_app_with(|config| config.auth.password_reset_expiry_minutes = 0).await;
+    mock_email(&app, 1).await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    post_forgot(&app, &email).await;
+    let token = reset_tokens(&app).await.remove(0);
+
+    // act
+    let response = post_reset(&app, &token, NEW_PASSWORD).await;
+
+    // assert
+    assert_eq!(response.status().as_u16(), 410);
+    let login = app.post_login(&email, TEST_PASSWORD).await;
+    assert_eq!(login.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resetting_the_password_invalidates_other_links() {
+    // arange
+    let app = spwan_app().await;
+    mock_email(&app, 2).await;
+    let email = app.create_admin_user(Role::Viewer).await;
+    post_forgot(&app, &email).await;
+    post_forgot(&app, &email).await;
+    let tokens = reset_tokens(&app).await;
+
+    // act
+    let second = post_reset(&app, &tokens[1], NEW_PASSWORD).await;
+    let first = post_reset(&app, &tokens[0], "yet another passphrase").await;
+
+    // assert
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(first.status().as_u16(), 410);
+}
@@ -1,3 +1,21 @@
+mod admin_api_keys;
+mod admin_blocklist;
+mod admin_subscribers;
+mod admin_subscribers_export;
+mod admin_subscribers_import;
+mod admin_totp;
+mod admin_users;
+mod admin_webhooks;
+mod bot_protection;
+mod cors;
+mod csrf;
 mod health_check;
 mod helpers;
+mod login_lockout;
+mod login_magic_link;
+mod login_password_reset;
+mod openapi;
+mod rate_limit;
+mod security_headers;
 mod subscriptions;
+mod subscriptions_confirm;
@@ -0,0 +1,96 @@
+use crate::helpers::spwan_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SNAPSHOT_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/api/snapshots/openapi_v1.json"
+);
+
+#[tokio::test]
+async fn openapi_document_matches_snapshot() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let response = reqwest::get(format!("{}/api/v1/openapi.json", app.address))
+        .await
+        .unwrap();
+
+    // assert, run with UPDATE_SNAPSHOTS=1 to accept an intended change of the API
+    assert_eq!(response.status().as_u16(), 200);
+    let served: serde_json::Value = response.json().await.unwrap();
+    if std::env::var("UPDATE_SNAPSHOTS").is_ok() {
+        let pretty = serde_json::to_string_pretty(&served).unwrap() + "\n";
+        std::fs::write(SNAPSHOT_PATH, pretty).unwrap();
+    }
+    let snapshot: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(SNAPSHOT_PATH).unwrap()).unwrap();
+    assert_eq!(
+        served, snapshot,
+        "OpenAPI document drifted from {}, run the tests with UPDATE_SNAPSHOTS=1 if the change \
+        is intended",
+        SNAPSHOT_PATH
+    );
+}
+
+#[tokio::test]
+async fn documented_routes_are_served_under_api_v1() {
+    // arange
+    let app = spwan_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act
+    let health = reqwest::get(format!("{}/api/v1/health_check", app.address))
+        .await
+        .unwrap();
+    let subscription = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .json(&serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"}))
+        .send()
+        .await
+        .unwrap();
+    let subscribers = app
+        .admin_client()
+        .get(format!("{}/api/v1/admin/subscribers", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(health.status().as_u16(), 200);
+    assert_eq!(subscription.status().as_u16(), 200);
+    assert_eq!(subscribers.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn admin_routes_are_only_served_under_api_v1() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let subscribers = app
+        .admin_client()
+        .get(format!("{}/admin/subscribers", app.address))
+        .send()
+        .await
+        .unwrap();
+    let audit_log = app
+        .admin_client()
+        .get(format!("{}/admin/audit_log", app.address))
+        .send()
+        .await
+        .unwrap();
+    let health = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(subscribers.status().as_u16(), 404);
+    assert_eq!(audit_log.status().as_u16(), 404);
+    assert_eq!(health.status().as_u16(), 200);
+}
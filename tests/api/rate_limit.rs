@@ -0,0 +1,127 @@
+use crate::helpers::{spwan_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::config::RateLimitStoreKind;
+
+async fn post_subscription_from(app: &TestApp, body: &str, ip: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Forwarded-For", ip)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_with_retry_after_when_ip_exceeds_limit() {
+    for store in [RateLimitStoreKind::Memory, RateLimitStoreKind::Postgres] {
+        // arange
+        let app = spwan_app_with(|config| {
+            config.app.rate_limit.store = store;
+            config.app.rate_limit.max_requests_per_ip = 2;
+        })
+        .await;
+        mount_email_server(&app).await;
+
+        // act
+        let mut responses = Vec::new();
+        for i in 0..3 {
+            let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+            responses.push(app.post_subscription(body).await);
+        }
+
+        // assert
+        assert_eq!(responses[0].status().as_u16(), 200, "store: {:?}", store);
+        assert_eq!(responses[1].status().as_u16(), 200, "store: {:?}", store);
+        assert_eq!(responses[2].status().as_u16(), 429, "store: {:?}", store);
+        let retry_after: i64 = responses[2].headers()["Retry-After"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_returns_429_when_email_exceeds_limit() {
+    // arange
+    let app = spwan_app_with(|config| {
+        config.app.rate_limit.max_requests_per_email = 1;
+    })
+    .await;
+    mount_email_server(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // act
+    let first = app.post_subscription(body.into()).await;
+    let second = app.post_subscription(body.into()).await;
+
+    // assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    assert!(second.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn forwarded_for_header_is_only_honored_for_trusted_proxies() {
+    // arange, the test client connects from 127.0.0.1
+    let untrusted = spwan_app_with(|config| {
+        config.app.rate_limit.max_requests_per_ip = 1;
+    })
+    .await;
+    let trusted = spwan_app_with(|config| {
+        config.app.rate_limit.max_requests_per_ip = 1;
+        config.app.rate_limit.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    mount_email_server(&untrusted).await;
+    mount_email_server(&trusted).await;
+
+    for (app, expected, description) in [
+        (&untrusted, 429, "untrusted proxy"),
+        (&trusted, 200, "trusted proxy"),
+    ] {
+        // act, send from two different forwarded addresses
+        post_subscription_from(app, "name=a&email=a%40gmail.com", "1.1.1.1").await;
+        let response = post_subscription_from(app, "name=b&email=b%40gmail.com", "2.2.2.2").await;
+
+        // assert
+        assert_eq!(
+            response.status().as_u16(),
+            expected,
+            "unexpected status for: {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn consent_event_records_forwarded_ip_from_trusted_proxy() {
+    // arange
+    let app = spwan_app_with(|config| {
+        config.app.rate_limit.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    // act
+    post_subscription_from(&app, "name=a&email=a%40gmail.com", "1.1.1.1").await;
+
+    // assert
+    let event = sqlx::query!("SELECT source_ip FROM subscription_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.source_ip.as_deref(), Some("1.1.1.1"));
+}
@@ -0,0 +1,48 @@
+use crate::helpers::{spwan_app, spwan_app_with};
+
+#[tokio::test]
+async fn responses_carry_security_headers() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let responses = [
+        reqwest::get(format!("{}/health_check", app.address))
+            .await
+            .unwrap(),
+        reqwest::get(format!("{}/login", app.address))
+            .await
+            .unwrap(),
+    ];
+
+    // assert
+    for response in responses {
+        let headers = response.headers();
+        assert_eq!(
+            headers["Content-Security-Policy"],
+            "default-src 'none'; form-action 'self'; base-uri 'none'; frame-ancestors 'none'"
+        );
+        assert_eq!(headers["X-Content-Type-Options"], "nosniff");
+        assert_eq!(headers["Referrer-Policy"], "no-referrer");
+        // only configured for production, where we're served over HTTPS
+        assert!(headers.get("Strict-Transport-Security").is_none());
+    }
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    // arange
+    let app =
+        spwan_app_with(|config| config.app.security_headers.hsts_max_age_secs = Some(600)).await;
+
+    // act
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(
+        response.headers()["Strict-Transport-Security"],
+        "max-age=600; includeSubDomains"
+    );
+}
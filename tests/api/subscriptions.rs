@@ -1,4 +1,4 @@
-use crate::helpers::{find_links, spwan_app};
+use crate::helpers::{spwan_app, spwan_app_with};
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -116,10 +116,383 @@ async fn subscribe_sends_confirmation_email_with_a_link() {
 
     // assert, check response
     let request = &app.email_server.received_requests().await.unwrap()[0];
-    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
-    let html_links = find_links(body["HtmlBody"].as_str().unwrap());
-    let text_links = find_links(body["TextBody"].as_str().unwrap());
-    assert_eq!(html_links.len(), 1);
-    assert_eq!(text_links.len(), 1);
-    assert_eq!(html_links[0].as_str(), text_links[0].as_str());
+    let confirmation_links = app.get_confirmation_links(request);
+    assert_eq!(confirmation_links.html, confirmation_links.text);
+}
+
+#[tokio::test]
+async fn subscribe_records_consent_event_for_valid_data() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, send request
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&source=footer";
+    app.post_subscription(body.into()).await;
+
+    // assert, check audit trail
+    let event = sqlx::query!(
+        "SELECT email, event_type, source_ip, form_source, consent_text_version \
+        FROM subscription_events",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("failed to fetch data from database");
+
+    assert_eq!(event.email, "ursula_le_guin@gmail.com");
+    assert_eq!(event.event_type, "subscribed");
+    assert_eq!(event.source_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(event.form_source.as_deref(), Some("footer"));
+    assert_eq!(event.consent_text_version, "2025-03-01");
+}
+
+#[tokio::test]
+async fn subscription_events_cannot_be_deleted() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscription(body.into()).await;
+
+    // act
+    let result = sqlx::query!("DELETE FROM subscription_events")
+        .execute(&app.db_pool)
+        .await;
+
+    // assert
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn subscribe_persists_the_new_subscriber_as_pending() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, send request
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscription(body.into()).await;
+
+    // assert, check database
+    let subscription = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscription.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_new_confirmation_email() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // act, send request twice
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let first = app.post_subscription(body.into()).await;
+    let second = app.post_subscription(body.into()).await;
+
+    // assert, check response and that links differ
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&requests[0]).html;
+    let second_link = app.get_confirmation_links(&requests[1]).html;
+    assert_ne!(first_link, second_link);
+    let events = sqlx::query!("SELECT event_type FROM subscription_events ORDER BY occurred_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .map(|e| e.event_type.as_str())
+            .collect::<Vec<_>>(),
+        vec!["subscribed", "confirmation_resent"]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_returns_200_without_sending_an_email() {
+    // arange, subscribe and confirm
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let link = app.get_confirmation_links(&requests[0]).html;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // act, subscribe again with another spelling of the same email
+    let response = app
+        .post_subscription("name=le%20guin&email=Ursula%40Gmail.com".into())
+        .await;
+
+    // assert, the answer doesn't tell that the address is subscribed already
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriptions = sqlx::query!("SELECT email, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].email, "ursula@gmail.com");
+    assert_eq!(subscriptions[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_normalizes_email_and_detects_case_variants_as_duplicates() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, subscribe with two spellings of the same email
+    let first = app
+        .post_subscription("name=le%20guin&email=%20Ursula%40Gmail.COM%20".into())
+        .await;
+    let second = app
+        .post_subscription("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+
+    // assert, check that only one normalized subscriber is stored
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    let subscriptions = sqlx::query!("SELECT email, canonical_email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].email, "Ursula@gmail.com");
+    assert_eq!(subscriptions[0].canonical_email, "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_folds_provider_aliases_if_configured() {
+    // arange, start app and create a client
+    let app = spwan_app_with(|config| config.subscriptions.fold_provider_aliases = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, subscribe with an alias of an existing email
+    app.post_subscription("name=le%20guin&email=ursula.le.guin%40gmail.com".into())
+        .await;
+    app.post_subscription("name=le%20guin&email=ursulaleguin%2Bnews%40googlemail.com".into())
+        .await;
+
+    // assert, check that the alias is the same subscriber
+    let subscriptions = sqlx::query!("SELECT email, canonical_email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].email, "ursula.le.guin@gmail.com");
+    assert_eq!(subscriptions[0].canonical_email, "ursulaleguin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_stores_normalized_name() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, send name with extra whitespace and a decomposed accent
+    let body = "name=%20Ursula%20%20Le%09Gui%CC%81n%20&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscription(body.into()).await;
+
+    // assert, check that the name is stored normalized
+    assert_eq!(response.status().as_u16(), 200);
+    let subscription = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscription.name, "Ursula Le Guín");
+}
+
+#[tokio::test]
+async fn subscribe_applies_name_policy_of_the_form_source() {
+    // arange, start app allowing email-only signups from the footer form
+    let app = spwan_app_with(|config| {
+        let mut footer_policy = config.subscriptions.name_policy.clone();
+        footer_policy.allow_empty = true;
+        config
+            .subscriptions
+            .name_policies_by_source
+            .insert("footer".into(), footer_policy);
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // act, send requests without name from different forms
+    let footer = app
+        .post_subscription("email=ursula%40gmail.com&source=footer".into())
+        .await;
+    let landing_page = app
+        .post_subscription("email=le_guin%40gmail.com&source=landing-page".into())
+        .await;
+
+    // assert, check that only the footer form accepts an empty name
+    assert_eq!(footer.status().as_u16(), 200);
+    assert_eq!(landing_page.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_and_responds_with_json() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // act, send request with JSON body
+    let body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    let response = app.post_subscription_json(&body).await;
+
+    // assert, check response and stored subscriber
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+    let subscription = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscription.email, "ursula_le_guin@gmail.com");
+    assert_eq!(subscription.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_returns_json_errors_for_json_requests() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"name": "le guin"}), "missing the email"),
+        (
+            serde_json::json!({"name": "le guin", "email": "not-an-email"}),
+            "invalid email",
+        ),
+    ];
+    for (body, error_message) in test_cases {
+        // act, send request
+        let response = app.post_subscription_json(&body).await;
+
+        // assert, check response
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "did not fail when: {}",
+            error_message
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            body["error"].is_string(),
+            "no error when: {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_redirects_browser_form_posts_to_thank_you_page() {
+    // arange, start app with a thank-you page and a client that doesn't follow redirects
+    let app = spwan_app_with(|config| {
+        config.subscriptions.thank_you_url = Some("https://example.com/thank-you".parse().unwrap())
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // act, post the form like a browser does
+    let response = client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    // assert, check redirect
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/thank-you"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_415_for_unsupported_content_type() {
+    // arange, start app and create a client
+    let app = spwan_app().await;
+
+    // act, send request with plain text body
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "text/plain")
+        .body("ursula_le_guin@gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    // assert, check response
+    assert_eq!(response.status().as_u16(), 415);
 }
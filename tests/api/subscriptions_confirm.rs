@@ -0,0 +1,181 @@
+use crate::helpers::spwan_app;
+use chrono::{TimeDelta, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::cleanup_worker::purge_pending_subscriptions;
+
+#[tokio::test]
+async fn confirm_without_token_returns_400() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn confirm_with_unknown_token_returns_401() {
+    // arange
+    let app = spwan_app().await;
+
+    // act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_the_subscriber() {
+    // arange
+    let app = spwan_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscription = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscription.status, "confirmed");
+    let event_types =
+        sqlx::query!("SELECT event_type FROM subscription_events ORDER BY occurred_at")
+            .fetch_all(&app.db_pool)
+            .await
+            .expect("failed to fetch data from database")
+            .into_iter()
+            .map(|r| r.event_type)
+            .collect::<Vec<_>>();
+    assert_eq!(event_types, vec!["subscribed", "confirmed"]);
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    // arange
+    let app = spwan_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+    // act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_confirmation_link_returns_410() {
+    // arange
+    let app = spwan_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // move the creation of the token further into the past than the configured expiry
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = $1",
+        Utc::now() - TimeDelta::days(30)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response.text().await.unwrap().contains("subscribe again"));
+    let subscription = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch data from database");
+    assert_eq!(subscription.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn purge_deletes_only_stale_pending_subscriptions() {
+    // arange
+    let app = spwan_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=stale&email=stale%40gmail.com".into())
+        .await;
+    app.post_subscription("name=fresh&email=fresh%40gmail.com".into())
+        .await;
+
+    let past = Utc::now() - TimeDelta::days(30);
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = $1 WHERE email = 'stale@gmail.com'",
+        past
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE subscription_tokens t SET created_at = $1 \
+        FROM subscriptions s WHERE s.id = t.subscriber_id AND s.email = 'stale@gmail.com'",
+        past
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // act
+    let purged = purge_pending_subscriptions(&app.db_pool, TimeDelta::days(7))
+        .await
+        .unwrap();
+
+    // assert
+    assert_eq!(purged, 1);
+    let emails = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.email)
+        .collect::<Vec<_>>();
+    assert_eq!(emails, vec!["fresh@gmail.com"]);
+}